@@ -8,6 +8,17 @@ use std::{env, fs, path::Path};
 
 
 fn main() {
+  // without the `aim` feature there is nothing to link, which is what allows
+  // building on hosts that don't have the AiM blobs
+  if env::var_os("CARGO_FEATURE_AIM").is_none() {
+    return;
+  }
+  // with the `runtime` feature, the library is loaded via `dlopen` (or
+  // `LoadLibrary` on Windows) when it is first used, so no linking either
+  if env::var_os("CARGO_FEATURE_RUNTIME").is_some() {
+    return;
+  }
+
   // because of the dynamic linking foo required to make these shared libraries
   // from AiM work on both platforms, we need to do some extra acrobatics in
   // this build script here.
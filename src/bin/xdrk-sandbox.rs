@@ -0,0 +1,43 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Helper process hosting the AiM library for `SandboxSource`.
+//!
+//! Reads requests from stdin and writes responses to stdout until stdin is
+//! closed. Not meant to be run by hand.
+
+use std::io::{self, BufReader, BufWriter, Write};
+use xdrk::{AimSource, DataSource, SandboxSource};
+
+
+fn main() -> io::Result<()> {
+  let input = BufReader::new(io::stdin());
+  let output = BufWriter::new(protocol_output()?);
+  SandboxSource::serve(input, output, |path| {
+    Ok(Box::new(AimSource::open(path)?) as Box<dyn DataSource>)
+  })
+}
+
+/// The AiM library may print to stdout, which would corrupt the responses.
+/// So they get a copy of stdout of their own, and stdout itself is pointed to
+/// stderr.
+#[cfg(target_family = "unix")]
+fn protocol_output() -> io::Result<Box<dyn Write>> {
+  use std::{fs::File, os::unix::io::FromRawFd};
+
+  let fd = unsafe { libc::dup(libc::STDOUT_FILENO) };
+  if fd < 0
+     || unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0
+  {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(Box::new(unsafe { File::from_raw_fd(fd) }))
+}
+
+#[cfg(not(target_family = "unix"))]
+fn protocol_output() -> io::Result<Box<dyn Write>> {
+  Ok(Box::new(io::stdout()))
+}
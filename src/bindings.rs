@@ -4,7 +4,141 @@
 //   Florian Eich <florian@bmc-labs.com>
 //   Jonas Reitemeyer <alumni@bmc-labs.com>
 
+#[cfg(feature = "runtime")]
+use crate::{Error, Result};
+#[cfg(feature = "runtime")]
+use once_cell::sync::OnceCell;
 use std::os::raw::{c_char, c_int};
+#[cfg(feature = "runtime")]
+use std::{env,
+          path::{Path, PathBuf}};
+
+
+/// Environment variable holding the path to the AiM library, used with the
+/// `runtime` feature if no path is passed to `load` explicitly.
+#[cfg(feature = "runtime")]
+const LIBRARY_PATH_VAR: &str = "XDRK_LIBRARY_PATH";
+
+/// File name of the AiM library, looked up using the platform's search rules
+/// if neither an explicit path nor `LIBRARY_PATH_VAR` is given.
+#[cfg(feature = "runtime")]
+const LIBRARY_NAME: &str = if cfg!(target_family = "windows") {
+  "libxdrk-x86_64.dll"
+} else {
+  "libxdrk-x86_64.so"
+};
+
+#[cfg(feature = "runtime")]
+static LIBRARY: OnceCell<Library> = OnceCell::new();
+
+
+/// Loads the AiM library and resolves all of its symbols (`runtime` feature
+/// only). Has to be called successfully before any of the bindings is used.
+///
+/// The library is loaded from `path` if given, else from the path in the
+/// environment variable `XDRK_LIBRARY_PATH`, else by its file name. It is
+/// loaded only once per process, since it holds global state (i.e. the open
+/// files); subsequent calls are cheap, but passing a path different from the
+/// one the library was loaded from is an error.
+#[cfg(feature = "runtime")]
+pub fn load(path: Option<&Path>) -> Result<()> {
+  let library = LIBRARY.get_or_try_init(|| {
+                         let path = match path {
+                           Some(path) => path.to_owned(),
+                           None => env::var_os(LIBRARY_PATH_VAR)
+                                   .map(PathBuf::from)
+                                   .unwrap_or_else(|| LIBRARY_NAME.into()),
+                         };
+                         Library::open(&path)
+                       })?;
+
+  if let Some(path) = path {
+    // the library may have been loaded via another path to the same file;
+    // a bare file name looked up by the platform can't be resolved here and
+    // is compared as is
+    let canonical = |path: &Path| {
+      path.canonicalize().unwrap_or_else(|_| path.to_owned())
+    };
+    if canonical(&library.path) != canonical(path) {
+      let reason =
+        format!("already loaded from {}", library.path.display());
+      return Err(Error::LibraryLoad { path: path.to_owned(), reason });
+    }
+  }
+  Ok(())
+}
+
+/// The loaded library. Panics if `load` wasn't called, which is a bug.
+#[cfg(feature = "runtime")]
+fn library() -> &'static Library {
+  LIBRARY.get()
+         .expect("AiM library used before it was loaded")
+}
+
+
+/// Declares the functions exported by the AiM library.
+///
+/// Without the `runtime` feature, this expands to a plain `extern "C"` block,
+/// i.e. the library is linked at build time. With it, this expands to a
+/// table of function pointers filled by `load` plus one wrapper function per
+/// symbol with the very same signature, so callers don't need to care which
+/// mode is used.
+macro_rules! bindings {
+  ($($(#[$attr:meta])*
+     pub fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+    /// Names of all functions exported by the AiM library.
+    #[allow(dead_code)]
+    pub const SYMBOLS: &[&str] = &[$(stringify!($name)),*];
+
+    #[cfg(not(feature = "runtime"))]
+    #[allow(dead_code)]
+    extern "C" {
+      $($(#[$attr])* pub fn $name($($arg: $ty),*) -> $ret;)*
+    }
+
+    /// Symbols resolved from the AiM library at runtime.
+    #[cfg(feature = "runtime")]
+    #[allow(non_snake_case)]
+    struct Library {
+      path:     PathBuf,
+      $($name: unsafe extern "C" fn($($ty),*) -> $ret,)*
+      _library: libloading::Library,
+    }
+
+    #[cfg(feature = "runtime")]
+    impl Library {
+      #[allow(non_snake_case)]
+      fn open(path: &Path) -> Result<Self> {
+        let library = unsafe { libloading::Library::new(path) }.map_err(|e| {
+          Error::LibraryLoad { path:   path.to_owned(),
+                               reason: e.to_string() }
+        })?;
+
+        $(let $name = *unsafe {
+          library.get::<unsafe extern "C" fn($($ty),*) -> $ret>(
+            concat!(stringify!($name), "\0").as_bytes()
+          )
+        }.map_err(|_| {
+          Error::LibraryLoad {
+            path:   path.to_owned(),
+            reason: format!("missing symbol `{}`", stringify!($name)),
+          }
+        })?;)*
+
+        Ok(Self { path: path.to_owned(),
+                  $($name,)*
+                  _library: library })
+      }
+    }
+
+    $(#[cfg(feature = "runtime")]
+      #[allow(dead_code, non_snake_case, clippy::missing_safety_doc)]
+      $(#[$attr])*
+      pub unsafe fn $name($($arg: $ty),*) -> $ret {
+        (library().$name)($($arg),*)
+      })*
+  };
+}
 
 
 /// Binding to C tm struct storing datetime info (defined in `time.h`)
@@ -24,8 +158,7 @@ pub struct tm {
 }
 
 
-#[allow(dead_code)]
-extern "C" {
+bindings! {
   // FILE OPENING / CLOSING FUNCTIONS -------------------------------------- //
   //
   /// Open a drk/xrk file
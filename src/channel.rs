@@ -4,7 +4,18 @@
 //   Florian Eich <florian@bmc-labs.com>
 //   Jonas Reitemeyer <alumni@bmc-labs.com>
 
-use eyre::{ensure, Result};
+mod arithmetic;
+mod calculus;
+mod distance;
+mod event;
+mod filter;
+mod resample;
+
+pub use event::{Event, EventDetector, Trigger};
+pub use filter::{Band, Filter};
+pub use resample::Interpolation;
+
+use super::{Error, Result, Statistic};
 use getset::{CopyGetters, Getters, MutGetters};
 use std::{iter, vec};
 
@@ -49,6 +60,13 @@ impl Channel {
                .clone() as f64
   }
 
+  /// Sample rate of the data in Hz, from the median interval between the
+  /// timestamps, so gaps and irregular sampling don't distort it; 0 for
+  /// channels with less than 2 distinct timestamps.
+  pub(crate) fn sample_rate(&self) -> f64 {
+    resample::rate(self.data.timestamps())
+  }
+
   pub fn len(&self) -> usize {
     self.data().len()
   }
@@ -75,18 +93,25 @@ impl Channel {
   /// - this channel or `other` contain less than 3 data points
   /// - this channel and `other` have non-intersecting timestamp ranges
   pub fn synchronize_with(&self, other: &Self) -> Result<Self> {
-    const DNCSDP: &str = "does not contain sufficient data points";
-    ensure!(self.len() >= 3, format!("channel {}", DNCSDP));
-    ensure!(other.len() >= 3, format!("other {}", DNCSDP));
+    for channel in [self, other].iter() {
+      if channel.len() < 3 {
+        return Err(Error::InsufficientSamples { channel:  channel.name.clone(),
+                                                count:    channel.len(),
+                                                required: 3, });
+      }
+    }
 
     // these unwraps are safe: we just tested that the channels are not empty,
     // which would be the only way for the `.first()` and `.last()` calls to
     // return a `None` value and thus cause a panic on unwrap
-    ensure!(self.data.timestamps().first().unwrap()
-            <= other.data.timestamps().last().unwrap()
-            && self.data.timestamps().last().unwrap()
-               >= other.data.timestamps().first().unwrap(),
-            "channels have non-intersecting timestamp ranges");
+    if !(self.data.timestamps().first().unwrap()
+         <= other.data.timestamps().last().unwrap()
+         && self.data.timestamps().last().unwrap()
+            >= other.data.timestamps().first().unwrap())
+    {
+      return Err(Error::DisjointChannels { channel: self.name.clone(),
+                                           other:   other.name.clone(), });
+    }
 
     let (mut idx, timestamps, samples) =
       (0usize, self.data.timestamps(), self.data.samples());
@@ -137,6 +162,368 @@ impl Channel {
                                        new_samples,
                                        other.len())))
   }
+
+  /// Resample the channel at a fixed rate of `rate_hz` over its whole time
+  /// range, see `resample_over`.
+  pub fn resample(&self, rate_hz: f64, method: Interpolation) -> Result<Self> {
+    // empty channels are rejected by `resample_over`
+    let timestamps = self.data.timestamps();
+    let (start, end) = (timestamps.first().copied().unwrap_or_default(),
+                        timestamps.last().copied().unwrap_or_default());
+    self.resample_over(start, end, rate_hz, method)
+  }
+
+  /// Resample the channel at a fixed rate of `rate_hz` between timestamps
+  /// `start` and `end`, computing samples in between the original ones using
+  /// `method`.
+  ///
+  /// ## Function
+  ///
+  /// - The new timestamps are the multiples of `1 / rate_hz` between `start`
+  ///   and `end`, so channels resampled at the same rate share them and can be
+  ///   compared sample by sample, whatever their original rates.
+  /// - Before the first and after the last original sample, the first or last
+  ///   sample is held.
+  /// - When downsampling, i.e. if `rate_hz` is below the rate of the channel
+  ///   (taken from the median interval between its timestamps), the samples
+  ///   are averaged over `1 / rate_hz` around each original sample first, so
+  ///   content above the new Nyquist frequency doesn't alias.
+  ///
+  /// ## Fails if
+  ///
+  /// - this channel contains less than 2 data points
+  /// - `rate_hz` is not positive or `start` is after `end`
+  pub fn resample_over(&self,
+                       start: f64,
+                       end: f64,
+                       rate_hz: f64,
+                       method: Interpolation)
+                       -> Result<Self> {
+    if self.len() < 2 {
+      return Err(Error::InsufficientSamples { channel:  self.name.clone(),
+                                              count:    self.len(),
+                                              required: 2, });
+    }
+    if !(rate_hz.is_finite() && rate_hz > 0.0) {
+      return Err(Error::InvalidArgument { name:   "rate_hz",
+                                          reason: format!("{} is not a \
+                                                           positive rate",
+                                                          rate_hz), });
+    }
+    if !(start.is_finite() && end.is_finite() && start <= end) {
+      return Err(Error::InvalidArgument { name:   "start",
+                                          reason: format!("range {}..{} is \
+                                                           empty",
+                                                          start, end), });
+    }
+
+    // grid points are computed from their index instead of accumulated, so
+    // rounding errors don't add up; the tolerance keeps `start` and `end`
+    // themselves if they are on the grid
+    const TOLERANCE: f64 = 1e-9;
+    let first = (start * rate_hz - TOLERANCE).ceil() as i64;
+    let last = (end * rate_hz + TOLERANCE).floor() as i64;
+    let grid = (first..=last).map(|k| k as f64 / rate_hz)
+                             .collect::<Vec<_>>();
+    Ok(self.sample_at(grid, rate_hz, method))
+  }
+
+  /// Computes the distance travelled from this channel, which must be a
+  /// speed in m/s, km/h or mph, as a channel named "Distance" in m starting
+  /// at 0 at the first sample. Negative speeds count as standing still.
+  ///
+  /// ## Fails if
+  ///
+  /// - the unit of this channel is not a unit of speed
+  /// - this channel contains less than 2 data points
+  pub fn distance(&self) -> Result<Self> {
+    let factor = distance::meters_per_second(&self.unit).ok_or_else(|| {
+                   Error::InvalidArgument { name:   "speed",
+                                            reason: format!("channel '{}' \
+                                                             has unit '{}', \
+                                                             which is not a \
+                                                             speed",
+                                                            self.name,
+                                                            self.unit), }
+                 })?;
+    if self.len() < 2 {
+      return Err(Error::InsufficientSamples { channel:  self.name.clone(),
+                                              count:    self.len(),
+                                              required: 2, });
+    }
+
+    let timestamps = self.data.timestamps();
+    let distances =
+      distance::integrate(timestamps, self.data.samples(), factor);
+    Ok(Self::new("Distance".to_string(),
+                 "m".to_string(),
+                 ChannelData::new(timestamps.clone(), distances)))
+  }
+
+  /// Computes the rate of change of this channel over time, e.g. jerk from
+  /// `aLon` or the steering rate from `bSteering`, in the unit of this
+  /// channel per second (`bar` becomes `bar/s`) and named e.g.
+  /// `d(pBrakeF)/dt`. Uses central differences weighted by the intervals to
+  /// the neighbouring samples, so irregular timestamps don't bias it. Fails
+  /// if this channel contains less than 2 data points.
+  pub fn derivative(&self) -> Result<Self> {
+    if self.len() < 2 {
+      return Err(Error::InsufficientSamples { channel:  self.name.clone(),
+                                              count:    self.len(),
+                                              required: 2, });
+    }
+    let timestamps = self.data.timestamps();
+    Ok(Self::new(calculus::derivative_name(&self.name),
+                 calculus::per_second(&self.unit),
+                 ChannelData::new(timestamps.clone(),
+                                  calculus::derivative(timestamps,
+                                                       self.data.samples()))))
+  }
+
+  /// Computes the cumulative integral of this channel over time using the
+  /// trapezoidal rule, starting at 0 at the first sample, in the unit of
+  /// this channel times seconds (`m/s` becomes `m`) and named e.g.
+  /// `∫pBrakeF dt`. Fails if this channel contains less than 2 data points.
+  pub fn integral(&self) -> Result<Self> {
+    self.integral_reset_at(&[])
+  }
+
+  /// Like `integral`, but starting over at 0 at the first sample at or
+  /// after each of the timestamps in `resets`, e.g. the starts of the laps
+  /// of a run for a channel of the whole run.
+  pub fn integral_reset_at(&self, resets: &[f64]) -> Result<Self> {
+    if self.len() < 2 {
+      return Err(Error::InsufficientSamples { channel:  self.name.clone(),
+                                              count:    self.len(),
+                                              required: 2, });
+    }
+    let timestamps = self.data.timestamps();
+    let samples = calculus::integral(timestamps, self.data.samples(), resets);
+    Ok(Self::new(calculus::integral_name(&self.name),
+                 calculus::times_second(&self.unit),
+                 ChannelData::new(timestamps.clone(), samples)))
+  }
+
+  /// This channel named `name`, e.g. for a channel computed by `map`.
+  pub fn with_name(mut self, name: String) -> Self {
+    self.name = name;
+    self
+  }
+
+  /// This channel in `unit`, e.g. for a channel computed by `map`.
+  pub fn with_unit(mut self, unit: String) -> Self {
+    self.unit = unit;
+    self
+  }
+
+  /// Applies `f` to each sample, keeping the name and unit of this channel,
+  /// e.g. `speed.map(|v| v * 3.6).with_unit("km/h".to_string())`.
+  pub fn map<F>(&self, f: F) -> Self
+    where F: Fn(f64) -> f64
+  {
+    let samples = self.data.samples().iter().map(|&s| f(s)).collect();
+    Self::new(self.name.clone(),
+              self.unit.clone(),
+              ChannelData::new(self.data.timestamps.clone(), samples))
+  }
+
+  /// Combines this channel with `other` sample by sample using `f`, keeping
+  /// the name and unit of this channel. The channel of lower sample rate
+  /// (taken from the median interval between its timestamps) is first
+  /// linearly resampled onto the timestamps of the other one, or of this
+  /// channel if their rates are the same; the arithmetic operators on
+  /// `&Channel` work the same way. Fails if the channel to be resampled
+  /// contains less than 2 data points and doesn't share the timestamps of
+  /// the other.
+  pub fn zip_with<F>(&self, other: &Self, f: F) -> Result<Self>
+    where F: Fn(f64, f64) -> f64
+  {
+    Ok(Self::new(self.name.clone(),
+                 self.unit.clone(),
+                 arithmetic::zip(self, other, f)?))
+  }
+
+  /// Limits the samples to the range from `min` to `max`. Panics if `min` is
+  /// greater than `max` or either is NaN, like `f64::clamp`.
+  pub fn clamp(&self, min: f64, max: f64) -> Self {
+    self.map(|sample| sample.clamp(min, max))
+        .with_name(format!("clamp({}, {}, {})", self.name, min, max))
+  }
+
+  /// Takes the absolute value of the samples, e.g. of a steering angle.
+  pub fn abs(&self) -> Self {
+    self.map(f64::abs).with_name(format!("abs({})", self.name))
+  }
+
+  /// This speed channel in km/h, `None` if its unit is not a unit of speed.
+  pub(crate) fn in_kmh(&self) -> Option<Self> {
+    let factor = distance::meters_per_second(&self.unit)? * 3.6;
+    let samples = self.data.samples.iter().map(|v| v * factor).collect();
+    Some(Self::new(self.name.clone(),
+                   "km/h".to_string(),
+                   ChannelData::new(self.data.timestamps.clone(), samples)))
+  }
+
+  /// Re-index the channel onto a distance axis, given the `distance`
+  /// travelled over time (see `distance`), taking a sample every `step` m.
+  /// The timestamps of the returned channel are the distances in m, starting
+  /// at 0 at the first sample of `distance`.
+  ///
+  /// If `length` is given, distances are scaled so the last sample of
+  /// `distance` is at `length`, e.g. the length of the track. Laps which
+  /// differ slightly in the distance measured then have the same number of
+  /// samples at the same points of the track and can be overlaid.
+  ///
+  /// ## Fails if
+  ///
+  /// - this channel or `distance` contain less than 2 data points
+  /// - `step` or `length` are not positive, or `length` is given but the
+  ///   distance travelled is 0
+  pub fn over_distance(&self,
+                       distance: &Self,
+                       step: f64,
+                       length: Option<f64>)
+                       -> Result<Self> {
+    let (distances, times) = distance.distance_grid(step, length)?;
+    let resampled = self.resample_at(&times, Interpolation::Linear)?;
+    Ok(Self::new(self.name.clone(),
+                 self.unit.clone(),
+                 ChannelData::new(distances, resampled.data.samples)))
+  }
+
+  /// Filters this channel with `filter`, e.g. to smooth `aLat` or remove
+  /// spikes from `bSteering`. Windows and cutoffs are converted to samples
+  /// using `frequency`; the timestamps stay the same.
+  ///
+  /// ## Fails if
+  ///
+  /// - this channel contains less than 3 data points
+  /// - a window spans less than 3 samples, or less than `order + 1` for
+  ///   `Filter::SavitzkyGolay`
+  /// - a cutoff isn't between 0 and half of `frequency`, the cutoffs of a
+  ///   band pass are out of order, or a Butterworth order isn't 1 or 2
+  pub fn filter(&self, filter: &Filter) -> Result<Self> {
+    if self.len() < 3 {
+      return Err(Error::InsufficientSamples { channel:  self.name.clone(),
+                                              count:    self.len(),
+                                              required: 3, });
+    }
+    let samples = filter.apply(self.data.samples(), self.frequency())?;
+    Ok(Self::new(self.name.clone(),
+                 self.unit.clone(),
+                 ChannelData::new(self.data.timestamps.clone(), samples)))
+  }
+
+  /// Computes `statistic` of this channel, `None` if it contains no samples,
+  /// see `Statistic`.
+  pub fn statistic(&self, statistic: Statistic) -> Option<f64> {
+    statistic.of(self)
+  }
+
+  /// Finds the events `detector` looks for, e.g. braking points or gear
+  /// shifts, in order. The events carry no lap index, see `Run::events` and
+  /// `Lap::events` for that.
+  pub fn events(&self, detector: &EventDetector) -> Vec<Event> {
+    let (timestamps, samples) = (self.data.timestamps(), self.data.samples());
+    detector.detect(timestamps, samples)
+            .into_iter()
+            .map(|idx| Event::new(idx, timestamps[idx], samples[idx]))
+            .collect()
+  }
+
+  /// For this distance channel, which must not be empty, the time the
+  /// distance `distance` is first reached at, holding the first or last
+  /// timestamp outside of the channel's range.
+  pub(crate) fn time_at_distance(&self, distance: f64) -> f64 {
+    distance::time_at(self.data.timestamps(), self.data.samples(), distance)
+  }
+
+  /// For this distance channel, the distances every `step` m (see
+  /// `over_distance`) and the times they are first reached at.
+  pub(crate) fn distance_grid(&self,
+                              step: f64,
+                              length: Option<f64>)
+                              -> Result<(Vec<f64>, Vec<f64>)> {
+    if self.len() < 2 {
+      return Err(Error::InsufficientSamples { channel:  self.name.clone(),
+                                              count:    self.len(),
+                                              required: 2, });
+    }
+    if !(step.is_finite() && step > 0.0) {
+      return Err(Error::InvalidArgument { name:   "step",
+                                          reason: format!("{} is not a \
+                                                           positive distance",
+                                                          step), });
+    }
+
+    let samples = self.data.samples();
+    let (first, last) = (samples[0], samples[samples.len() - 1]);
+    let scale = match length {
+      Some(length) if !(length.is_finite() && length > 0.0) => {
+        return Err(Error::InvalidArgument { name:   "length",
+                                            reason: format!("{} is not a \
+                                                             positive \
+                                                             distance",
+                                                            length), });
+      }
+      Some(_) if last <= first => {
+        return Err(Error::InvalidArgument { name:   "length",
+                                            reason: format!("channel '{}' \
+                                                             doesn't cover \
+                                                             any distance",
+                                                            self.name), });
+      }
+      Some(length) => length / (last - first),
+      None => 1.0,
+    };
+
+    let distances = samples.iter()
+                           .map(|distance| (distance - first) * scale)
+                           .collect::<Vec<_>>();
+    Ok(distance::grid(self.data.timestamps(), &distances, step))
+  }
+
+  /// Resample the channel at the given timestamps, which must be sorted,
+  /// like `resample_over` does at a fixed rate. Used to put channels onto the
+  /// timestamps of a reference channel.
+  pub(crate) fn resample_at(&self,
+                            timestamps: &[f64],
+                            method: Interpolation)
+                            -> Result<Self> {
+    if self.len() < 2 {
+      return Err(Error::InsufficientSamples { channel:  self.name.clone(),
+                                              count:    self.len(),
+                                              required: 2, });
+    }
+    Ok(self.sample_at(timestamps.to_vec(), resample::rate(timestamps), method))
+  }
+
+  /// Computes the samples at `grid`, anti-aliasing first if `rate_hz`, the
+  /// rate of `grid`, is below the one of this channel.
+  fn sample_at(&self,
+               grid: Vec<f64>,
+               rate_hz: f64,
+               method: Interpolation)
+               -> Self {
+    const TOLERANCE: f64 = 1e-9;
+    let timestamps = self.data.timestamps();
+    let filtered;
+    let samples = if rate_hz > 0.0
+                     && rate_hz
+                        < resample::rate(timestamps) * (1.0 - TOLERANCE)
+    {
+      filtered =
+        resample::anti_alias(timestamps, self.data.samples(), 1.0 / rate_hz);
+      &filtered
+    } else {
+      self.data.samples()
+    };
+    let samples = resample::interpolate(timestamps, samples, &grid, method);
+
+    Self::new(self.name.clone(),
+              self.unit.clone(),
+              ChannelData::new(grid, samples))
+  }
 }
 
 
@@ -149,6 +536,16 @@ pub struct ChannelData {
 }
 
 impl ChannelData {
+  /// Creates a new `ChannelData` object from a set of timestamps and the
+  /// corresponding samples. Panics if the lengths do not match.
+  pub fn new(timestamps: Vec<f64>, samples: Vec<f64>) -> Self {
+    assert_eq!(timestamps.len(),
+               samples.len(),
+               "number of timestamps not equivalent to number of samples");
+    Self { timestamps,
+           samples }
+  }
+
   /// Helper function which allocates memory buffers in the required format.
   pub fn allocate(count: usize) -> (Vec<f64>, Vec<f64>) {
     (vec![0.0; count], vec![0.0; count])
@@ -181,6 +578,19 @@ impl ChannelData {
   pub fn is_empty(&self) -> bool {
     self.len() == 0usize
   }
+
+  /// The samples with `start <= timestamp < end`, which is how data is cut
+  /// into laps.
+  pub(crate) fn between(&self, start: f64, end: f64) -> Self {
+    let (timestamps, samples) =
+      self.timestamps
+          .iter()
+          .zip(&self.samples)
+          .filter(|(&ts, _)| start <= ts && ts < end)
+          .unzip();
+    Self { timestamps,
+           samples }
+  }
 }
 
 impl IntoIterator for ChannelData {
@@ -272,6 +682,124 @@ mod tests {
     // std::fs::write("p_brake_sync_data.csv", p_brake_sync_data).unwrap();
   }
 
+  #[test]
+  fn resample_test() {
+    let channel = |rate: f64, count: usize| {
+      let timestamps = (0..count).map(|i| 0.013 + i as f64 / rate)
+                                 .collect::<Vec<_>>();
+      let samples = timestamps.iter().map(|t| 2.0 * t).collect();
+      Channel::new("ramp".to_string(),
+                   "m".to_string(),
+                   ChannelData::new(timestamps, samples))
+    };
+    let (fast, slow) = (channel(1000.0, 1001), channel(10.0, 11));
+
+    // both end up on the same grid, starting at the first multiple of 1 / 50
+    let fast_resampled = fast.resample(50.0, Interpolation::Linear).unwrap();
+    let slow_resampled = slow.resample(50.0, Interpolation::Linear).unwrap();
+    assert_eq!(fast_resampled.data().timestamps(),
+               slow_resampled.data().timestamps());
+    assert_eq!(0.02, fast_resampled.data().timestamps()[0]);
+    assert_eq!(50, fast_resampled.len());
+    // away from the ends, where the anti-aliasing filter lacks samples on one
+    // side, a ramp stays a ramp (up to a sample more or less in the window)
+    for (a, b) in
+      fast_resampled.data().samples()[1..49].iter()
+                                            .zip(&slow_resampled.data()
+                                                                .samples()
+                                                   [1..49])
+    {
+      assert_eq!(true, (a - b).abs() < 1e-3);
+    }
+
+    // the range may exceed the data, which is held at the ends
+    let held =
+      slow.resample_over(-0.1, 0.1, 10.0, Interpolation::ZeroOrderHold)
+          .unwrap();
+    assert_eq!(&vec![-0.1, 0.0, 0.1], held.data().timestamps());
+    assert_eq!(&vec![0.026, 0.026, 0.026], held.data().samples());
+
+    assert_eq!(true,
+               matches!(slow.resample(0.0, Interpolation::Nearest),
+                        Err(Error::InvalidArgument { name: "rate_hz",
+                                                     .. })));
+    assert_eq!(true,
+               matches!(slow.resample_over(1.0,
+                                           0.0,
+                                           10.0,
+                                           Interpolation::Nearest),
+                        Err(Error::InvalidArgument { .. })));
+    assert_eq!(true,
+               matches!(Channel::default().resample(10.0,
+                                                    Interpolation::Linear),
+                        Err(Error::InsufficientSamples { required: 2, .. })));
+  }
+
+  #[test]
+  fn map_test() {
+    let data = ChannelData::new(vec![0.0, 0.1, 0.2, 0.3],
+                                vec![-90.0, -10.0, 5.0, 80.0]);
+    let steering = Channel::new("aSteer".to_string(), "deg".to_string(), data);
+    let abs = steering.abs();
+    assert_eq!("abs(aSteer)", abs.name());
+    assert_eq!("deg", abs.unit());
+    assert_eq!(&vec![90.0, 10.0, 5.0, 80.0], abs.data().samples());
+    let clamped = steering.clamp(-45.0, 45.0);
+    assert_eq!("clamp(aSteer, -45, 45)", clamped.name());
+    assert_eq!(&vec![-45.0, -10.0, 5.0, 45.0], clamped.data().samples());
+
+    let radians = steering.map(f64::to_radians)
+                          .with_unit("rad".to_string());
+    assert_eq!("aSteer", radians.name());
+    assert_eq!("rad", radians.unit());
+    assert_eq!(steering.data().timestamps(), radians.data().timestamps());
+
+    let ratio = Channel::new("rSteer".to_string(),
+                             String::new(),
+                             ChannelData::new(vec![0.0, 0.2], vec![1.0, 3.0]));
+    let wheel = steering.zip_with(&ratio, |steer, ratio| steer / ratio)
+                        .unwrap()
+                        .with_name("aWheel".to_string());
+    assert_eq!("aWheel", wheel.name());
+    assert_eq!("deg", wheel.unit());
+    assert_eq!(&vec![-90.0, -5.0, 5.0 / 3.0, 80.0 / 3.0],
+               wheel.data().samples());
+  }
+
+  #[test]
+  fn distance_test() {
+    let timestamps = (0..=10).map(f64::from).collect::<Vec<_>>();
+    let speed = Channel::new("GPS Speed".to_string(),
+                             "km/h".to_string(),
+                             ChannelData::new(timestamps.clone(),
+                                              vec![36.0; 11]));
+    let distance = speed.distance().unwrap();
+    assert_eq!("m", distance.unit());
+    assert_eq!(true, (distance.data().samples()[10] - 100.0).abs() < 1e-9);
+
+    // a ramp over time is a ramp over distance at constant speed
+    let ramp = Channel::new("ramp".to_string(),
+                            "s".to_string(),
+                            ChannelData::new(timestamps.clone(),
+                                             timestamps.clone()));
+    let over_distance = ramp.over_distance(&distance, 10.0, None).unwrap();
+    assert_eq!(11, over_distance.len());
+    assert_eq!(50.0, over_distance.data().timestamps()[5]);
+    assert_eq!(true, (over_distance.data().samples()[5] - 5.0).abs() < 1e-9);
+
+    // scaled to a lap of 50 m, every 10 m are 2 s
+    let scaled = ramp.over_distance(&distance, 10.0, Some(50.0)).unwrap();
+    assert_eq!(6, scaled.len());
+    assert_eq!(true, (scaled.data().samples()[1] - 2.0).abs() < 1e-9);
+
+    assert_eq!(true,
+               matches!(ramp.distance(),
+                        Err(Error::InvalidArgument { name: "speed", .. })));
+    assert_eq!(true,
+               matches!(ramp.over_distance(&distance, 0.0, None),
+                        Err(Error::InvalidArgument { name: "step", .. })));
+  }
+
   #[test]
   #[should_panic]
   fn channel_data_from_tsc_panic_first_test() {
@@ -0,0 +1,229 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Arithmetic operators on channels, with other channels and with scalars.
+//!
+//! Operators on two channels synchronize them first, see `Channel::zip_with`,
+//! and thus return a `Result`; operators with a scalar can't fail and return
+//! the `Channel` itself. Results are named after the operation, e.g.
+//! `pBrakeF + pBrakeR` or `(pBrakeF + pBrakeR) * 0.5`, with a unit derived
+//! from the units of the operands, see `sum_unit`, `product_unit` and
+//! `quotient_unit`.
+
+use super::{calculus, Channel, ChannelData, Interpolation};
+use super::super::Result;
+use std::ops::{Add, Div, Mul, Sub};
+
+
+/// Name of a channel as an operand of a binary operation, in parentheses if
+/// it is the result of one itself.
+fn operand(name: &str) -> String {
+  if [" + ", " - ", " * ", " / "].iter().any(|op| name.contains(op)) {
+    format!("({})", name)
+  } else {
+    name.to_string()
+  }
+}
+
+/// Unit of the sum or difference of channels in `lhs` and `rhs`: their unit
+/// if they agree or one of them has none, no unit otherwise.
+fn sum_unit(lhs: &str, rhs: &str) -> String {
+  match (lhs, rhs) {
+    (lhs, rhs) if lhs == rhs => lhs.to_string(),
+    (unit, "") | ("", unit) => unit.to_string(),
+    _ => String::new(),
+  }
+}
+
+/// Unit of the product of channels in `lhs` and `rhs`, e.g. `N·m` for `N`
+/// and `m`, `m²` for `m` and `m` and `m` for `m/s` and `s`.
+fn product_unit(lhs: &str, rhs: &str) -> String {
+  match (lhs, rhs) {
+    (unit, "") | ("", unit) => unit.to_string(),
+    (unit, "s") | ("s", unit) => calculus::times_second(unit),
+    (lhs, rhs) if lhs == rhs => format!("{}²", lhs),
+    (lhs, rhs) => format!("{}·{}", lhs, rhs),
+  }
+}
+
+/// Unit of the quotient of channels in `lhs` and `rhs`, e.g. `m/s²` for
+/// `m/s` and `s`, `bar/m` for `bar` and `m` and none for `bar` and `bar`.
+fn quotient_unit(lhs: &str, rhs: &str) -> String {
+  match (lhs, rhs) {
+    (lhs, rhs) if lhs == rhs => String::new(),
+    (unit, "") => unit.to_string(),
+    (unit, "s") => calculus::per_second(unit),
+    ("", unit) => format!("1/{}", unit),
+    (lhs, rhs) => format!("{}/{}", lhs, rhs),
+  }
+}
+
+/// Combines the samples of `lhs` and `rhs` using `f`, with the one of lower
+/// sample rate resampled onto the timestamps of the other, see
+/// `Channel::zip_with`.
+pub(super) fn zip<F>(lhs: &Channel, rhs: &Channel, f: F) -> Result<ChannelData>
+  where F: Fn(f64, f64) -> f64
+{
+  let (base, other) = if rhs.sample_rate() > lhs.sample_rate() {
+    (rhs, lhs)
+  } else {
+    (lhs, rhs)
+  };
+  let timestamps = base.data.timestamps();
+  let resampled;
+  let other_samples = if other.data.timestamps() == timestamps {
+    other.data.samples()
+  } else {
+    resampled = other.resample_at(timestamps, Interpolation::Linear)?;
+    resampled.data.samples()
+  };
+  let (lhs_samples, rhs_samples) = if std::ptr::eq(base, lhs) {
+    (base.data.samples(), other_samples)
+  } else {
+    (other_samples, base.data.samples())
+  };
+  let samples = lhs_samples.iter()
+                           .zip(rhs_samples.iter())
+                           .map(|(&l, &r)| f(l, r))
+                           .collect();
+  Ok(ChannelData::new(timestamps.clone(), samples))
+}
+
+macro_rules! operator {
+  ($trait:ident, $method:ident, $symbol:expr, $unit:ident) => {
+    /// Synchronizes the channels to the one of higher sample rate, see
+    /// `Channel::zip_with`, and combines them sample by sample.
+    impl $trait<&Channel> for &Channel {
+      type Output = Result<Channel>;
+
+      fn $method(self, rhs: &Channel) -> Self::Output {
+        let data = zip(self, rhs, |l, r| $trait::$method(l, r))?;
+        Ok(Channel::new(format!("{} {} {}",
+                                operand(self.name()),
+                                $symbol,
+                                operand(rhs.name())),
+                        $unit(self.unit(), rhs.unit()),
+                        data))
+      }
+    }
+
+    /// Combines each sample of the channel with the scalar, keeping the unit.
+    impl $trait<f64> for &Channel {
+      type Output = Channel;
+
+      fn $method(self, rhs: f64) -> Self::Output {
+        self.map(|sample| $trait::$method(sample, rhs))
+            .with_name(format!("{} {} {}", operand(self.name()), $symbol, rhs))
+      }
+    }
+
+    /// Combines the scalar with each sample of the channel.
+    impl $trait<&Channel> for f64 {
+      type Output = Channel;
+
+      fn $method(self, rhs: &Channel) -> Self::Output {
+        rhs.map(|sample| $trait::$method(self, sample))
+           .with_name(format!("{} {} {}", self, $symbol, operand(rhs.name())))
+           .with_unit($unit("", rhs.unit()))
+      }
+    }
+  };
+}
+
+operator!(Add, add, "+", sum_unit);
+operator!(Sub, sub, "-", sum_unit);
+operator!(Mul, mul, "*", product_unit);
+operator!(Div, div, "/", quotient_unit);
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  fn channel(name: &str, unit: &str, rate_hz: f64, samples: &[f64])
+             -> Channel {
+    Channel::new(name.to_string(),
+                 unit.to_string(),
+                 ChannelData::new((0..samples.len()).map(|i| {
+                                                      i as f64 / rate_hz
+                                                    })
+                                                    .collect(),
+                                  samples.to_vec()))
+  }
+
+  #[test]
+  fn operator_test() {
+    let front = channel("pBrakeF", "bar", 10.0, &[4.0, 6.0, 8.0, 10.0]);
+    let rear = channel("pBrakeR", "bar", 10.0, &[1.0, 2.0, 3.0, 4.0]);
+
+    let sum = (&front + &rear).unwrap();
+    assert_eq!("pBrakeF + pBrakeR", sum.name());
+    assert_eq!("bar", sum.unit());
+    assert_eq!(&vec![5.0, 8.0, 11.0, 14.0], sum.data().samples());
+    let difference = (&front - &rear).unwrap();
+    assert_eq!(&vec![3.0, 4.0, 5.0, 6.0], difference.data().samples());
+    assert_eq!("bar²", (&front * &rear).unwrap().unit());
+
+    let balance = &(&front / &sum).unwrap() * 100.0;
+    assert_eq!("(pBrakeF / (pBrakeF + pBrakeR)) * 100", balance.name());
+    assert_eq!("", balance.unit());
+    for (sample, expected) in balance.data()
+                                     .samples()
+                                     .iter()
+                                     .zip([80.0, 75.0, 800.0 / 11.0].iter())
+    {
+      assert_eq!(true, (sample - expected).abs() < 1e-12);
+    }
+    let share = 1.0 - &(&front / &sum).unwrap();
+    assert_eq!("1 - (pBrakeF / (pBrakeF + pBrakeR))", share.name());
+    assert_eq!(true, (share.data().samples()[1] - 0.25).abs() < 1e-12);
+    assert_eq!("1/bar", (1.0 / &rear).unit());
+    assert_eq!(&vec![0.5, 1.0, 1.5, 2.0], (&rear / 2.0).data().samples());
+  }
+
+  #[test]
+  fn synchronization_test() {
+    // the slower channel is resampled onto the faster one, whichever side
+    // of the operator it is on
+    let speed = channel("vCar", "m/s", 10.0, &[0.0, 1.0, 2.0, 3.0, 4.0]);
+    let time = channel("tLap", "s", 5.0, &[1.0, 1.0, 1.0]);
+    let distance = (&time * &speed).unwrap();
+    assert_eq!(speed.data().timestamps(), distance.data().timestamps());
+    assert_eq!(&vec![0.0, 1.0, 2.0, 3.0, 4.0], distance.data().samples());
+    assert_eq!("m", distance.unit());
+    assert_eq!("m/s²", (&speed / &time).unwrap().unit());
+    assert_eq!(5, (&speed - &time).unwrap().len());
+    assert_eq!("", (&speed - &time).unwrap().unit());
+
+    // the rate is taken from the median interval, so a burst of samples at
+    // the start doesn't make a slow channel the faster one
+    let burst = Channel::new("tLap".to_string(),
+                             "s".to_string(),
+                             ChannelData::new(vec![0.0, 0.01, 0.02, 0.2, 0.4],
+                                              vec![1.0; 5]));
+    assert_eq!(speed.data().timestamps(),
+               (&burst * &speed).unwrap().data().timestamps());
+
+    let empty = channel("empty", "", 10.0, &[]);
+    assert_eq!(true, (&empty + &speed).is_err());
+  }
+
+  #[test]
+  fn unit_test() {
+    assert_eq!("bar", sum_unit("bar", "bar"));
+    assert_eq!("bar", sum_unit("", "bar"));
+    assert_eq!("", sum_unit("bar", "km/h"));
+    assert_eq!("N·m", product_unit("N", "m"));
+    assert_eq!("m", product_unit("m", ""));
+    assert_eq!("bar·s", product_unit("s", "bar"));
+    assert_eq!("m/s", quotient_unit("m", "s"));
+    assert_eq!("bar/m", quotient_unit("bar", "m"));
+    assert_eq!("", quotient_unit("bar", "bar"));
+    assert_eq!("1/s", quotient_unit("", "s"));
+  }
+}
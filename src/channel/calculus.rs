@@ -0,0 +1,174 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Derivatives and integrals over time, see `Channel::derivative` and
+//! `Channel::integral`.
+
+
+/// Rate of change of the samples at each timestamp, from the neighbouring
+/// samples. Interior samples use central differences weighted by the
+/// intervals to either side, so irregular timestamps don't bias the result;
+/// the first and last sample, and samples next to a repeated timestamp, use
+/// the one-sided difference to the neighbour which is apart in time.
+pub(super) fn derivative(timestamps: &[f64], samples: &[f64]) -> Vec<f64> {
+  let n = timestamps.len();
+  let slope = |i: usize, j: usize| {
+    (samples[j] - samples[i]) / (timestamps[j] - timestamps[i])
+  };
+  (0..n).map(|i| {
+          let h0 = if i > 0 { timestamps[i] - timestamps[i - 1] } else { 0.0 };
+          let h1 = if i + 1 < n {
+            timestamps[i + 1] - timestamps[i]
+          } else {
+            0.0
+          };
+          match (h0 > 0.0, h1 > 0.0) {
+            (true, true) => {
+              (h0 * h0 * samples[i + 1] - h1 * h1 * samples[i - 1]
+               + (h1 * h1 - h0 * h0) * samples[i])
+              / (h0 * h1 * (h0 + h1))
+            }
+            (true, false) => slope(i - 1, i),
+            (false, true) => slope(i, i + 1),
+            (false, false) => 0.0,
+          }
+        })
+        .collect()
+}
+
+/// Cumulative integral of the samples over time using the trapezoidal rule,
+/// starting at 0 at the first sample and again at the first sample at or
+/// after each of `resets`.
+pub(super) fn integral(timestamps: &[f64],
+                       samples: &[f64],
+                       resets: &[f64])
+                       -> Vec<f64> {
+  let mut sum = 0.0;
+  (0..timestamps.len()).map(|i| {
+                         let t = timestamps[i];
+                         let reset = i == 0
+                                     || resets.iter().any(|&r| {
+                                                       timestamps[i - 1] < r
+                                                       && r <= t
+                                                     });
+                         if reset {
+                           sum = 0.0;
+                         } else {
+                           sum += (samples[i - 1] + samples[i]) / 2.0
+                                  * (t - timestamps[i - 1]);
+                         }
+                         sum
+                       })
+                       .collect()
+}
+
+/// Unit of the derivative over time of a channel in `unit`, e.g. `bar/s` for
+/// `bar` and `m/s²` for `m/s`.
+pub(super) fn per_second(unit: &str) -> String {
+  let unit = unit.trim();
+  if unit.is_empty() {
+    "1/s".to_string()
+  } else if let Some(base) = unit.strip_suffix("/s²") {
+    format!("{}/s³", base)
+  } else if let Some(base) = unit.strip_suffix("/s") {
+    format!("{}/s²", base)
+  } else {
+    format!("{}/s", unit)
+  }
+}
+
+/// Unit of the integral over time of a channel in `unit`, e.g. `m` for `m/s`
+/// and `bar·s` for `bar`.
+pub(super) fn times_second(unit: &str) -> String {
+  let unit = unit.trim();
+  if unit.is_empty() {
+    "s".to_string()
+  } else if let Some(base) = unit.strip_suffix("/s³") {
+    format!("{}/s²", base)
+  } else if let Some(base) = unit.strip_suffix("/s²") {
+    format!("{}/s", base)
+  } else if let Some(base) = unit.strip_suffix("/s") {
+    base.to_string()
+  } else {
+    format!("{}·s", unit)
+  }
+}
+
+/// Name of the derivative over time of a channel named `name`, e.g.
+/// `d(pBrakeF)/dt`.
+pub(super) fn derivative_name(name: &str) -> String {
+  format!("d({})/dt", name)
+}
+
+/// Name of the integral over time of a channel named `name`, e.g.
+/// `∫pBrakeF dt`, with names containing spaces in parentheses.
+pub(super) fn integral_name(name: &str) -> String {
+  if name.contains(' ') {
+    format!("∫({}) dt", name)
+  } else {
+    format!("∫{} dt", name)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn derivative_test() {
+    // a parabola is differentiated exactly at interior samples, however
+    // irregular the timestamps
+    let timestamps = vec![0.0, 0.1, 0.3, 0.4, 1.0];
+    let samples = timestamps.iter().map(|t| t * t).collect::<Vec<_>>();
+    let rates = derivative(&timestamps, &samples);
+    for i in 1..4 {
+      assert_eq!(true, (rates[i] - 2.0 * timestamps[i]).abs() < 1e-9);
+    }
+    assert_eq!(true, (rates[0] - 0.1).abs() < 1e-9);
+    assert_eq!(true, (rates[4] - 1.4).abs() < 1e-9);
+
+    // a repeated timestamp falls back to the neighbour on the other side
+    let rates = derivative(&[0.0, 1.0, 1.0, 2.0], &[0.0, 1.0, 1.0, 3.0]);
+    assert_eq!(vec![1.0, 1.0, 2.0, 2.0], rates);
+    assert_eq!(vec![0.0], derivative(&[0.0], &[5.0]));
+  }
+
+  #[test]
+  fn integral_test() {
+    let timestamps = vec![0.0, 1.0, 3.0, 4.0, 5.0];
+    let samples = vec![2.0, 2.0, 4.0, 4.0, 0.0];
+    assert_eq!(vec![0.0, 2.0, 8.0, 12.0, 14.0],
+               integral(&timestamps, &samples, &[]));
+    assert_eq!(vec![0.0, 2.0, 0.0, 4.0, 6.0],
+               integral(&timestamps, &samples, &[0.0, 2.5]));
+    assert_eq!(vec![0.0, 2.0, 8.0, 0.0, 2.0],
+               integral(&timestamps, &samples, &[4.0]));
+  }
+
+  #[test]
+  fn unit_test() {
+    assert_eq!("bar/s", per_second("bar"));
+    assert_eq!("m/s²", per_second("m/s"));
+    assert_eq!("m/s³", per_second("m/s²"));
+    assert_eq!("1/s", per_second(""));
+    assert_eq!("m", times_second("m/s"));
+    assert_eq!("m/s", times_second("m/s²"));
+    assert_eq!("bar·s", times_second("bar"));
+    assert_eq!("km/h·s", times_second("km/h"));
+    assert_eq!("s", times_second(" "));
+  }
+
+  #[test]
+  fn name_test() {
+    assert_eq!("d(pBrakeF)/dt", derivative_name("pBrakeF"));
+    assert_eq!("d(GPS Speed)/dt", derivative_name("GPS Speed"));
+    assert_eq!("∫pBrakeF dt", integral_name("pBrakeF"));
+    assert_eq!("∫(GPS Speed) dt", integral_name("GPS Speed"));
+  }
+}
@@ -0,0 +1,103 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Distance travelled, computed from speed channels, see
+//! `Channel::distance` and `Channel::over_distance`.
+
+
+/// Factor converting speeds in `unit` to m/s, if it is a unit of speed.
+pub(super) fn meters_per_second(unit: &str) -> Option<f64> {
+  match unit.trim().to_lowercase().as_str() {
+    "m/s" => Some(1.0),
+    "km/h" | "kph" => Some(1.0 / 3.6),
+    "mph" => Some(0.447_04),
+    _ => None,
+  }
+}
+
+/// Cumulative distance in m at `timestamps`, starting at 0, from speeds
+/// multiplied by `factor` to get m/s, using the trapezoidal rule. Negative
+/// speeds count as standing still, so the distance never decreases.
+pub(super) fn integrate(timestamps: &[f64],
+                        speeds: &[f64],
+                        factor: f64)
+                        -> Vec<f64> {
+  let mut distance = 0.0;
+  let mut distances = Vec::with_capacity(timestamps.len());
+  for (i, &t) in timestamps.iter().enumerate() {
+    if i > 0 {
+      let (v0, v1) = (speeds[i - 1].max(0.0), speeds[i].max(0.0));
+      distance += (v0 + v1) / 2.0 * factor * (t - timestamps[i - 1]);
+    }
+    distances.push(distance);
+  }
+  distances
+}
+
+/// For each multiple of `step` up to the last of `distances`, which must not
+/// decrease, returns that distance and the time it is first reached,
+/// interpolating linearly in between samples.
+pub(super) fn grid(timestamps: &[f64],
+                   distances: &[f64],
+                   step: f64)
+                   -> (Vec<f64>, Vec<f64>) {
+  let last = distances[distances.len() - 1];
+  let count = (last / step + 1e-9).floor() as usize;
+  (0..=count).map(|k| {
+               let distance = k as f64 * step;
+               (distance, time_at(timestamps, distances, distance))
+             })
+             .unzip()
+}
+
+/// The time `distance` is first reached, given `distances`, which must not
+/// decrease, at `timestamps`. Interpolates linearly in between samples and
+/// holds the first or last timestamp outside of them.
+pub(super) fn time_at(timestamps: &[f64],
+                      distances: &[f64],
+                      distance: f64)
+                      -> f64 {
+  let n = distances.len();
+  // index of the first sample at or beyond `distance`
+  let idx = distances.partition_point(|&d| d < distance);
+  if idx == 0 {
+    timestamps[0]
+  } else if idx == n {
+    timestamps[n - 1]
+  } else {
+    let (d0, d1) = (distances[idx - 1], distances[idx]);
+    let (t0, t1) = (timestamps[idx - 1], timestamps[idx]);
+    t0 + (distance - d0) / (d1 - d0) * (t1 - t0)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn integrate_test() {
+    assert_eq!(Some(1.0), meters_per_second(" M/S"));
+    assert_eq!(None, meters_per_second("rpm"));
+
+    let timestamps = vec![0.0, 1.0, 2.0, 3.0];
+    assert_eq!(vec![0.0, 5.0, 15.0, 20.0],
+               integrate(&timestamps, &[0.0, 10.0, 10.0, -5.0], 1.0));
+  }
+
+  #[test]
+  fn grid_test() {
+    // standing still between 1 s and 2 s, the first time counts
+    let (distances, times) =
+      grid(&[0.0, 1.0, 2.0, 3.0], &[0.0, 10.0, 10.0, 25.0], 5.0);
+    assert_eq!(vec![0.0, 5.0, 10.0, 15.0, 20.0, 25.0], distances);
+    assert_eq!(vec![0.0, 0.5, 1.0, 2.0 + 1.0 / 3.0, 2.0 + 2.0 / 3.0, 3.0],
+               times);
+  }
+}
@@ -0,0 +1,281 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Detection of events in channel data, e.g. braking points or gear shifts,
+//! see `Channel::events`.
+
+use getset::{CopyGetters, Setters};
+
+
+/// What `EventDetector` looks for. Levels are in the unit of the channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+  /// The channel rises to or above the level, e.g. `pBrakeF` for braking
+  /// points.
+  Above(f64),
+  /// The channel falls to or below the level.
+  Below(f64),
+  /// The channel increases from one sample to the next, e.g. `posGear` for
+  /// upshifts.
+  RisingEdge,
+  /// The channel decreases from one sample to the next.
+  FallingEdge,
+  /// A local maximum at or above the level, e.g. `aVer` for kerb strikes.
+  Peak(f64),
+  /// A local minimum at or below the level.
+  Valley(f64),
+}
+
+
+/// Detects events of one kind in a channel, see `Channel::events`.
+///
+/// `hysteresis` and `min_dwell` keep noise from triggering events:
+///
+/// - for `Above` and `Below`, the channel must get back across the level by
+///   more than `hysteresis` before triggering again, and must not do so
+///   within `min_dwell` s after triggering
+/// - for `RisingEdge` and `FallingEdge`, a change must be larger than
+///   `hysteresis`, and the new value must hold within `hysteresis` for
+///   `min_dwell` s
+/// - for `Peak` and `Valley`, the channel must get back by more than
+///   `hysteresis` in between two of them, which must be at least
+///   `min_dwell` s apart; otherwise only the more extreme one counts
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters, Setters)]
+#[getset(get_copy = "pub", set = "pub")]
+pub struct EventDetector {
+  trigger:    Trigger,
+  hysteresis: f64,
+  min_dwell:  f64,
+}
+
+impl EventDetector {
+  /// Detector for `trigger`, without hysteresis and minimum dwell time.
+  pub fn new(trigger: Trigger) -> Self {
+    Self { trigger,
+           hysteresis: 0.0,
+           min_dwell: 0.0 }
+  }
+
+  /// Indices of the samples at which events occur.
+  pub(super) fn detect(&self,
+                       timestamps: &[f64],
+                       samples: &[f64])
+                       -> Vec<usize> {
+    // falling edges, values below a level and valleys are detected as rising
+    // edges, values above the negated level and peaks of the negated samples
+    let (sign, level) = match self.trigger {
+      Trigger::Above(level) | Trigger::Peak(level) => (1.0, level),
+      Trigger::Below(level) | Trigger::Valley(level) => (-1.0, -level),
+      Trigger::RisingEdge => (1.0, 0.0),
+      Trigger::FallingEdge => (-1.0, 0.0),
+    };
+    let signed = samples.iter().map(|v| sign * v).collect::<Vec<_>>();
+    match self.trigger {
+      Trigger::Above(_) | Trigger::Below(_) => {
+        self.crossings(timestamps, &signed, level)
+      }
+      Trigger::RisingEdge | Trigger::FallingEdge => {
+        self.edges(timestamps, &signed)
+      }
+      Trigger::Peak(_) | Trigger::Valley(_) => {
+        self.peaks(timestamps, &signed, level)
+      }
+    }
+  }
+
+  /// Samples reaching `level` after having been below `level - hysteresis`,
+  /// unless the channel drops below that again within `min_dwell`.
+  fn crossings(&self,
+               timestamps: &[f64],
+               samples: &[f64],
+               level: f64)
+               -> Vec<usize> {
+    let rearm = level - self.hysteresis;
+    let mut events = Vec::new();
+    // a channel starting at or above the level hasn't crossed it
+    let mut armed = false;
+    for (i, &v) in samples.iter().enumerate() {
+      if v < rearm {
+        armed = true;
+      } else if armed && v >= level {
+        armed = false;
+        if self.holds(timestamps, samples, i, |w| w >= rearm) {
+          events.push(i);
+        }
+      }
+    }
+    events
+  }
+
+  /// Samples exceeding the previous one by more than `hysteresis`, with the
+  /// following ones staying within `hysteresis` of them for `min_dwell`.
+  fn edges(&self, timestamps: &[f64], samples: &[f64]) -> Vec<usize> {
+    (1..samples.len()).filter(|&i| {
+                        let v = samples[i];
+                        v - samples[i - 1] > self.hysteresis
+                        && self.holds(timestamps, samples, i, |w| {
+                                 (w - v).abs() <= self.hysteresis
+                               })
+                      })
+                      .collect()
+  }
+
+  /// Local maxima at or above `level`, of which those too close to each
+  /// other are merged into the highest one.
+  fn peaks(&self,
+           timestamps: &[f64],
+           samples: &[f64],
+           level: f64)
+           -> Vec<usize> {
+    let n = samples.len();
+    let separated = |a: usize, b: usize| {
+      let dip = samples[a..=b].iter().copied().fold(f64::MAX, f64::min);
+      timestamps[b] - timestamps[a] >= self.min_dwell
+      && dip < samples[a].min(samples[b]) - self.hysteresis
+    };
+
+    let mut peaks: Vec<usize> = Vec::new();
+    for i in 0..n {
+      let v = samples[i];
+      let is_peak = v >= level
+                    && (i == 0 || v > samples[i - 1])
+                    && (i + 1 == n || v >= samples[i + 1]);
+      if !is_peak {
+        continue;
+      }
+      match peaks.last_mut() {
+        Some(last) if !separated(*last, i) => {
+          if v > samples[*last] {
+            *last = i;
+          }
+        }
+        _ => peaks.push(i),
+      }
+    }
+    peaks
+  }
+
+  /// Whether `holds` is true for the sample at `i` and all samples following
+  /// it within `min_dwell`.
+  fn holds<F>(&self,
+              timestamps: &[f64],
+              samples: &[f64],
+              i: usize,
+              holds: F)
+              -> bool
+    where F: Fn(f64) -> bool {
+    timestamps[i..].iter()
+                   .zip(&samples[i..])
+                   .take_while(|(&t, _)| t - timestamps[i] < self.min_dwell)
+                   .all(|(_, &v)| holds(v))
+  }
+}
+
+
+/// An event found by an `EventDetector`, i.e. the sample it occurred at.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct Event {
+  /// Index of the sample in the channel the event was found in.
+  idx:       usize,
+  /// Time of the event, in the time base of the channel.
+  timestamp: f64,
+  /// Value of the sample.
+  value:     f64,
+  /// Index of the lap the event occurred in, if known, see `Run::events`.
+  lap:       Option<usize>,
+}
+
+impl Event {
+  pub fn new(idx: usize, timestamp: f64, value: f64) -> Self {
+    Self { idx,
+           timestamp,
+           value,
+           lap: None }
+  }
+
+  /// This event, occurring in the lap with index `lap`.
+  pub fn with_lap(mut self, lap: usize) -> Self {
+    self.lap = Some(lap);
+    self
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Channel, ChannelData};
+  use pretty_assertions::assert_eq;
+
+
+  fn channel(samples: &[f64]) -> Channel {
+    let timestamps = (0..samples.len()).map(|i| i as f64 / 10.0).collect();
+    Channel::new("pBrakeF".to_string(),
+                 "bar".to_string(),
+                 ChannelData::new(timestamps, samples.to_vec()))
+  }
+
+  fn indices(channel: &Channel, detector: &EventDetector) -> Vec<usize> {
+    channel.events(detector).iter().map(Event::idx).collect()
+  }
+
+  #[test]
+  fn crossing_test() {
+    let pressure =
+      channel(&[0.0, 5.0, 12.0, 9.0, 11.0, 30.0, 2.0, 15.0, 16.0, 0.0, 20.0]);
+    let mut detector = EventDetector::new(Trigger::Above(10.0));
+    assert_eq!(vec![2, 4, 7, 10], indices(&pressure, &detector));
+
+    // the dip at 3 isn't deep enough to rearm
+    detector.set_hysteresis(5.0);
+    assert_eq!(vec![2, 7, 10], indices(&pressure, &detector));
+
+    // the pressure at 7 drops off too soon
+    detector.set_min_dwell(0.25);
+    assert_eq!(vec![2, 10], indices(&pressure, &detector));
+
+    let events = pressure.events(&EventDetector::new(Trigger::Below(1.0)));
+    assert_eq!(vec![Event::new(9, 0.9, 0.0)], events);
+    assert_eq!(None, events[0].lap());
+  }
+
+  #[test]
+  fn edge_test() {
+    let gear = channel(&[1.0, 2.0, 2.0, 3.0, 2.0, 3.0, 3.0, 3.0, 4.0, 4.0]);
+    let mut detector = EventDetector::new(Trigger::RisingEdge);
+    assert_eq!(vec![1, 3, 5, 8], indices(&gear, &detector));
+
+    // the shift to 3 at 3 doesn't hold
+    detector.set_min_dwell(0.15);
+    assert_eq!(vec![1, 5, 8], indices(&gear, &detector));
+
+    detector.set_trigger(Trigger::FallingEdge);
+    assert_eq!(Vec::<usize>::new(), indices(&gear, &detector));
+    detector.set_min_dwell(0.0);
+    assert_eq!(vec![4], indices(&gear, &detector));
+  }
+
+  #[test]
+  fn peak_test() {
+    let accel =
+      channel(&[0.0, 3.0, 2.0, 2.8, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 4.0, 1.0]);
+    let mut detector = EventDetector::new(Trigger::Peak(2.5));
+    assert_eq!(vec![1, 3, 10], indices(&accel, &detector));
+
+    // the dip at 2 is too shallow to separate 1 and 3
+    detector.set_hysteresis(1.5);
+    assert_eq!(vec![1, 10], indices(&accel, &detector));
+
+    // 1 and 10 are far enough apart, 1 and 3 are not
+    detector.set_hysteresis(0.0);
+    detector.set_min_dwell(0.5);
+    assert_eq!(vec![1, 10], indices(&accel, &detector));
+
+    detector = EventDetector::new(Trigger::Valley(0.5));
+    assert_eq!(vec![0, 4, 7], indices(&accel, &detector));
+  }
+}
@@ -0,0 +1,480 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Digital filters for noisy channels, see `Channel::filter`.
+
+use super::super::{Error, Result};
+use std::f64::consts::{PI, SQRT_2};
+
+
+/// A filter `Channel::filter` applies to the samples of a channel. Windows
+/// are in s and cutoff frequencies in Hz, which are turned into numbers of
+/// samples using the frequency of the channel, i.e. samples are taken to be
+/// evenly spaced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+  /// Mean of the samples in a centered window, which shrinks towards the
+  /// ends of the channel.
+  MovingAverage { window: f64 },
+  /// Median of the samples in a centered window, which removes spikes
+  /// shorter than half the window while keeping steps sharp. NaN samples,
+  /// e.g. from sensor dropouts, are left out, so they are filled in from
+  /// their neighbours; a window of NaN samples only yields NaN.
+  Median { window: f64 },
+  /// Least squares fit of a polynomial of degree `order` to the samples in
+  /// a centered window, evaluated at each sample. Smooths while keeping
+  /// peaks better than a moving average of the same window.
+  SavitzkyGolay { window: f64, order: usize },
+  /// Butterworth filter of `order` 1 or 2, applied forward and backward so
+  /// it doesn't shift the channel in time. This doubles the order of the
+  /// response, and the cutoff is where it is damped by 6 dB.
+  Butterworth { order: usize, band: Band },
+}
+
+/// The frequencies a `Filter::Butterworth` lets pass, in Hz.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Band {
+  /// Frequencies below the cutoff.
+  LowPass(f64),
+  /// Frequencies above the cutoff.
+  HighPass(f64),
+  /// Frequencies between the two cutoffs, by a high-pass at the first and a
+  /// low-pass at the second one.
+  BandPass(f64, f64),
+}
+
+
+impl Filter {
+  /// Filters `samples` recorded at `rate_hz`. Fails if the parameters of the
+  /// filter are out of range for the rate, see `Channel::filter`.
+  pub(super) fn apply(&self,
+                       samples: &[f64],
+                       rate_hz: f64)
+                       -> Result<Vec<f64>> {
+    match *self {
+      Self::MovingAverage { window } => {
+        let half = half_width(window, rate_hz)?;
+        Ok(windowed(samples, half, |window| {
+             window.iter().sum::<f64>() / window.len() as f64
+           }))
+      }
+      Self::Median { window } => {
+        let half = half_width(window, rate_hz)?;
+        Ok(windowed(samples, half, |window| {
+             let mut sorted = window.iter()
+                                    .copied()
+                                    .filter(|sample| !sample.is_nan())
+                                    .collect::<Vec<_>>();
+             sorted.sort_by(f64::total_cmp);
+             let mid = sorted.len() / 2;
+             if sorted.is_empty() {
+               f64::NAN
+             } else if sorted.len() % 2 == 0 {
+               (sorted[mid - 1] + sorted[mid]) / 2.0
+             } else {
+               sorted[mid]
+             }
+           }))
+      }
+      Self::SavitzkyGolay { window, order } => {
+        let half = half_width(window, rate_hz)?;
+        if order > 2 * half {
+          return Err(invalid("order",
+                             format!("order {} needs a window of more than \
+                                      {} samples",
+                                     order,
+                                     order)));
+        }
+        Ok(savitzky_golay(samples, half, order))
+      }
+      Self::Butterworth { order, band } => {
+        let sections = band.sections(order, rate_hz)?;
+        Ok(sections.iter()
+                   .fold(samples.to_vec(), |samples, section| {
+                     section.filtfilt(&samples)
+                   }))
+      }
+    }
+  }
+}
+
+impl Band {
+  /// The biquad sections of a Butterworth filter of `order` for this band.
+  fn sections(&self, order: usize, rate_hz: f64) -> Result<Vec<Biquad>> {
+    if order != 1 && order != 2 {
+      return Err(invalid("order",
+                         format!("Butterworth filters of order {} are not \
+                                  supported, only 1 and 2",
+                                 order)));
+    }
+    let check = |cutoff: f64| {
+      if cutoff > 0.0 && cutoff < rate_hz / 2.0 {
+        Ok(cutoff)
+      } else {
+        Err(invalid("band",
+                    format!("cutoff {} Hz must be between 0 and half the \
+                             sample rate of {} Hz",
+                            cutoff,
+                            rate_hz)))
+      }
+    };
+    Ok(match *self {
+      Self::LowPass(cutoff) => {
+        vec![Biquad::low_pass(order, check(cutoff)? / rate_hz)]
+      }
+      Self::HighPass(cutoff) => {
+        vec![Biquad::high_pass(order, check(cutoff)? / rate_hz)]
+      }
+      Self::BandPass(low, high) => {
+        if low >= high {
+          return Err(invalid("band",
+                             format!("lower cutoff {} Hz must be below the \
+                                      upper cutoff {} Hz",
+                                     low,
+                                     high)));
+        }
+        vec![Biquad::high_pass(order, check(low)? / rate_hz),
+             Biquad::low_pass(order, check(high)? / rate_hz)]
+      }
+    })
+  }
+}
+
+
+/// A second order IIR filter section, normalized so `a0` is 1. First order
+/// sections have `b2` and `a2` set to 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Biquad {
+  b: [f64; 3],
+  a: [f64; 2],
+}
+
+impl Biquad {
+  /// Butterworth low-pass at `cutoff`, given as a fraction of the sample
+  /// rate, designed via the bilinear transform.
+  fn low_pass(order: usize, cutoff: f64) -> Self {
+    let k = (PI * cutoff).tan();
+    if order == 1 {
+      let norm = 1.0 / (1.0 + k);
+      Self { b: [k * norm, k * norm, 0.0],
+             a: [(k - 1.0) * norm, 0.0] }
+    } else {
+      let norm = 1.0 / (1.0 + SQRT_2 * k + k * k);
+      let b0 = k * k * norm;
+      Self { b: [b0, 2.0 * b0, b0],
+             a: [2.0 * (k * k - 1.0) * norm,
+                 (1.0 - SQRT_2 * k + k * k) * norm] }
+    }
+  }
+
+  /// Butterworth high-pass at `cutoff`, see `low_pass`.
+  fn high_pass(order: usize, cutoff: f64) -> Self {
+    let k = (PI * cutoff).tan();
+    if order == 1 {
+      let norm = 1.0 / (1.0 + k);
+      Self { b: [norm, -norm, 0.0],
+             a: [(k - 1.0) * norm, 0.0] }
+    } else {
+      let norm = 1.0 / (1.0 + SQRT_2 * k + k * k);
+      Self { b: [norm, -2.0 * norm, norm],
+             a: [2.0 * (k * k - 1.0) * norm,
+                 (1.0 - SQRT_2 * k + k * k) * norm] }
+    }
+  }
+
+  /// Applies the filter forward and backward. The ends are extended by
+  /// point reflection and the filter starts in the state it would be in for
+  /// a constant input, which keeps transients at the ends small.
+  fn filtfilt(&self, samples: &[f64]) -> Vec<f64> {
+    let n = samples.len();
+    let pad = (3 * 3).min(n.saturating_sub(1));
+    let mut extended = Vec::with_capacity(n + 2 * pad);
+    extended.extend((1..=pad).rev().map(|i| 2.0 * samples[0] - samples[i]));
+    extended.extend_from_slice(samples);
+    extended.extend((1..=pad).map(|i| {
+                               2.0 * samples[n - 1] - samples[n - 1 - i]
+                             }));
+
+    let mut filtered = self.filter(&extended);
+    filtered.reverse();
+    let mut filtered = self.filter(&filtered);
+    filtered.reverse();
+    filtered[pad..pad + n].to_vec()
+  }
+
+  /// Applies the filter forward, in transposed direct form II.
+  fn filter(&self, samples: &[f64]) -> Vec<f64> {
+    let ([b0, b1, b2], [a1, a2]) = (self.b, self.a);
+    let first = samples.first().copied().unwrap_or_default();
+    // steady state for a constant input at the first sample
+    let gain = (b0 + b1 + b2) / (1.0 + a1 + a2);
+    let mut z2 = (b2 - a2 * gain) * first;
+    let mut z1 = (b1 - a1 * gain) * first + z2;
+    samples.iter()
+           .map(|&x| {
+             let y = b0 * x + z1;
+             z1 = b1 * x - a1 * y + z2;
+             z2 = b2 * x - a2 * y;
+             y
+           })
+           .collect()
+  }
+}
+
+
+/// Number of samples on either side of the center of a window of `window` s
+/// at `rate_hz`, rounded down to an odd number of samples in the window.
+/// Fails if the window doesn't span at least three samples.
+fn half_width(window: f64, rate_hz: f64) -> Result<usize> {
+  let count = (window * rate_hz).round();
+  if count < 3.0 || !count.is_finite() {
+    return Err(invalid("window",
+                       format!("window of {} s spans less than 3 samples at \
+                                {} Hz",
+                               window,
+                               rate_hz)));
+  }
+  Ok((count as usize - 1) / 2)
+}
+
+fn invalid(name: &'static str, reason: String) -> Error {
+  Error::InvalidArgument { name, reason }
+}
+
+/// Applies `f` to the samples up to `half` samples before and after each
+/// sample.
+fn windowed<F>(samples: &[f64], half: usize, f: F) -> Vec<f64>
+  where F: Fn(&[f64]) -> f64 {
+  let n = samples.len();
+  (0..n).map(|i| f(&samples[i.saturating_sub(half)..(i + half + 1).min(n)]))
+        .collect()
+}
+
+/// Savitzky-Golay smoothing with windows of `2 * half + 1` samples. Towards
+/// the ends, the window stays at the first or last samples and the
+/// polynomial is evaluated off its center.
+fn savitzky_golay(samples: &[f64], half: usize, order: usize) -> Vec<f64> {
+  let n = samples.len();
+  let width = 2 * half + 1;
+  if n < width || n <= order {
+    // not enough samples for a single window, fit them all at once
+    let offsets = (0..n).map(|j| j as f64).collect::<Vec<_>>();
+    let order = order.min(n.saturating_sub(1));
+    return (0..n).map(|i| {
+                   fit_weights(&offsets, i as f64, order).iter()
+                                                         .zip(samples)
+                                                         .map(|(w, y)| w * y)
+                                                         .sum()
+                 })
+                 .collect();
+  }
+
+  let offsets = (0..width).map(|j| j as f64 - half as f64)
+                          .collect::<Vec<_>>();
+  let central = fit_weights(&offsets, 0.0, order);
+  (0..n).map(|i| {
+          let start = i.saturating_sub(half).min(n - width);
+          let window = &samples[start..start + width];
+          let at = i as f64 - (start + half) as f64;
+          let weights = if at == 0.0 {
+            central.clone()
+          } else {
+            fit_weights(&offsets, at, order)
+          };
+          weights.iter().zip(window).map(|(w, y)| w * y).sum()
+        })
+        .collect()
+}
+
+/// Weights which, applied to samples at `offsets`, give the value at `at` of
+/// the least squares polynomial of degree `order` through them.
+fn fit_weights(offsets: &[f64], at: f64, order: usize) -> Vec<f64> {
+  // offsets are scaled to about [-1, 1] to keep the normal equations well
+  // conditioned
+  let scale = offsets.iter()
+                     .map(|x| x.abs())
+                     .fold(1.0, f64::max);
+  let powers = |x: f64| {
+    (0..=order).map(|k| (x / scale).powi(k as i32))
+               .collect::<Vec<_>>()
+  };
+  let rows = offsets.iter().map(|&x| powers(x)).collect::<Vec<_>>();
+
+  // solve (A^T A) c = p(at), then the weights are A c
+  let size = order + 1;
+  let mut normal = vec![vec![0.0; size + 1]; size];
+  for (r, row) in normal.iter_mut().enumerate() {
+    for (c, cell) in row.iter_mut().take(size).enumerate() {
+      *cell = rows.iter().map(|p| p[r] * p[c]).sum();
+    }
+  }
+  for (row, p) in normal.iter_mut().zip(powers(at)) {
+    row[size] = p;
+  }
+  let coefficients = solve(normal);
+  rows.iter()
+      .map(|p| p.iter().zip(&coefficients).map(|(a, c)| a * c).sum())
+      .collect()
+}
+
+/// Solves the linear system given as augmented matrix by Gaussian elimination
+/// with partial pivoting.
+fn solve(mut matrix: Vec<Vec<f64>>) -> Vec<f64> {
+  let size = matrix.len();
+  for col in 0..size {
+    let pivot = (col..size).max_by(|&a, &b| {
+                             matrix[a][col].abs()
+                                           .partial_cmp(&matrix[b][col].abs())
+                                           .unwrap()
+                           })
+                           .unwrap();
+    matrix.swap(col, pivot);
+    let (upper, lower) = matrix.split_at_mut(col + 1);
+    let pivot = &upper[col];
+    for row in lower {
+      let factor = row[col] / pivot[col];
+      for (cell, p) in row[col..].iter_mut().zip(&pivot[col..]) {
+        *cell -= factor * p;
+      }
+    }
+  }
+  let mut solution = vec![0.0; size];
+  for row in (0..size).rev() {
+    let sum = (row + 1..size).map(|k| matrix[row][k] * solution[k])
+                             .sum::<f64>();
+    solution[row] = (matrix[row][size] - sum) / matrix[row][row];
+  }
+  solution
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  fn max_error(samples: &[f64], expected: &[f64]) -> f64 {
+    samples.iter()
+           .zip(expected)
+           .map(|(a, b)| (a - b).abs())
+           .fold(0.0, f64::max)
+  }
+
+  /// Ratio of the amplitude of a sine of `frequency` Hz after filtering to
+  /// before, away from the ends.
+  fn gain(filter: Filter, frequency: f64) -> f64 {
+    let sine = (0..2000).map(|i| {
+                          (2.0 * PI * frequency * i as f64 / 100.0).sin()
+                        })
+                        .collect::<Vec<_>>();
+    let filtered = filter.apply(&sine, 100.0).unwrap();
+    filtered[500..1500].iter().fold(0.0, |max, v| v.abs().max(max))
+  }
+
+  #[test]
+  fn moving_average_test() {
+    let filter = Filter::MovingAverage { window: 0.03 };
+    assert_eq!(vec![1.5, 2.0, 3.0, 3.5],
+               filter.apply(&[1.0, 2.0, 3.0, 4.0], 100.0).unwrap());
+    assert_eq!(true,
+               matches!(filter.apply(&[1.0, 2.0, 3.0], 10.0),
+                        Err(Error::InvalidArgument { name: "window", .. })));
+  }
+
+  #[test]
+  fn median_test() {
+    let filter = Filter::Median { window: 0.03 };
+    assert_eq!(vec![1.5, 1.0, 2.0, 5.0, 5.0, 5.0, 5.0],
+               filter.apply(&[1.0, 2.0, 1.0, 5.0, 50.0, 5.0, 5.0], 100.0)
+                     .unwrap());
+
+    // NaN samples are left out of the windows they are in
+    let nan = f64::NAN;
+    assert_eq!(vec![1.5, 1.5, 3.0, 4.0],
+               filter.apply(&[1.0, 2.0, nan, 4.0], 100.0).unwrap());
+    let filtered = filter.apply(&[nan, nan, nan, 4.0], 100.0).unwrap();
+    assert_eq!(true, filtered[0].is_nan() && filtered[1].is_nan());
+    assert_eq!(vec![4.0, 4.0], filtered[2..].to_vec());
+  }
+
+  #[test]
+  fn savitzky_golay_test() {
+    // polynomials up to the order of the filter pass unchanged, including
+    // at the ends
+    let parabola = (0..20).map(|i| {
+                            let x = i as f64;
+                            0.5 * x * x - 3.0 * x + 1.0
+                          })
+                          .collect::<Vec<_>>();
+    let filter = Filter::SavitzkyGolay { window: 0.07,
+                                         order:  2, };
+    let filtered = filter.apply(&parabola, 100.0).unwrap();
+    assert_eq!(true, max_error(&filtered, &parabola) < 1e-9);
+
+    // with a window of 3 samples, order 2 fits exactly
+    let noisy = [0.0, 1.0, 0.0, 1.0];
+    let filter = Filter::SavitzkyGolay { window: 0.03,
+                                         order:  2, };
+    let filtered = filter.apply(&noisy, 100.0).unwrap();
+    assert_eq!(true, max_error(&filtered, &noisy) < 1e-9);
+    let filter = Filter::SavitzkyGolay { window: 0.03,
+                                         order:  3, };
+    assert_eq!(true,
+               matches!(filter.apply(&noisy, 100.0),
+                        Err(Error::InvalidArgument { name: "order", .. })));
+  }
+
+  #[test]
+  fn butterworth_test() {
+    let low = |order| Filter::Butterworth { order,
+                                            band: Band::LowPass(5.0) };
+    for order in [1, 2].iter() {
+      assert_eq!(true, gain(low(*order), 1.0) > 0.9);
+      // the cutoff is where half of the amplitude passes
+      assert_eq!(true, (gain(low(*order), 5.0) - 0.5).abs() < 0.02);
+      assert_eq!(true, gain(low(*order), 20.0) < 0.15);
+    }
+
+    let high = Filter::Butterworth { order: 2,
+                                     band:  Band::HighPass(5.0), };
+    assert_eq!(true, gain(high, 1.0) < 0.01);
+    assert_eq!(true, gain(high, 25.0) > 0.95);
+
+    let band = Filter::Butterworth { order: 2,
+                                     band:  Band::BandPass(2.0, 10.0), };
+    assert_eq!(true, gain(band, 0.2) < 0.01);
+    assert_eq!(true, gain(band, 4.5) > 0.9);
+    assert_eq!(true, gain(band, 40.0) < 0.01);
+
+    // zero phase: a constant passes a low-pass unchanged, and a peak stays
+    // where it is
+    let constant = vec![3.0; 50];
+    let filtered = low(2).apply(&constant, 100.0).unwrap();
+    assert_eq!(true, max_error(&filtered, &constant) < 1e-9);
+    let mut pulse = vec![0.0; 101];
+    pulse[50] = 1.0;
+    let filtered = low(2).apply(&pulse, 100.0).unwrap();
+    let peak = (0..101).max_by(|&a, &b| {
+                         filtered[a].partial_cmp(&filtered[b]).unwrap()
+                       })
+                       .unwrap();
+    assert_eq!(50, peak);
+
+    for band in [Band::LowPass(50.0), Band::BandPass(5.0, 2.0)].iter() {
+      let filter = Filter::Butterworth { order: 2,
+                                         band:  *band, };
+      assert_eq!(true,
+                 matches!(filter.apply(&constant, 100.0),
+                          Err(Error::InvalidArgument { name: "band", .. })));
+    }
+    let filter = Filter::Butterworth { order: 3,
+                                       band:  Band::LowPass(5.0), };
+    assert_eq!(true,
+               matches!(filter.apply(&constant, 100.0),
+                        Err(Error::InvalidArgument { name: "order", .. })));
+  }
+}
@@ -0,0 +1,209 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Interpolation of channel data onto new timestamps, see
+//! `Channel::resample`.
+
+
+/// How `Channel::resample` computes samples in between the original ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interpolation {
+  /// The value of the latest original sample at or before the timestamp,
+  /// i.e. a step function as the logger saw it.
+  ZeroOrderHold,
+  /// Straight lines between the original samples.
+  Linear,
+  /// A natural cubic spline through the original samples, which is smooth
+  /// but may overshoot at steps.
+  CubicSpline,
+  /// The value of the original sample closest to the timestamp; the earlier
+  /// one on ties.
+  Nearest,
+}
+
+
+/// Returns the samples at `grid`, which must be sorted, computed from the
+/// samples at `timestamps` using `method`. Before the first and after the
+/// last timestamp, the first or last sample is held.
+pub(super) fn interpolate(timestamps: &[f64],
+                          samples: &[f64],
+                          grid: &[f64],
+                          method: Interpolation)
+                          -> Vec<f64> {
+  let n = timestamps.len();
+  let curvatures = match method {
+    Interpolation::CubicSpline => spline_curvatures(timestamps, samples),
+    _ => Vec::new(),
+  };
+
+  grid.iter()
+      .map(|&t| {
+        // number of original samples at or before `t`
+        let after = timestamps.partition_point(|&ts| ts <= t);
+        if after == 0 {
+          return samples[0];
+        }
+        if after == n {
+          return samples[n - 1];
+        }
+
+        let (i, j) = (after - 1, after);
+        let (t0, t1) = (timestamps[i], timestamps[j]);
+        let (y0, y1) = (samples[i], samples[j]);
+        let h = t1 - t0;
+        match method {
+          Interpolation::ZeroOrderHold => y0,
+          Interpolation::Nearest => {
+            if t - t0 <= t1 - t {
+              y0
+            } else {
+              y1
+            }
+          }
+          Interpolation::Linear => y0 + (y1 - y0) * (t - t0) / h,
+          Interpolation::CubicSpline => {
+            let (a, b) = ((t1 - t) / h, (t - t0) / h);
+            a * y0
+            + b * y1
+            + ((a.powi(3) - a) * curvatures[i]
+               + (b.powi(3) - b) * curvatures[j])
+              * h.powi(2)
+              / 6.0
+          }
+        }
+      })
+      .collect()
+}
+
+/// Second derivatives of the natural cubic spline through the samples,
+/// solving the tridiagonal system with the Thomas algorithm.
+fn spline_curvatures(timestamps: &[f64], samples: &[f64]) -> Vec<f64> {
+  let n = timestamps.len();
+  let mut curvatures = vec![0.0; n];
+  if n < 3 {
+    return curvatures;
+  }
+
+  // forward sweep over the interior points; the ends have zero curvature
+  let (mut upper, mut rhs) = (vec![0.0; n], vec![0.0; n]);
+  for i in 1..n - 1 {
+    let (h0, h1) =
+      (timestamps[i] - timestamps[i - 1], timestamps[i + 1] - timestamps[i]);
+    let d = 6.0
+            * ((samples[i + 1] - samples[i]) / h1
+               - (samples[i] - samples[i - 1]) / h0);
+    let diagonal = 2.0 * (h0 + h1) - h0 * upper[i - 1];
+    upper[i] = h1 / diagonal;
+    rhs[i] = (d - h0 * rhs[i - 1]) / diagonal;
+  }
+  // back substitution
+  for i in (1..n - 1).rev() {
+    curvatures[i] = rhs[i] - upper[i] * curvatures[i + 1];
+  }
+  curvatures
+}
+
+/// Low-pass filters the samples with a centered moving average of width
+/// `window` (in s), which suppresses content above the Nyquist frequency of a
+/// rate of `1 / window` before downsampling to it.
+pub(super) fn anti_alias(timestamps: &[f64],
+                         samples: &[f64],
+                         window: f64)
+                         -> Vec<f64> {
+  let half = window / 2.0;
+  let (mut start, mut end, mut sum) = (0, 0, 0.0);
+  timestamps.iter()
+            .map(|&t| {
+              while end < timestamps.len() && timestamps[end] <= t + half {
+                sum += samples[end];
+                end += 1;
+              }
+              while timestamps[start] < t - half {
+                sum -= samples[start];
+                start += 1;
+              }
+              sum / (end - start) as f64
+            })
+            .collect()
+}
+
+/// Sample rate of the data in Hz, from the median interval between
+/// timestamps, so gaps in the recording don't distort it.
+pub(super) fn rate(timestamps: &[f64]) -> f64 {
+  let mut intervals = timestamps.windows(2)
+                                .map(|pair| pair[1] - pair[0])
+                                .filter(|interval| *interval > 0.0)
+                                .collect::<Vec<_>>();
+  if intervals.is_empty() {
+    return 0.0;
+  }
+  intervals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+  1.0 / intervals[intervals.len() / 2]
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn interpolate_test() {
+    let (timestamps, samples) = (vec![0.0, 1.0, 2.0], vec![0.0, 10.0, 0.0]);
+    let grid = vec![-1.0, 0.0, 0.4, 0.5, 1.5, 2.0, 3.0];
+    let at = |method| interpolate(&timestamps, &samples, &grid, method);
+
+    assert_eq!(vec![0.0, 0.0, 0.0, 0.0, 10.0, 0.0, 0.0],
+               at(Interpolation::ZeroOrderHold));
+    assert_eq!(vec![0.0, 0.0, 0.0, 0.0, 10.0, 0.0, 0.0],
+               at(Interpolation::Nearest));
+    assert_eq!(vec![0.0, 0.0, 4.0, 5.0, 5.0, 0.0, 0.0],
+               at(Interpolation::Linear));
+
+    // the spline goes through the samples and bulges in between
+    let spline = interpolate(&timestamps,
+                             &samples,
+                             &[0.0, 0.5, 1.0, 2.0],
+                             Interpolation::CubicSpline);
+    assert_eq!(vec![0.0, 10.0, 0.0], vec![spline[0], spline[2], spline[3]]);
+    assert_eq!(true, spline[1] > 5.0);
+  }
+
+  #[test]
+  fn spline_test() {
+    // splines reproduce straight lines exactly and curves closely
+    let timestamps = (0..50).map(|i| i as f64 * 0.1).collect::<Vec<_>>();
+    let line = timestamps.iter().map(|t| 2.0 * t + 1.0).collect::<Vec<_>>();
+    let sine = timestamps.iter().map(|t| t.sin()).collect::<Vec<_>>();
+    let grid = vec![0.05, 1.23, 2.47, 3.21];
+
+    for (t, value) in grid.iter().zip(interpolate(&timestamps,
+                                                  &line,
+                                                  &grid,
+                                                  Interpolation::CubicSpline))
+    {
+      assert_eq!(true, (value - (2.0 * t + 1.0)).abs() < 1e-9);
+    }
+    for (t, value) in grid.iter().zip(interpolate(&timestamps,
+                                                  &sine,
+                                                  &grid,
+                                                  Interpolation::CubicSpline))
+    {
+      assert_eq!(true, (value - t.sin()).abs() < 1e-3);
+    }
+  }
+
+  #[test]
+  fn anti_alias_test() {
+    let timestamps = (0..100).map(|i| i as f64 * 0.01).collect::<Vec<_>>();
+    let samples = (0..100).map(|i| (-1.0f64).powi(i)).collect::<Vec<_>>();
+    assert_eq!(100.0, rate(&timestamps).round());
+
+    let filtered = anti_alias(&timestamps, &samples, 0.1);
+    assert_eq!(true, filtered[5..95].iter().all(|value| value.abs() < 0.2));
+  }
+}
@@ -0,0 +1,388 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Classification of laps into out laps, in laps, flying laps and so on, so
+//! that statistics can be restricted to laps driven at pace.
+
+use super::{Channel, Lap, Result};
+use getset::{CopyGetters, Getters, Setters};
+use std::collections::VecDeque;
+
+
+/// What kind of lap a lap is, see `Classifier`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LapKind {
+  /// Lap starting in the pit lane.
+  Out,
+  /// Lap ending in the pit lane.
+  In,
+  /// Lap driven at pace from line to line.
+  Flying,
+  /// Lap entering and leaving the pit lane, or stopping on track.
+  PitStop,
+  /// Lap not driven from line to line, e.g. the one the recording ends in,
+  /// or a lap much shorter or slower than the others of the session.
+  Incomplete,
+}
+
+
+/// Classifies laps by their position in the run, their lap time relative to
+/// the session and the channels telling the vehicle is in the pit lane.
+///
+/// Evidence of the pit lane is the pit switch being on, the speed staying
+/// at the pit lane speed limit for a while, or the vehicle standing still.
+/// In the first half of a lap, it makes the lap an out lap; in the second
+/// half, an in lap; in both, or standing still in between, a pit stop. Laps
+/// without any evidence are flying laps, except for
+///
+/// - the first lap of the run, which is taken to be an out lap, as is a lap
+///   following an in lap
+/// - the last lap of the run, which is incomplete since the recording ends
+///   before the line is crossed again
+/// - laps faster or slower than the median lap time by more than
+///   `outlier_factor`, which are incomplete too
+#[derive(Debug, Clone, PartialEq, CopyGetters, Getters, Setters)]
+pub struct Classifier {
+  /// Name of the pit switch channel, which is on while not 0.
+  #[getset(get = "pub", set = "pub")]
+  pit_switch:      String,
+  /// Name of the speed channel.
+  #[getset(get = "pub", set = "pub")]
+  speed:           String,
+  /// Speed limit in the pit lane in km/h. If `None`, any speed between 20
+  /// and 100 km/h held for long enough counts.
+  #[getset(get_copy = "pub", set = "pub")]
+  pit_speed_limit: Option<f64>,
+  /// Deviation from a held speed still counted as holding it, in km/h.
+  #[getset(get_copy = "pub", set = "pub")]
+  tolerance:       f64,
+  /// Time in s the speed must be held, or the vehicle stand still, to count
+  /// as evidence of the pit lane.
+  #[getset(get_copy = "pub", set = "pub")]
+  min_duration:    f64,
+  /// Factor by which lap times may differ from the median lap time.
+  #[getset(get_copy = "pub", set = "pub")]
+  outlier_factor:  f64,
+}
+
+impl Default for Classifier {
+  fn default() -> Self {
+    Self { pit_switch:      "swRotPit".to_string(),
+           speed:           "GPS Speed".to_string(),
+           pit_speed_limit: None,
+           tolerance:       2.0,
+           min_duration:    4.0,
+           outlier_factor:  1.15, }
+  }
+}
+
+impl Classifier {
+  /// Speed in km/h below which the vehicle is taken to stand still.
+  const STANDSTILL: f64 = 5.0;
+
+  /// Classifies `laps`, which must be all laps of a run in order. Only the
+  /// pit switch and speed channels of the laps are looked at, which may
+  /// also be missing. Fails if one of them is a math channel which can't be
+  /// computed, see `Lap::math_channel`.
+  pub fn classify(&self, laps: &[Lap]) -> Result<Vec<LapKind>> {
+    let median = median_lap_time(laps);
+    let mut kinds: Vec<LapKind> = Vec::with_capacity(laps.len());
+    for (i, lap) in laps.iter().enumerate() {
+      let evidence = self.evidence(lap)?;
+      let (first, last) = (i == 0, i + 1 == laps.len());
+      let outlier = median.is_some_and(|median| {
+                             lap.time() > median * self.outlier_factor
+                             || lap.time() < median / self.outlier_factor
+                           });
+      let after_in = kinds.last() == Some(&LapKind::In);
+
+      let kind = if evidence.at_start && evidence.at_end
+                    || evidence.stopped && !first && !last
+      {
+        LapKind::PitStop
+      } else if evidence.at_start || first || after_in {
+        LapKind::Out
+      } else if evidence.at_end {
+        LapKind::In
+      } else if last || outlier {
+        LapKind::Incomplete
+      } else {
+        LapKind::Flying
+      };
+      kinds.push(kind);
+    }
+    Ok(kinds)
+  }
+
+  /// Evidence of the pit lane in `lap`.
+  fn evidence(&self, lap: &Lap) -> Result<Evidence> {
+    let mut found = Vec::new();
+    let mut stopped = false;
+    if let Some(switch) = lap.optional(&self.pit_switch)? {
+      found.extend(intervals(switch, |sample| sample != 0.0));
+    }
+    if let Some(speed) = lap.optional(&self.speed)?.and_then(Channel::in_kmh)
+    {
+      found.extend(plateaus(&speed,
+                            self.pit_speed_limit,
+                            self.tolerance,
+                            self.min_duration));
+      for (from, to) in intervals(&speed, |v| v < Self::STANDSTILL) {
+        if to - from >= self.min_duration {
+          stopped = true;
+          found.push((from, to));
+        }
+      }
+    }
+
+    let middle = lap.start() + lap.time() / 2.0;
+    Ok(Evidence { at_start: found.iter().any(|&(from, _)| from < middle),
+                  at_end: found.iter().any(|&(_, to)| to >= middle),
+                  stopped })
+  }
+}
+
+
+/// Evidence of the pit lane found in a lap.
+#[derive(Debug)]
+struct Evidence {
+  /// Some evidence starts in the first half of the lap.
+  at_start: bool,
+  /// Some evidence ends in the second half of the lap.
+  at_end:   bool,
+  /// The vehicle stands still for at least `min_duration`.
+  stopped:  bool,
+}
+
+
+/// Intervals of consecutive samples of `channel` for which `on` holds.
+pub(crate) fn intervals<F>(channel: &Channel, on: F) -> Vec<(f64, f64)>
+  where F: Fn(f64) -> bool {
+  let mut intervals = Vec::new();
+  let mut current = None;
+  let data = channel.data();
+  for (&t, &sample) in data.timestamps().iter().zip(data.samples()) {
+    match (current, on(sample)) {
+      (None, true) => current = Some((t, t)),
+      (Some((start, _)), true) => current = Some((start, t)),
+      (Some(interval), false) => {
+        intervals.push(interval);
+        current = None;
+      }
+      (None, false) => {}
+    }
+  }
+  intervals.extend(current);
+  intervals
+}
+
+/// Intervals during which `speed` in km/h is held at `limit`, give or take
+/// `tolerance`, for at least `min_duration`. Without a `limit`, any speed
+/// between 20 and 100 km/h counts.
+pub(crate) fn plateaus(speed: &Channel,
+                       limit: Option<f64>,
+                       tolerance: f64,
+                       min_duration: f64)
+                       -> Vec<(f64, f64)> {
+  let timestamps = speed.data().timestamps();
+  let samples = speed.data().samples();
+  let mut plateaus = Vec::new();
+  // indices of the samples from `i` to `j` in which the minimum and maximum
+  // of those after them lie, so both are at the front. the end `j` of the
+  // longest run within the tolerance from `i` never moves back as `i`
+  // advances, which keeps this linear in the number of samples
+  let (mut mins, mut maxs) = (VecDeque::new(), VecDeque::new());
+  let push = |mins: &mut VecDeque<usize>, maxs: &mut VecDeque<usize>, k| {
+    while mins.back().is_some_and(|&m| samples[m] >= samples[k]) {
+      mins.pop_back();
+    }
+    while maxs.back().is_some_and(|&m| samples[m] <= samples[k]) {
+      maxs.pop_back();
+    }
+    mins.push_back(k);
+    maxs.push_back(k);
+  };
+  let (mut i, mut j) = (0, 0);
+  while i < samples.len() {
+    if mins.is_empty() {
+      j = i;
+      push(&mut mins, &mut maxs, i);
+    }
+    // the longest run of samples from `i` on within the tolerance
+    while j + 1 < samples.len() {
+      let lo = samples[mins[0]].min(samples[j + 1]);
+      let hi = samples[maxs[0]].max(samples[j + 1]);
+      if hi - lo > 2.0 * tolerance {
+        break;
+      }
+      j += 1;
+      push(&mut mins, &mut maxs, j);
+    }
+    let mean = (samples[mins[0]] + samples[maxs[0]]) / 2.0;
+    let at_limit = match limit {
+      Some(limit) => (mean - limit).abs() <= tolerance,
+      None => (20.0..=100.0).contains(&mean),
+    };
+    if at_limit && timestamps[j] - timestamps[i] >= min_duration {
+      plateaus.push((timestamps[i], timestamps[j]));
+      i = j + 1;
+      mins.clear();
+      maxs.clear();
+    } else {
+      i += 1;
+      if mins[0] < i {
+        mins.pop_front();
+      }
+      if maxs[0] < i {
+        maxs.pop_front();
+      }
+    }
+  }
+  plateaus
+}
+
+/// Median time of the laps between the first and the last one, which are
+/// rarely complete, or of all laps if there are no others.
+fn median_lap_time(laps: &[Lap]) -> Option<f64> {
+  let inner = if laps.len() > 2 {
+    &laps[1..laps.len() - 1]
+  } else {
+    laps
+  };
+  let mut times = inner.iter().map(Lap::time).collect::<Vec<_>>();
+  if times.is_empty() {
+    return None;
+  }
+  times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+  let mid = times.len() / 2;
+  if times.len() % 2 == 0 {
+    Some((times[mid - 1] + times[mid]) / 2.0)
+  } else {
+    Some(times[mid])
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ChannelData, LapInfo};
+  use pretty_assertions::assert_eq;
+
+
+  /// A lap of `time` s starting at `start`, with a speed sample per second
+  /// given by `speed` and, if `pit` is given, the pit switch on in between.
+  fn lap<F>(idx: usize,
+            start: f64,
+            time: f64,
+            speed: F,
+            pit: Option<(f64, f64)>)
+            -> Lap
+    where F: Fn(f64) -> f64 {
+    let timestamps = (0..time as usize).map(|i| start + i as f64)
+                                       .collect::<Vec<_>>();
+    let speeds = timestamps.iter().map(|&t| speed(t - start)).collect();
+    let switch = timestamps.iter()
+                           .map(|&t| {
+                             let on = pit.is_some_and(|(from, to)| {
+                                            from <= t - start && t - start < to
+                                          });
+                             if on {
+                               1.0
+                             } else {
+                               0.0
+                             }
+                           })
+                           .collect();
+    Lap::new(LapInfo::new(idx, start, time),
+             vec![Channel::new("GPS Speed".to_string(),
+                               "km/h".to_string(),
+                               ChannelData::new(timestamps.clone(), speeds)),
+                  Channel::new("swRotPit".to_string(),
+                               "#".to_string(),
+                               ChannelData::new(timestamps, switch))])
+  }
+
+  #[test]
+  fn classify_test() {
+    let racing = |t: f64| 150.0 + 30.0 * (t / 5.0).sin();
+    let laps = vec![lap(0, 0.0, 100.0, racing, None),
+                    lap(1, 100.0, 90.0, racing, None),
+                    // pit lane speed limit at the end of the lap
+                    lap(2,
+                        190.0,
+                        95.0,
+                        |t| if t > 80.0 { 60.0 } else { racing(t) },
+                        None),
+                    // pit switch on at the start of the lap
+                    lap(3, 285.0, 100.0, racing, Some((0.0, 10.0))),
+                    lap(4, 385.0, 91.0, racing, None),
+                    // standing still on track
+                    lap(5,
+                        476.0,
+                        95.0,
+                        |t| {
+                          if (40.0..50.0).contains(&t) {
+                            0.0
+                          } else {
+                            racing(t)
+                          }
+                        },
+                        None),
+                    lap(6, 571.0, 120.0, racing, None),
+                    lap(7, 691.0, 89.0, racing, None),
+                    lap(8, 780.0, 30.0, racing, None)];
+
+    assert_eq!(vec![LapKind::Out,
+                    LapKind::Flying,
+                    LapKind::In,
+                    LapKind::Out,
+                    LapKind::Flying,
+                    LapKind::PitStop,
+                    LapKind::Incomplete,
+                    LapKind::Flying,
+                    LapKind::Incomplete],
+               Classifier::default().classify(&laps).unwrap());
+
+    // with a known pit speed limit, other plateaus don't count
+    let mut classifier = Classifier::default();
+    classifier.set_pit_speed_limit(Some(80.0));
+    assert_eq!(LapKind::Flying, classifier.classify(&laps).unwrap()[2]);
+  }
+
+  #[test]
+  fn intervals_test() {
+    let channel =
+      Channel::new("swRotPit".to_string(),
+                   "#".to_string(),
+                   ChannelData::new(vec![0.0, 1.0, 2.0, 3.0, 4.0],
+                                    vec![1.0, 1.0, 0.0, 0.0, 2.0]));
+    assert_eq!(vec![(0.0, 1.0), (4.0, 4.0)],
+               intervals(&channel, |sample| sample != 0.0));
+  }
+
+  #[test]
+  fn plateaus_test() {
+    let speed = |samples: Vec<f64>| {
+      Channel::new("GPS Speed".to_string(),
+                   "km/h".to_string(),
+                   ChannelData::new((0..samples.len()).map(|i| i as f64)
+                                                      .collect(),
+                                    samples))
+    };
+    // a plateau may start within a run of samples which isn't one itself
+    let drift = speed(vec![57.5, 59.5, 59.5, 61.0, 61.0, 61.0]);
+    assert_eq!(vec![(1.0, 5.0)], plateaus(&drift, Some(60.0), 1.0, 2.0));
+    assert_eq!(Vec::<(f64, f64)>::new(),
+               plateaus(&drift, Some(60.0), 1.0, 5.0));
+
+    // a long standstill followed by driving at the pit speed limit
+    let mut samples = vec![0.0; 30_000];
+    samples.extend(vec![60.0; 2_000]);
+    assert_eq!(vec![(30_000.0, 31_999.0)],
+               plateaus(&speed(samples), None, 1.0, 10.0));
+  }
+}
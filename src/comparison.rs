@@ -0,0 +1,157 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Comparison of two laps over distance, e.g. of a driver's lap against the
+//! best lap or a teammate's lap.
+
+use super::{Channel, ChannelData, Interpolation, Lap, Result};
+use getset::{CopyGetters, Getters};
+
+
+/// Two laps aligned by distance, with the time one of them gains or loses
+/// against the other along the way.
+///
+/// The laps may come from different `Run`s. Both are aligned on the distance
+/// axis of the reference lap: the distance travelled in the other lap (see
+/// `Lap::distance`) is scaled to the same total, so both cover the same
+/// points of the track, every `step` m.
+#[derive(Debug, Clone, CopyGetters, Getters)]
+pub struct LapComparison<'a> {
+  #[getset(get_copy = "pub")]
+  lap:             &'a Lap,
+  #[getset(get_copy = "pub")]
+  reference:       &'a Lap,
+  /// Time at which each point is reached in the lap.
+  lap_times:       Vec<f64>,
+  reference_times: Vec<f64>,
+  /// Running delta time in s, over distance in m.
+  #[getset(get = "pub")]
+  delta:           Channel,
+}
+
+impl<'a> LapComparison<'a> {
+  /// Aligns `lap` and `reference` by distance with a point every `step` m.
+  ///
+  /// ## Fails if
+  ///
+  /// - the distance can't be computed for either lap, see `Lap::distance`
+  /// - `step` is not positive or the reference lap covers no distance
+  pub fn new(lap: &'a Lap, reference: &'a Lap, step: f64) -> Result<Self> {
+    let reference_distance = reference.distance()?;
+    let samples = reference_distance.data().samples();
+    let length = samples[samples.len() - 1] - samples[0];
+
+    let (mut distances, mut reference_times) =
+      reference_distance.distance_grid(step, None)?;
+    let (_, mut lap_times) =
+      lap.distance()?.distance_grid(step, Some(length))?;
+    // both cover `length`, but rounding may leave one point more in one of
+    // them
+    let len = distances.len().min(lap_times.len());
+    distances.truncate(len);
+    reference_times.truncate(len);
+    lap_times.truncate(len);
+
+    // the delta is taken between the times since the start of either lap
+    let delta = relative(&lap_times).iter()
+                                    .zip(relative(&reference_times))
+                                    .map(|(t, reference_t)| t - reference_t)
+                                    .collect();
+    Ok(Self { lap,
+              reference,
+              lap_times,
+              reference_times,
+              delta: Channel::new("Delta".to_string(),
+                                  "s".to_string(),
+                                  ChannelData::new(distances, delta)) })
+  }
+
+  /// Distances in m of the points the laps are compared at.
+  pub fn distances(&self) -> &Vec<f64> {
+    self.delta.data().timestamps()
+  }
+
+  /// Difference of the lap times, i.e. `lap.time() - reference.time()`. The
+  /// running delta ends close to, but not exactly at this value, since the
+  /// samples don't exactly span the laps.
+  pub fn lap_time_delta(&self) -> f64 {
+    self.lap.time() - self.reference.time()
+  }
+
+  /// The channel named `name` of both laps on the shared distance axis, i.e.
+  /// with the distances in m as timestamps. Fails if either lap lacks the
+  /// channel or it contains less than 2 data points.
+  pub fn overlay(&self, name: &str) -> Result<Overlay> {
+    let at_distances = |lap: &Lap, times: &[f64]| {
+      let channel = lap.required(name)?;
+      let resampled = channel.resample_at(times, Interpolation::Linear)?;
+      Ok(Channel::new(channel.name().clone(),
+                      channel.unit().clone(),
+                      ChannelData::new(self.distances().clone(),
+                                       resampled.data().samples().clone())))
+    };
+    Ok(Overlay { lap:       at_distances(self.lap, &self.lap_times)?,
+                 reference: at_distances(self.reference,
+                                         &self.reference_times)?, })
+  }
+}
+
+
+/// A channel of two compared laps on the same distance axis, see
+/// `LapComparison::overlay`.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct Overlay {
+  lap:       Channel,
+  reference: Channel,
+}
+
+
+/// Times relative to the first one.
+fn relative(times: &[f64]) -> Vec<f64> {
+  times.iter().map(|t| t - times[0]).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Error, LapInfo};
+  use pretty_assertions::assert_eq;
+
+
+  fn lap(idx: usize, start: f64, speed: f64, count: usize) -> Lap {
+    let timestamps = (0..count).map(|i| start + i as f64 * 0.125)
+                               .collect::<Vec<_>>();
+    Lap::new(LapInfo::new(idx, start, (count - 1) as f64 * 0.125),
+             vec![Channel::new("GPS Speed".to_string(),
+                               "m/s".to_string(),
+                               ChannelData::new(timestamps,
+                                                vec![speed; count]))])
+  }
+
+  #[test]
+  fn comparison_test() {
+    // 200 m at 20 m/s and 16 m/s, recorded at different times
+    let (reference, slower) =
+      (lap(0, 0.0, 20.0, 81), lap(3, 100.0, 16.0, 101));
+    let comparison = slower.compare_to(&reference, 10.0).unwrap();
+
+    assert_eq!(21, comparison.distances().len());
+    assert_eq!(100.0, comparison.distances()[10]);
+    assert_eq!(1.25, comparison.delta().data().samples()[10]);
+    assert_eq!(2.5, comparison.delta().data().samples()[20]);
+    assert_eq!(2.5, comparison.lap_time_delta());
+
+    let overlay = comparison.overlay("GPS Speed").unwrap();
+    assert_eq!(comparison.distances(), overlay.lap().data().timestamps());
+    assert_eq!(&vec![16.0; 21], overlay.lap().data().samples());
+    assert_eq!(&vec![20.0; 21], overlay.reference().data().samples());
+    assert_eq!(true,
+               matches!(comparison.overlay("fEngRpm"),
+                        Err(Error::ChannelNotFound { .. })));
+  }
+}
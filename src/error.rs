@@ -0,0 +1,248 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+use super::ChannelGroup;
+use std::{io,
+          path::{Path, PathBuf},
+          time::Duration};
+use thiserror::Error;
+
+
+/// `Result` type used throughout this library.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+
+/// Everything that can go wrong when accessing a drk/xrk file.
+///
+/// Variants carry the path of the file they relate to (which is empty for
+/// sources not backed by a file) and, where applicable, the channel and lap
+/// in question, so callers can react to the kind of failure instead of
+/// parsing messages.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+  /// The path does not point to a file which could be opened, e.g. because
+  /// it does not exist or its extension is neither .xrk nor .drk.
+  #[error("{reason} ({path})")]
+  InvalidPath {
+    path:   PathBuf,
+    reason: &'static str,
+  },
+
+  /// The file could not be read.
+  #[error("could not read file ({path}): {source}")]
+  Io { path: PathBuf, source: io::Error },
+
+  /// The file was read, but its contents can't be parsed.
+  #[error("file can't be parsed ({path}): {reason}")]
+  Unparsable { path: PathBuf, reason: String },
+
+  /// The file does not contain any laps.
+  #[error("file contains 0 laps ({path})")]
+  NoLaps { path: PathBuf },
+
+  /// The file does not contain any channels.
+  #[error("file contains 0 channels ({path})")]
+  NoChannels { path: PathBuf },
+
+  /// There is no lap with index `lap`.
+  #[error("lap index {lap} out of range, file contains {count} laps \
+           ({path})")]
+  LapOutOfRange {
+    path:  PathBuf,
+    lap:   usize,
+    count: usize,
+  },
+
+  /// There is no channel with index `channel` in channel group `group`. For
+  /// functions indexing regular and GPS channels together (e.g.
+  /// `Run::channel`), `group` is `ChannelGroup::Regular` and `count` the
+  /// number of both.
+  #[error("{group} index {channel} out of range, file contains {count} \
+           ({path})")]
+  ChannelOutOfRange {
+    path:    PathBuf,
+    group:   ChannelGroup,
+    channel: usize,
+    count:   usize,
+  },
+
+  /// There is no channel named `name`.
+  #[error("no channel '{name}' found ({path})")]
+  ChannelNotFound { path: PathBuf, name: String },
+
+  /// The math channel `name` can't be computed, e.g. because a channel it
+  /// references is missing.
+  #[error("math channel '{name}' can't be computed{}: {reason}", on(path))]
+  MathChannel {
+    path:   PathBuf,
+    name:   String,
+    reason: String,
+  },
+
+  /// There is no channel named `name` among the channels passed to a
+  /// function processing channel data, e.g. `RunFrame::new`.
+  #[error("no channel '{name}' among the given channels")]
+  MissingChannel { name: String },
+
+  /// The channel contains no samples, either at all (`lap` is `None`) or in
+  /// the lap with index `lap`.
+  #[error("{group} {channel} contains 0 samples{} ({path})",
+          lap.map(|lap| format!(" in lap {}", lap)).unwrap_or_default())]
+  EmptyChannel {
+    path:    PathBuf,
+    group:   ChannelGroup,
+    channel: usize,
+    lap:     Option<usize>,
+  },
+
+  /// A call into the AiM library failed. Calls signalling failure by
+  /// returning `NULL` are reported with `code` 0.
+  #[error("library call `{call}` failed with code {code}{}", on(path))]
+  Library {
+    path: PathBuf,
+    call: &'static str,
+    code: i32,
+  },
+
+  /// A call into the AiM library returned data which can't be interpreted,
+  /// e.g. a string which isn't valid UTF-8.
+  #[error("library call `{call}` returned invalid data{}", on(path))]
+  LibraryData { path: PathBuf, call: &'static str },
+
+  /// The thread hosting the AiM library failed to handle a call.
+  #[error("AiM worker thread failed{}", on(path))]
+  Worker { path: PathBuf },
+
+  /// The helper process hosting the AiM library failed, e.g. because it
+  /// crashed (`sandbox` feature only).
+  #[error("sandbox failed ({path}): {reason}")]
+  Sandbox { path: PathBuf, reason: String },
+
+  /// The helper process hosting the AiM library did not answer within
+  /// `timeout` and was killed (`sandbox` feature only).
+  #[error("sandbox did not answer within {timeout:?} ({path})")]
+  Timeout { path: PathBuf, timeout: Duration },
+
+  /// The file was closed via `Run::close`.
+  #[error("file was closed ({path})")]
+  Closed { path: PathBuf },
+
+  /// The AiM library could not be loaded (`runtime` feature only).
+  #[error("could not load AiM library ({path}): {reason}")]
+  LibraryLoad { path: PathBuf, reason: String },
+
+  /// The channel contains fewer samples than the operation requires.
+  #[error("channel '{channel}' contains {count} samples, at least \
+           {required} required")]
+  InsufficientSamples {
+    channel:  String,
+    count:    usize,
+    required: usize,
+  },
+
+  /// The timestamps of two channels don't overlap.
+  #[error("channels '{channel}' and '{other}' have non-intersecting \
+           timestamp ranges")]
+  DisjointChannels { channel: String, other: String },
+
+  /// An argument passed to a function processing channel data is out of the
+  /// range it accepts, e.g. a negative sample rate.
+  #[error("invalid argument `{name}`: {reason}")]
+  InvalidArgument {
+    name:   &'static str,
+    reason: String,
+  },
+}
+
+impl Error {
+  /// Path of the file the error relates to, if any.
+  pub fn path(&self) -> Option<&Path> {
+    match self {
+      Self::InvalidPath { path, .. }
+      | Self::Io { path, .. }
+      | Self::Unparsable { path, .. }
+      | Self::NoLaps { path }
+      | Self::NoChannels { path }
+      | Self::LapOutOfRange { path, .. }
+      | Self::ChannelOutOfRange { path, .. }
+      | Self::ChannelNotFound { path, .. }
+      | Self::MathChannel { path, .. }
+      | Self::EmptyChannel { path, .. }
+      | Self::Library { path, .. }
+      | Self::LibraryData { path, .. }
+      | Self::Worker { path }
+      | Self::Sandbox { path, .. }
+      | Self::Timeout { path, .. }
+      | Self::Closed { path }
+      | Self::LibraryLoad { path, .. } => Some(path),
+      Self::MissingChannel { .. }
+      | Self::InsufficientSamples { .. }
+      | Self::DisjointChannels { .. }
+      | Self::InvalidArgument { .. } => None,
+    }
+  }
+
+  /// Attributes an `Error::MissingChannel` to the file at `path`, making it
+  /// an `Error::ChannelNotFound`. Other errors are returned unchanged.
+  pub(crate) fn in_file(self, path: &Path) -> Self {
+    match self {
+      Self::MissingChannel { name } => {
+        Self::ChannelNotFound { path: path.to_path_buf(),
+                                name }
+      }
+      error => error,
+    }
+  }
+}
+
+
+/// Formats the path of a file for library errors, which may not relate to
+/// one (e.g. when asking for the library compilation date).
+fn on(path: &Path) -> String {
+  if path.as_os_str().is_empty() {
+    String::new()
+  } else {
+    format!(" ({})", path.display())
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn display_test() {
+    let path = PathBuf::from("run.xrk");
+    assert_eq!("channel 3 contains 0 samples in lap 1 (run.xrk)",
+               Error::EmptyChannel { path:    path.clone(),
+                                     group:   ChannelGroup::Regular,
+                                     channel: 3,
+                                     lap:     Some(1), }.to_string());
+    assert_eq!("GPS channel 0 contains 0 samples (run.xrk)",
+               Error::EmptyChannel { path:    path.clone(),
+                                     group:   ChannelGroup::Gps,
+                                     channel: 0,
+                                     lap:     None, }.to_string());
+    assert_eq!("library call `get_laps_count` failed with code -1 (run.xrk)",
+               Error::Library { path: path.clone(),
+                                call: "get_laps_count",
+                                code: -1, }.to_string());
+    assert_eq!("library call `get_library_date` failed with code 0",
+               Error::Library { path: PathBuf::new(),
+                                call: "get_library_date",
+                                code: 0, }.to_string());
+    assert_eq!(Some(path.as_path()),
+               Error::NoLaps { path: path.clone() }.path());
+    let missing = Error::MissingChannel { name: "fEngRpm".to_string() };
+    assert_eq!(None, missing.path());
+    assert_eq!("no channel 'fEngRpm' found (run.xrk)",
+               missing.in_file(&path).to_string());
+  }
+}
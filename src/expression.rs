@@ -0,0 +1,682 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Expressions computing a channel from other channels, e.g.
+//! `pBrakeF / (pBrakeF + pBrakeR) * 100`, see `Expression`.
+
+use super::{Channel, ChannelData, Error, Interpolation, Result};
+use getset::Getters;
+
+
+/// An expression computing a new channel from channels referenced by name,
+/// sample by sample.
+///
+/// ## Syntax
+///
+/// - numbers like `100`, `0.5` or `1e3`
+/// - channels by name, e.g. `pBrakeF`, or in double quotes if the name
+///   contains anything but letters, digits, `_` and `.`, e.g. `"GPS Speed"`
+/// - arithmetic with `+`, `-`, `*`, `/` and `^` (power)
+/// - comparisons with `<`, `<=`, `>`, `>=`, `==` and `!=`, and logic with
+///   `&&`, `||` and `!`, which are 1 if true and 0 if false; any value but 0
+///   counts as true
+/// - `if(condition, then, else)`
+/// - `abs`, `sqrt`, `exp`, `ln`, `log10`, `sin`, `cos`, `tan`, `atan2(y, x)`,
+///   `min(a, b)`, `max(a, b)`, `clamp(x, low, high)`, `round`, `floor` and
+///   `ceil`
+/// - `lag(x, seconds)`, the value of `x` the given number of seconds
+///   earlier, `derivative(x)` over time and `integral(x)` over time from the
+///   first sample on, see `Channel::derivative` and `Channel::integral`
+///
+/// Operators bind as usual, from loosest to tightest: `||`, `&&`,
+/// comparisons, `+` and `-`, `*` and `/`, unary `-` and `!`, `^`.
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct Expression {
+  /// The text the expression was parsed from.
+  #[getset(get = "pub")]
+  source: String,
+  root:   Node,
+}
+
+impl Expression {
+  /// Parses `source`. Fails with `Error::InvalidArgument` if it isn't a
+  /// valid expression, telling where the problem is.
+  pub fn parse(source: &str) -> Result<Self> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { source,
+                              tokens: &tokens,
+                              pos: 0 };
+    let root = parser.or()?;
+    if let Some((at, _)) = parser.peek() {
+      return Err(parser.error(at, "expected an operator"));
+    }
+    Ok(Self { source: source.to_string(),
+              root })
+  }
+
+  /// Names of the channels the expression references, in order of first
+  /// appearance.
+  pub fn channels(&self) -> Vec<String> {
+    let mut names = Vec::new();
+    self.root.channels(&mut names);
+    names
+  }
+
+  /// Evaluates the expression on `channels`, which must contain all channels
+  /// it references. These are synchronized to the timestamps of the one
+  /// with the highest sample rate (taken from the median interval between
+  /// its timestamps), interpolating linearly. The result is a
+  /// channel named after the source of the expression, without a unit.
+  ///
+  /// ## Fails if
+  ///
+  /// - a channel referenced is missing from `channels`
+  /// - the expression doesn't reference any channel, so there are no
+  ///   timestamps to compute it at
+  /// - a channel referenced contains less than 2 data points
+  pub fn evaluate(&self, channels: &[Channel]) -> Result<Channel> {
+    let inputs = self.channels()
+                     .into_iter()
+                     .map(|name| {
+                       channels.iter()
+                               .find(|channel| *channel.name() == name)
+                               .ok_or(Error::MissingChannel { name })
+                     })
+                     .collect::<Result<Vec<_>>>()?;
+    let mut base: Option<&Channel> = None;
+    for &channel in &inputs {
+      match base {
+        Some(base) if base.sample_rate() >= channel.sample_rate() => {}
+        _ => base = Some(channel),
+      }
+    }
+    let base = base.ok_or_else(|| {
+                     Error::InvalidArgument { name:   "expression",
+                                              reason: format!("'{}' \
+                                                               references \
+                                                               no channel",
+                                                              self.source), }
+                   })?;
+
+    let timestamps = base.data().timestamps().clone();
+    let samples = inputs.iter()
+                        .map(|channel| {
+                          let samples = if channel.name() == base.name() {
+                            channel.data().samples().clone()
+                          } else {
+                            channel.resample_at(&timestamps,
+                                                Interpolation::Linear)?
+                                   .data()
+                                   .samples()
+                                   .clone()
+                          };
+                          Ok((channel.name().clone(), samples))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+    let context = Context { timestamps: &timestamps,
+                            inputs:     &samples, };
+    let result = self.root.evaluate(&context)?;
+    Ok(Channel::new(self.source.trim().to_string(),
+                    String::new(),
+                    ChannelData::new(timestamps, result)))
+  }
+}
+
+
+/// A node of the syntax tree of an `Expression`.
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+  Number(f64),
+  Channel(String),
+  Negate(Box<Node>),
+  Not(Box<Node>),
+  Binary(Operator, Box<Node>, Box<Node>),
+  Call(Function, Vec<Node>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+  Power,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  Equal,
+  NotEqual,
+  And,
+  Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+  If,
+  Abs,
+  Sqrt,
+  Exp,
+  Ln,
+  Log10,
+  Sin,
+  Cos,
+  Tan,
+  Atan2,
+  Min,
+  Max,
+  Clamp,
+  Round,
+  Floor,
+  Ceil,
+  Lag,
+  Derivative,
+  Integral,
+}
+
+impl Function {
+  fn by_name(name: &str) -> Option<Self> {
+    Some(match name {
+      "if" => Self::If,
+      "abs" => Self::Abs,
+      "sqrt" => Self::Sqrt,
+      "exp" => Self::Exp,
+      "ln" => Self::Ln,
+      "log10" => Self::Log10,
+      "sin" => Self::Sin,
+      "cos" => Self::Cos,
+      "tan" => Self::Tan,
+      "atan2" => Self::Atan2,
+      "min" => Self::Min,
+      "max" => Self::Max,
+      "clamp" => Self::Clamp,
+      "round" => Self::Round,
+      "floor" => Self::Floor,
+      "ceil" => Self::Ceil,
+      "lag" => Self::Lag,
+      "derivative" => Self::Derivative,
+      "integral" => Self::Integral,
+      _ => return None,
+    })
+  }
+
+  /// Number of arguments the function takes.
+  fn arity(self) -> usize {
+    match self {
+      Self::If | Self::Clamp => 3,
+      Self::Atan2 | Self::Min | Self::Max | Self::Lag => 2,
+      _ => 1,
+    }
+  }
+
+  /// Applies the function to a single value, for functions of one argument
+  /// which work sample by sample.
+  fn unary(self, x: f64) -> f64 {
+    match self {
+      Self::Abs => x.abs(),
+      Self::Sqrt => x.sqrt(),
+      Self::Exp => x.exp(),
+      Self::Ln => x.ln(),
+      Self::Log10 => x.log10(),
+      Self::Sin => x.sin(),
+      Self::Cos => x.cos(),
+      Self::Tan => x.tan(),
+      Self::Round => x.round(),
+      Self::Floor => x.floor(),
+      Self::Ceil => x.ceil(),
+      _ => unreachable!("{:?} takes more than one argument", self),
+    }
+  }
+}
+
+
+/// Timestamps and synchronized samples of the channels an expression is
+/// evaluated on.
+struct Context<'a> {
+  timestamps: &'a [f64],
+  inputs:     &'a [(String, Vec<f64>)],
+}
+
+impl Node {
+  fn channels(&self, names: &mut Vec<String>) {
+    match self {
+      Self::Number(_) => {}
+      Self::Channel(name) => {
+        if !names.contains(name) {
+          names.push(name.clone());
+        }
+      }
+      Self::Negate(node) | Self::Not(node) => node.channels(names),
+      Self::Binary(_, lhs, rhs) => {
+        lhs.channels(names);
+        rhs.channels(names);
+      }
+      Self::Call(_, args) => args.iter().for_each(|arg| arg.channels(names)),
+    }
+  }
+
+  /// The value of the node at each timestamp of `context`.
+  fn evaluate(&self, context: &Context) -> Result<Vec<f64>> {
+    let n = context.timestamps.len();
+    let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+    Ok(match self {
+      Self::Number(value) => vec![*value; n],
+      Self::Channel(name) => {
+        // all channels referenced were synchronized before
+        let (_, samples) =
+          context.inputs.iter().find(|(input, _)| input == name).unwrap();
+        samples.clone()
+      }
+      Self::Negate(node) => node.evaluate(context)?
+                                .into_iter()
+                                .map(|x| -x)
+                                .collect(),
+      Self::Not(node) => node.evaluate(context)?
+                             .into_iter()
+                             .map(|x| truth(x == 0.0))
+                             .collect(),
+      Self::Binary(operator, lhs, rhs) => {
+        let (lhs, rhs) = (lhs.evaluate(context)?, rhs.evaluate(context)?);
+        lhs.into_iter()
+           .zip(rhs)
+           .map(|(a, b)| match operator {
+             Operator::Add => a + b,
+             Operator::Subtract => a - b,
+             Operator::Multiply => a * b,
+             Operator::Divide => a / b,
+             Operator::Power => a.powf(b),
+             Operator::Less => truth(a < b),
+             Operator::LessEqual => truth(a <= b),
+             Operator::Greater => truth(a > b),
+             Operator::GreaterEqual => truth(a >= b),
+             Operator::Equal => truth(a == b),
+             Operator::NotEqual => truth(a != b),
+             Operator::And => truth(a != 0.0 && b != 0.0),
+             Operator::Or => truth(a != 0.0 || b != 0.0),
+           })
+           .collect()
+      }
+      Self::Call(Function::Lag, args) => {
+        // the parser makes sure the lag is a number
+        let lag = match args[1] {
+          Self::Number(lag) => lag,
+          _ => unreachable!(),
+        };
+        let earlier =
+          context.timestamps.iter().map(|t| t - lag).collect::<Vec<_>>();
+        self.over_time(&args[0], context, |channel| {
+              channel.resample_at(&earlier, Interpolation::Linear)
+            })?
+      }
+      Self::Call(Function::Derivative, args) => {
+        self.over_time(&args[0], context, Channel::derivative)?
+      }
+      Self::Call(Function::Integral, args) => {
+        self.over_time(&args[0], context, Channel::integral)?
+      }
+      Self::Call(function, args) => {
+        let args = args.iter()
+                       .map(|arg| arg.evaluate(context))
+                       .collect::<Result<Vec<_>>>()?;
+        (0..n).map(|i| match function {
+                Function::If => {
+                  if args[0][i] != 0.0 {
+                    args[1][i]
+                  } else {
+                    args[2][i]
+                  }
+                }
+                Function::Atan2 => args[0][i].atan2(args[1][i]),
+                Function::Min => args[0][i].min(args[1][i]),
+                Function::Max => args[0][i].max(args[1][i]),
+                Function::Clamp => args[0][i].max(args[1][i]).min(args[2][i]),
+                _ => function.unary(args[0][i]),
+              })
+              .collect()
+      }
+    })
+  }
+
+  /// Evaluates `arg` and applies `f` to it as a channel, for functions which
+  /// need the timestamps rather than working sample by sample.
+  fn over_time<F>(&self,
+                  arg: &Node,
+                  context: &Context,
+                  f: F)
+                  -> Result<Vec<f64>>
+    where F: Fn(&Channel) -> Result<Channel> {
+    let channel = Channel::new(String::new(),
+                               String::new(),
+                               ChannelData::new(context.timestamps.to_vec(),
+                                                arg.evaluate(context)?));
+    Ok(f(&channel)?.data().samples().clone())
+  }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Number(f64),
+  Name(String),
+  Symbol(&'static str),
+}
+
+/// Splits `source` into tokens, each with its position in `source`.
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>> {
+  const SYMBOLS: [&str; 17] = ["<=", ">=", "==", "!=", "&&", "||", "+", "-",
+                               "*", "/", "^", "<", ">", "!", "(", ")", ","];
+  let chars = source.char_indices().collect::<Vec<_>>();
+  let mut tokens = Vec::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let (at, c) = chars[i];
+    let rest = &source[at..];
+    if c.is_whitespace() {
+      i += 1;
+    } else if c.is_ascii_digit() || c == '.' {
+      let len = number_len(rest);
+      let number = rest[..len].parse::<f64>()
+                              .map_err(|_| {
+                                error(source, at, "invalid number")
+                              })?;
+      tokens.push((at, Token::Number(number)));
+      i += rest[..len].chars().count();
+    } else if c.is_alphabetic() || c == '_' {
+      let len = rest.find(|c: char| {
+                      !(c.is_alphanumeric() || c == '_' || c == '.')
+                    })
+                    .unwrap_or(rest.len());
+      tokens.push((at, Token::Name(rest[..len].to_string())));
+      i += rest[..len].chars().count();
+    } else if c == '"' {
+      let len = rest[1..].find('"')
+                         .ok_or_else(|| error(source, at, "unclosed quote"))?;
+      tokens.push((at, Token::Name(rest[1..=len].to_string())));
+      i += rest[..len + 2].chars().count();
+    } else {
+      let symbol = SYMBOLS.iter()
+                          .find(|symbol| rest.starts_with(*symbol))
+                          .ok_or_else(|| {
+                            error(source, at, "unexpected character")
+                          })?;
+      tokens.push((at, Token::Symbol(symbol)));
+      i += symbol.len();
+    }
+  }
+  Ok(tokens)
+}
+
+/// Length of the number at the start of `text`, with an optional fraction
+/// and exponent.
+fn number_len(text: &str) -> usize {
+  let bytes = text.as_bytes();
+  let digits = |from: usize| {
+    from + bytes[from..].iter().take_while(|b| b.is_ascii_digit()).count()
+  };
+  let mut len = digits(0);
+  if bytes.get(len) == Some(&b'.') {
+    len = digits(len + 1);
+  }
+  if matches!(bytes.get(len), Some(b'e') | Some(b'E')) {
+    let sign = matches!(bytes.get(len + 1), Some(b'+') | Some(b'-')) as usize;
+    let exponent = digits(len + 1 + sign);
+    if exponent > len + 1 + sign {
+      len = exponent;
+    }
+  }
+  len
+}
+
+fn error(source: &str, at: usize, problem: &str) -> Error {
+  Error::InvalidArgument { name:   "expression",
+                           reason: format!("{} at position {} of '{}'",
+                                           problem, at, source), }
+}
+
+
+/// Recursive descent parser over the tokens of an expression, one method
+/// per level of precedence.
+struct Parser<'a> {
+  source: &'a str,
+  tokens: &'a [(usize, Token)],
+  pos:    usize,
+}
+
+impl Parser<'_> {
+  fn peek(&self) -> Option<(usize, &Token)> {
+    self.tokens.get(self.pos).map(|(at, token)| (*at, token))
+  }
+
+  /// Consumes the next token if it is one of `symbols`.
+  fn symbol(&mut self, symbols: &[&str]) -> Option<&'static str> {
+    match self.peek() {
+      Some((_, Token::Symbol(symbol))) if symbols.contains(symbol) => {
+        let symbol = *symbol;
+        self.pos += 1;
+        Some(symbol)
+      }
+      _ => None,
+    }
+  }
+
+  fn expect(&mut self, symbol: &str) -> Result<()> {
+    match self.symbol(&[symbol]) {
+      Some(_) => Ok(()),
+      None => Err(self.error(self.here(), &format!("expected '{}'", symbol))),
+    }
+  }
+
+  /// Position of the next token, or the end of the source.
+  fn here(&self) -> usize {
+    self.peek().map_or(self.source.len(), |(at, _)| at)
+  }
+
+  fn error(&self, at: usize, problem: &str) -> Error {
+    error(self.source, at, problem)
+  }
+
+  /// Parses operands separated by any of `operators`, left associative.
+  fn binary<F>(&mut self,
+               operators: &[(&str, Operator)],
+               operand: F)
+               -> Result<Node>
+    where F: Fn(&mut Self) -> Result<Node> {
+    let symbols = operators.iter().map(|(s, _)| *s).collect::<Vec<_>>();
+    let mut lhs = operand(self)?;
+    while let Some(symbol) = self.symbol(&symbols) {
+      let operator = operators.iter().find(|(s, _)| *s == symbol).unwrap().1;
+      lhs = Node::Binary(operator, Box::new(lhs), Box::new(operand(self)?));
+    }
+    Ok(lhs)
+  }
+
+  fn or(&mut self) -> Result<Node> {
+    self.binary(&[("||", Operator::Or)], Self::and)
+  }
+
+  fn and(&mut self) -> Result<Node> {
+    self.binary(&[("&&", Operator::And)], Self::comparison)
+  }
+
+  fn comparison(&mut self) -> Result<Node> {
+    self.binary(&[("<=", Operator::LessEqual),
+                  (">=", Operator::GreaterEqual),
+                  ("==", Operator::Equal),
+                  ("!=", Operator::NotEqual),
+                  ("<", Operator::Less),
+                  (">", Operator::Greater)],
+                Self::sum)
+  }
+
+  fn sum(&mut self) -> Result<Node> {
+    self.binary(&[("+", Operator::Add), ("-", Operator::Subtract)],
+                Self::product)
+  }
+
+  fn product(&mut self) -> Result<Node> {
+    self.binary(&[("*", Operator::Multiply), ("/", Operator::Divide)],
+                Self::unary)
+  }
+
+  fn unary(&mut self) -> Result<Node> {
+    match self.symbol(&["-", "!"]) {
+      Some("-") => Ok(Node::Negate(Box::new(self.unary()?))),
+      Some(_) => Ok(Node::Not(Box::new(self.unary()?))),
+      None => self.power(),
+    }
+  }
+
+  /// A primary, possibly raised to a power; right associative and binding
+  /// tighter than a unary minus in front of it, so `-2^2` is -4.
+  fn power(&mut self) -> Result<Node> {
+    let base = self.primary()?;
+    if self.symbol(&["^"]).is_some() {
+      return Ok(Node::Binary(Operator::Power,
+                             Box::new(base),
+                             Box::new(self.unary()?)));
+    }
+    Ok(base)
+  }
+
+  fn primary(&mut self) -> Result<Node> {
+    let at = self.here();
+    let token = self.peek().map(|(_, token)| token.clone());
+    match token {
+      Some(Token::Number(value)) => {
+        self.pos += 1;
+        Ok(Node::Number(value))
+      }
+      Some(Token::Name(name)) => {
+        self.pos += 1;
+        let quoted = self.source[at..].starts_with('"');
+        if quoted || self.symbol(&["("]).is_none() {
+          return Ok(Node::Channel(name));
+        }
+        let unknown = format!("unknown function '{}'", name);
+        let function = Function::by_name(&name).ok_or_else(|| {
+                                                 self.error(at, &unknown)
+                                               })?;
+        self.call(function, at)
+      }
+      Some(Token::Symbol("(")) => {
+        self.pos += 1;
+        let node = self.or()?;
+        self.expect(")")?;
+        Ok(node)
+      }
+      _ => Err(self.error(at, "expected a number, channel or '('")),
+    }
+  }
+
+  /// The arguments of a call to `function` at `at`, after the opening
+  /// parenthesis.
+  fn call(&mut self, function: Function, at: usize) -> Result<Node> {
+    let mut args = vec![self.or()?];
+    while self.symbol(&[","]).is_some() {
+      args.push(self.or()?);
+    }
+    self.expect(")")?;
+    if args.len() != function.arity() {
+      return Err(self.error(at,
+                            &format!("function takes {} argument(s), not {}",
+                                     function.arity(),
+                                     args.len())));
+    }
+    if function == Function::Lag && !matches!(args[1], Node::Number(_)) {
+      return Err(self.error(at, "lag must be a number of seconds"));
+    }
+    Ok(Node::Call(function, args))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  fn channel(name: &str, timestamps: Vec<f64>, samples: Vec<f64>) -> Channel {
+    Channel::new(name.to_string(),
+                 "bar".to_string(),
+                 ChannelData::new(timestamps, samples))
+  }
+
+  fn evaluate(source: &str) -> Vec<f64> {
+    let channels =
+      vec![channel("pBrakeF", vec![0.0, 1.0, 2.0], vec![30.0, 10.0, 0.0]),
+           channel("pBrakeR", vec![0.0, 1.0, 2.0], vec![10.0, 10.0, 0.0]),
+           channel("GPS Speed", vec![0.0, 1.0, 2.0], vec![2.0, 3.0, 4.0])];
+    Expression::parse(source).unwrap()
+                             .evaluate(&channels)
+                             .unwrap()
+                             .data()
+                             .samples()
+                             .clone()
+  }
+
+  fn invalid(source: &str) -> bool {
+    matches!(Expression::parse(source),
+             Err(Error::InvalidArgument { name: "expression", .. }))
+  }
+
+  #[test]
+  fn evaluate_test() {
+    let balance = evaluate("pBrakeF / (pBrakeF + pBrakeR) * 100");
+    assert_eq!(vec![75.0, 50.0], balance[..2].to_vec());
+    assert_eq!(true, balance[2].is_nan());
+
+    assert_eq!(vec![1.0, 0.0, 0.0], evaluate("if(pBrakeF > 15, 1, 0)"));
+    assert_eq!(vec![5.0, 0.0, -7.0],
+               evaluate("-\"GPS Speed\"^2 + 2 * 3e0 * (1 + 0.5)"));
+    assert_eq!(vec![0.0, 1.0, 0.0],
+               evaluate("pBrakeF == pBrakeR && !(pBrakeF < 1) || 0"));
+    assert_eq!(vec![16.0, 16.0, 8.0], evaluate("2^3^0 * 2^(2 - .5e1 * 0.2)^0 \
+                                               * max(pBrakeR / 2.5, 2)"));
+    assert_eq!(vec![20.0, 10.0, 5.0],
+               evaluate("clamp(pBrakeF, 5, 20) + min(0, abs(-1))"));
+    assert_eq!(vec![30.0, 30.0, 10.0], evaluate("lag(pBrakeF, 1)"));
+    assert_eq!(vec![-20.0, -15.0, -10.0], evaluate("derivative(pBrakeF)"));
+    assert_eq!(vec![0.0, 2.5, 6.0], evaluate("integral(\"GPS Speed\")"));
+  }
+
+  #[test]
+  fn synchronize_test() {
+    // the inputs are synchronized to the channel with the highest rate
+    let fast = channel("a", vec![0.0, 0.5, 1.0, 1.5, 2.0], vec![1.0; 5]);
+    let slow = channel("b", vec![0.0, 1.0, 2.0], vec![0.0, 2.0, 4.0]);
+    let expression = Expression::parse("b - a").unwrap();
+    assert_eq!(vec!["b".to_string(), "a".to_string()],
+               expression.channels());
+    let result = expression.evaluate(&[slow, fast]).unwrap();
+    assert_eq!("b - a", result.name());
+    assert_eq!(&vec![0.0, 0.5, 1.0, 1.5, 2.0], result.data().timestamps());
+    assert_eq!(&vec![-1.0, 0.0, 1.0, 2.0, 3.0], result.data().samples());
+
+    // the rate is taken from the median interval, so a burst of samples at
+    // the start doesn't make a slow channel the fastest
+    let fast = channel("a", vec![0.0, 0.5, 1.0, 1.5, 2.0], vec![1.0; 5]);
+    let burst = channel("b", vec![0.0, 0.01, 0.02, 1.0, 2.0], vec![0.0; 5]);
+    let result = expression.evaluate(&[burst, fast]).unwrap();
+    assert_eq!(&vec![0.0, 0.5, 1.0, 1.5, 2.0], result.data().timestamps());
+
+    assert_eq!(true,
+               matches!(expression.evaluate(&[]),
+                        Err(Error::MissingChannel { .. })));
+    assert_eq!(true,
+               matches!(Expression::parse("1 + 2").unwrap().evaluate(&[]),
+                        Err(Error::InvalidArgument { .. })));
+  }
+
+  #[test]
+  fn parse_test() {
+    for source in ["", "1 +", "(1", "1 2", "foo(1)", "abs(1, 2)", "1 = 2",
+                   "\"GPS Speed", "lag(a, b)", "a # b"].iter()
+    {
+      assert_eq!(true, invalid(source), "{}", source);
+    }
+  }
+}
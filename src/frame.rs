@@ -0,0 +1,384 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Tables of many channels on one shared time base.
+//!
+//! The channels of a lap or run are recorded at different rates and thus
+//! have different timestamps. A `RunFrame` resamples a set of them onto one
+//! time base, so they can be processed row by row (all channels at one point
+//! in time) or column by column (one channel at all points in time).
+
+use super::{Channel, ChannelData, Error, Interpolation, Result};
+use getset::Getters;
+
+
+/// The timestamps a `RunFrame` resamples its channels onto.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeBase {
+  /// The timestamps of the channel with the given name, whose samples are
+  /// taken as they are.
+  Reference(String),
+  /// A fixed rate at the highest frequency of any of the channels, see
+  /// `Lap::max_frequency`.
+  MaxFrequency,
+  /// A fixed rate in Hz.
+  Rate(f64),
+}
+
+
+/// A single channel of a `RunFrame`.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct Column {
+  name:    String,
+  unit:    String,
+  samples: Vec<f64>,
+}
+
+
+/// A column-oriented table of channels sharing the same timestamps.
+///
+/// Build one with `Lap::to_frame`, `Run::frame` or, from any set of channels,
+/// `RunFrame::new`. Frames re-indexed onto a distance axis (see
+/// `over_distance`) have a distance for each row in addition to the
+/// timestamp.
+#[derive(Debug, Clone, Default, PartialEq, Getters)]
+pub struct RunFrame {
+  #[getset(get = "pub")]
+  timestamps: Vec<f64>,
+  distances:  Option<Vec<f64>>,
+  #[getset(get = "pub")]
+  columns:    Vec<Column>,
+}
+
+impl RunFrame {
+  /// Resamples `channels` onto `time_base`, computing samples in between the
+  /// original ones using `method`, see `Channel::resample`.
+  ///
+  /// With a fixed rate, the timestamps are the multiples of `1 / rate` within
+  /// the range all channels have data for, so nothing is extrapolated. With a
+  /// reference channel, they are the timestamps of that channel, and other
+  /// channels are held at their first or last sample where they lack data.
+  ///
+  /// ## Fails if
+  ///
+  /// - `channels` is empty or any channel contains less than 2 data points
+  /// - the reference channel is not among `channels`
+  /// - none of the channels has a known frequency (`TimeBase::MaxFrequency`)
+  ///   or the rate is not positive (`TimeBase::Rate`)
+  /// - the channels have non-intersecting timestamp ranges (fixed rates only)
+  pub fn new(channels: &[Channel],
+             time_base: TimeBase,
+             method: Interpolation)
+             -> Result<Self> {
+    if channels.is_empty() {
+      let reason = "no channels given".to_string();
+      return Err(Error::InvalidArgument { name: "channels",
+                                          reason });
+    }
+    for channel in channels {
+      if channel.len() < 2 {
+        return Err(Error::InsufficientSamples { channel:  channel.name()
+                                                                 .clone(),
+                                                count:    channel.len(),
+                                                required: 2, });
+      }
+    }
+
+    let rate_hz = match time_base {
+      TimeBase::Reference(name) => {
+        let reference =
+          channels.iter()
+                  .find(|channel| channel.name() == &name)
+                  .ok_or(Error::MissingChannel { name })?;
+        let timestamps = reference.data().timestamps();
+        let resampled =
+          channels.iter()
+                  .map(|channel| channel.resample_at(timestamps, method))
+                  .collect::<Result<Vec<_>>>()?;
+        return Ok(Self::from_resampled(timestamps.clone(), resampled));
+      }
+      TimeBase::MaxFrequency => {
+        let rate_hz = channels.iter()
+                              .map(|channel| channel.frequency())
+                              .fold(0.0, f64::max);
+        if rate_hz <= 0.0 {
+          return Err(Error::InvalidArgument { name:   "time_base",
+                                              reason:
+                                                "no channel has a known \
+                                                 frequency"
+                                                           .to_string(), });
+        }
+        rate_hz
+      }
+      TimeBase::Rate(rate_hz) => rate_hz,
+    };
+
+    // the range all channels have data for; indexing is safe since every
+    // channel has at least 2 samples
+    let first = |channel: &Channel| channel.data().timestamps()[0];
+    let last =
+      |channel: &Channel| channel.data().timestamps()[channel.len() - 1];
+    let latest_start =
+      channels.iter()
+              .max_by(|a, b| first(a).partial_cmp(&first(b)).unwrap())
+              .unwrap();
+    let earliest_end =
+      channels.iter()
+              .min_by(|a, b| last(a).partial_cmp(&last(b)).unwrap())
+              .unwrap();
+    let (start, end) = (first(latest_start), last(earliest_end));
+    if start > end {
+      return Err(Error::DisjointChannels { channel: latest_start.name()
+                                                                .clone(),
+                                           other:   earliest_end.name()
+                                                                .clone(), });
+    }
+
+    let resampled =
+      channels.iter()
+              .map(|channel| {
+                channel.resample_over(start, end, rate_hz, method)
+              })
+              .collect::<Result<Vec<_>>>()?;
+    let timestamps = resampled[0].data().timestamps().clone();
+    Ok(Self::from_resampled(timestamps, resampled))
+  }
+
+  fn from_resampled(timestamps: Vec<f64>, channels: Vec<Channel>) -> Self {
+    let columns = channels.into_iter()
+                          .map(|channel| {
+                            let (name, unit) =
+                              (channel.name().clone(), channel.unit().clone());
+                            let samples = channel.data().samples().clone();
+                            Column { name,
+                                     unit,
+                                     samples }
+                          })
+                          .collect();
+    Self { timestamps,
+           distances: None,
+           columns }
+  }
+
+  /// Re-index the frame onto a distance axis, given the `distance` travelled
+  /// over time, with a row every `step` m at the time that distance is first
+  /// reached. See `Channel::over_distance`, also for `length`.
+  pub fn over_distance(&self,
+                       distance: &Channel,
+                       step: f64,
+                       length: Option<f64>)
+                       -> Result<Self> {
+    let (distances, timestamps) = distance.distance_grid(step, length)?;
+    let resampled = self.columns
+                        .iter()
+                        .map(|column| {
+                          self.to_channel(column)
+                              .resample_at(&timestamps, Interpolation::Linear)
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+    Ok(Self { distances: Some(distances),
+              ..Self::from_resampled(timestamps, resampled) })
+  }
+
+  /// Distances of the rows in m, if the frame is indexed by distance.
+  pub fn distances(&self) -> Option<&Vec<f64>> {
+    self.distances.as_ref()
+  }
+
+  /// Number of rows, i.e. timestamps.
+  pub fn len(&self) -> usize {
+    self.timestamps.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// List of the names of all columns, in order.
+  pub fn column_names(&self) -> Vec<String> {
+    self.columns
+        .iter()
+        .map(|column| column.name.clone())
+        .collect()
+  }
+
+  /// Request a column by name.
+  pub fn column(&self, name: &str) -> Option<&Column> {
+    self.columns.iter().find(|column| column.name == name)
+  }
+
+  /// Request a column by name as a `Channel` with the timestamps of the
+  /// frame.
+  pub fn channel(&self, name: &str) -> Option<Channel> {
+    self.column(name).map(|column| self.to_channel(column))
+  }
+
+  fn to_channel(&self, column: &Column) -> Channel {
+    Channel::new(column.name.clone(),
+                 column.unit.clone(),
+                 ChannelData::new(self.timestamps.clone(),
+                                  column.samples.clone()))
+  }
+
+  /// Request the row with index `idx`.
+  pub fn row(&self, idx: usize) -> Option<Row<'_>> {
+    if idx < self.len() {
+      Some(Row { frame: self, idx })
+    } else {
+      None
+    }
+  }
+
+  /// Iterate over all rows, in order of their timestamps.
+  pub fn rows(&self) -> impl Iterator<Item = Row<'_>> {
+    (0..self.len()).map(move |idx| Row { frame: self, idx })
+  }
+}
+
+
+/// A row of a `RunFrame`, i.e. the samples of all columns at one timestamp.
+#[derive(Debug, Clone, Copy)]
+pub struct Row<'a> {
+  frame: &'a RunFrame,
+  idx:   usize,
+}
+
+impl<'a> Row<'a> {
+  /// Index of the row in the frame.
+  pub fn idx(&self) -> usize {
+    self.idx
+  }
+
+  pub fn timestamp(&self) -> f64 {
+    self.frame.timestamps[self.idx]
+  }
+
+  /// Distance of the row in m, if the frame is indexed by distance.
+  pub fn distance(&self) -> Option<f64> {
+    self.frame
+        .distances
+        .as_ref()
+        .map(|distances| distances[self.idx])
+  }
+
+  /// Sample of the column named `name`.
+  pub fn get(&self, name: &str) -> Option<f64> {
+    self.frame
+        .column(name)
+        .map(|column| column.samples[self.idx])
+  }
+
+  /// Samples of all columns, in order of the columns.
+  pub fn values(&self) -> impl Iterator<Item = f64> + 'a {
+    let idx = self.idx;
+    self.frame
+        .columns
+        .iter()
+        .map(move |column| column.samples[idx])
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ChannelGroup, Lap, LapInfo, MemorySource, Run};
+  use pretty_assertions::assert_eq;
+
+
+  fn channel(name: &str, timestamps: Vec<f64>, samples: Vec<f64>) -> Channel {
+    Channel::new(name.to_string(),
+                 "unit".to_string(),
+                 ChannelData::new(timestamps, samples))
+  }
+
+  #[test]
+  fn frame_test() {
+    let fast = channel("fast",
+                       (0..=8).map(|i| i as f64 / 8.0).collect(),
+                       (0..=8).map(|i| i as f64).collect());
+    let slow = channel("slow", vec![0.25, 0.5, 0.75], vec![0.0, 4.0, 8.0]);
+
+    // fixed rates cover the range both channels have data for
+    let frame = RunFrame::new(&[fast.clone(), slow.clone()],
+                              TimeBase::Rate(8.0),
+                              Interpolation::Linear).unwrap();
+    assert_eq!(vec!["fast".to_string(), "slow".to_string()],
+               frame.column_names());
+    assert_eq!(&vec![0.25, 0.375, 0.5, 0.625, 0.75], frame.timestamps());
+    let row = frame.row(1).unwrap();
+    assert_eq!(0.375, row.timestamp());
+    assert_eq!(Some(3.0), row.get("fast"));
+    assert_eq!(Some(2.0), row.get("slow"));
+    assert_eq!(None, row.get("medium"));
+    assert_eq!(None, frame.row(5).map(|row| row.idx()));
+
+    // a reference channel keeps its timestamps; faster channels are
+    // anti-aliased, which leaves a ramp as it is
+    let frame = RunFrame::new(&[fast.clone(), slow.clone()],
+                              TimeBase::Reference("slow".to_string()),
+                              Interpolation::Linear).unwrap();
+    assert_eq!(vec![vec![0.25, 2.0, 0.0],
+                    vec![0.5, 4.0, 4.0],
+                    vec![0.75, 6.0, 8.0]],
+               frame.rows()
+                    .map(|row| {
+                      std::iter::once(row.timestamp()).chain(row.values())
+                                                      .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>());
+    assert_eq!(slow, frame.channel("slow").unwrap());
+
+    let medium = TimeBase::Reference("medium".to_string());
+    assert_eq!(true,
+               matches!(RunFrame::new(&[fast.clone(), slow.clone()],
+                                      medium,
+                                      Interpolation::Linear),
+                        Err(Error::MissingChannel { .. })));
+    assert_eq!(true,
+               matches!(RunFrame::new(&[fast.clone(),
+                                        channel("late",
+                                                vec![2.0, 3.0],
+                                                vec![0.0, 0.0])],
+                                      TimeBase::Rate(5.0),
+                                      Interpolation::Linear),
+                        Err(Error::DisjointChannels { .. })));
+    assert_eq!(true,
+               matches!(RunFrame::new(&[],
+                                      TimeBase::MaxFrequency,
+                                      Interpolation::Linear),
+                        Err(Error::InvalidArgument { name: "channels",
+                                                     .. })));
+  }
+
+  #[test]
+  fn lap_frame_test() {
+    let lap =
+      Lap::new(LapInfo::new(0, 0.0, 1.0),
+               vec![channel("fast",
+                            (0..=100).map(|i| i as f64 * 0.01).collect(),
+                            vec![1.0; 101]),
+                    channel("slow",
+                            (0..=10).map(|i| i as f64 * 0.1).collect(),
+                            vec![2.0; 11])]);
+    let frame = lap.to_frame(TimeBase::MaxFrequency).unwrap();
+    assert_eq!(101, frame.len());
+    assert_eq!(true, frame.rows().all(|row| row.get("slow") == Some(2.0)));
+
+    let mut source = MemorySource::new(vec![LapInfo::new(0, 0.0, 1.0)]);
+    for channel in lap.data() {
+      source.add_channel(ChannelGroup::Regular, channel.clone());
+    }
+    let run = Run::from_source(source).unwrap();
+    let frame = run.frame(&["slow"], None, TimeBase::Rate(20.0)).unwrap();
+    assert_eq!(vec!["slow".to_string()], frame.column_names());
+    assert_eq!(21, frame.len());
+    assert_eq!(true,
+               matches!(run.frame(&["medium"], None, TimeBase::MaxFrequency),
+                        Err(Error::ChannelNotFound { .. })));
+  }
+}
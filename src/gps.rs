@@ -0,0 +1,238 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Positions on the track, from the ECEF positions among the GPS raw
+//! channels, and gates across the track to time when they are crossed.
+
+use super::{Channel,
+            Interpolation,
+            Lap,
+            Result,
+            RunFrame,
+            TimeBase};
+use getset::CopyGetters;
+
+
+/// Names of the GPS raw channels holding the position of the vehicle in
+/// earth-centered, earth-fixed coordinates.
+pub(crate) const ECEF_POSITIONS: [&str; 3] =
+  ["ECEF position_X", "ECEF position_Y", "ECEF position_Z"];
+
+/// Semi-major axis of the WGS 84 ellipsoid in m.
+const WGS84_A: f64 = 6_378_137.0;
+/// Flattening of the WGS 84 ellipsoid.
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+/// Squared eccentricity of the WGS 84 ellipsoid.
+const WGS84_E2: f64 = WGS84_F * (2.0 - WGS84_F);
+
+
+/// A point on the surface of the earth, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct GeoPoint {
+  latitude:  f64,
+  longitude: f64,
+}
+
+impl GeoPoint {
+  pub fn new(latitude: f64, longitude: f64) -> Self {
+    Self { latitude,
+           longitude }
+  }
+
+  /// Earth-centered, earth-fixed coordinates of the point in m.
+  pub(crate) fn to_ecef(self) -> [f64; 3] {
+    let (lat, lon) = (self.latitude.to_radians(), self.longitude.to_radians());
+    let n = WGS84_A / (1.0 - WGS84_E2 * lat.sin().powi(2)).sqrt();
+    [n * lat.cos() * lon.cos(),
+     n * lat.cos() * lon.sin(),
+     n * (1.0 - WGS84_E2) * lat.sin()]
+  }
+}
+
+
+/// A line across the track between two points, e.g. the start/finish line or
+/// the end of a sector. `left` and `right` are the ends of the line as seen
+/// in the direction of travel. It is crossed whenever the path of the
+/// vehicle intersects the line in that direction, so spinning or reversing
+/// over the line, e.g. in a pit box next to it, doesn't count.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct Gate {
+  left:  GeoPoint,
+  right: GeoPoint,
+}
+
+impl Gate {
+  pub fn new(left: GeoPoint, right: GeoPoint) -> Self {
+    Self { left, right }
+  }
+
+  /// Times at which the vehicle crosses the gate along `track` in the
+  /// direction of travel, i.e. with `left` on its left, in order.
+  pub(crate) fn crossings(&self, track: &Track) -> Vec<f64> {
+    // the gate and the track are projected onto the plane tangent to the
+    // earth at the center of the gate, with east and north as axes
+    let (left, right) = (self.left, self.right);
+    let center = GeoPoint::new((left.latitude + right.latitude) / 2.0,
+                               (left.longitude + right.longitude) / 2.0);
+    let (lat, lon) =
+      (center.latitude.to_radians(), center.longitude.to_radians());
+    let origin = center.to_ecef();
+    let project = |point: &[f64; 3]| {
+      let offset = [point[0] - origin[0],
+                    point[1] - origin[1],
+                    point[2] - origin[2]];
+      let (east, north, _) = ecef_to_enu(offset, lat, lon);
+      (east, north)
+    };
+
+    let (left, right) = (project(&left.to_ecef()), project(&right.to_ecef()));
+    let gate = (right.0 - left.0, right.1 - left.1);
+    let points = track.positions.iter().map(project).collect::<Vec<_>>();
+
+    let mut crossings = Vec::new();
+    for i in 1..points.len() {
+      let (from, to) = (points[i - 1], points[i]);
+      let path = (to.0 - from.0, to.1 - from.1);
+      // positive for paths crossing from right to left of the direction of
+      // travel, i.e. backwards, and 0 for paths parallel to the gate
+      let denominator = cross(path, gate);
+      if denominator >= 0.0 {
+        continue;
+      }
+      let offset = (left.0 - from.0, left.1 - from.1);
+      // fractions along the path and the gate at which they intersect; the
+      // end of the path is left out, so a crossing exactly at a sample isn't
+      // counted twice
+      let (along_path, along_gate) =
+        (cross(offset, gate) / denominator, cross(offset, path) / denominator);
+      if (0.0..1.0).contains(&along_path) && (0.0..=1.0).contains(&along_gate)
+      {
+        let (t0, t1) = (track.timestamps[i - 1], track.timestamps[i]);
+        crossings.push(t0 + along_path * (t1 - t0));
+      }
+    }
+    crossings
+  }
+}
+
+
+/// Path of the vehicle, as ECEF positions over time.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Track {
+  timestamps: Vec<f64>,
+  positions:  Vec<[f64; 3]>,
+}
+
+impl Track {
+  /// Reads the path of the vehicle from the ECEF position channels of a lap,
+  /// which are among the GPS raw channels, see `Run::lap_in`.
+  pub(crate) fn of(lap: &Lap) -> Result<Self> {
+    let channels = ECEF_POSITIONS.iter()
+                                 .map(|name| lap.required(name).cloned())
+                                 .collect::<Result<Vec<_>>>()?;
+    Self::new(&channels)
+  }
+
+  /// Reads the path of the vehicle from the ECEF position `channels`, in the
+  /// order of `ECEF_POSITIONS`.
+  pub(crate) fn new(channels: &[Channel]) -> Result<Self> {
+    let reference = TimeBase::Reference(ECEF_POSITIONS[0].to_string());
+    let frame = RunFrame::new(channels, reference, Interpolation::Linear)?;
+    let positions = frame.rows()
+                         .map(|row| {
+                           let mut values = row.values();
+                           let mut next = || values.next().unwrap();
+                           [next(), next(), next()]
+                         })
+                         .collect();
+    Ok(Self { timestamps: frame.timestamps().clone(),
+              positions })
+  }
+}
+
+
+/// Converts ECEF coordinates to latitude, longitude (both in radians) and
+/// height above the WGS84 ellipsoid.
+#[cfg(feature = "native")]
+pub(crate) fn ecef_to_geodetic([x, y, z]: [f64; 3]) -> (f64, f64, f64) {
+  let longitude = y.atan2(x);
+  let p = x.hypot(y);
+  let mut latitude = z.atan2(p * (1.0 - WGS84_E2));
+  let mut height = 0.0;
+  for _ in 0..5 {
+    let n = WGS84_A / (1.0 - WGS84_E2 * latitude.sin().powi(2)).sqrt();
+    height = p / latitude.cos() - n;
+    latitude = z.atan2(p * (1.0 - WGS84_E2 * n / (n + height)));
+  }
+  (latitude, longitude, height)
+}
+
+/// Rotates an ECEF vector into the local east, north, up frame.
+pub(crate) fn ecef_to_enu([x, y, z]: [f64; 3],
+                          latitude: f64,
+                          longitude: f64)
+                          -> (f64, f64, f64) {
+  let (sin_lat, cos_lat) = latitude.sin_cos();
+  let (sin_lon, cos_lon) = longitude.sin_cos();
+  (-sin_lon * x + cos_lon * y,
+   -sin_lat * cos_lon * x - sin_lat * sin_lon * y + cos_lat * z,
+   cos_lat * cos_lon * x + cos_lat * sin_lon * y + sin_lat * z)
+}
+
+fn cross(a: (f64, f64), b: (f64, f64)) -> f64 {
+  a.0 * b.1 - a.1 * b.0
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn ecef_test() {
+    let [x, y, z] = GeoPoint::new(0.0, 0.0).to_ecef();
+    assert_eq!((WGS84_A, 0.0, 0.0), (x, y, z));
+    let [x, y, z] = GeoPoint::new(90.0, 0.0).to_ecef();
+    assert_eq!(true, x.abs() < 1e-6 && y.abs() < 1e-6);
+    assert_eq!(true, (z - 6_356_752.314).abs() < 1e-3);
+  }
+
+  #[test]
+  fn crossings_test() {
+    // driving east along a parallel at one sample per second, and back
+    let longitudes = (0..20).chain((0..20).rev())
+                            .map(|i| 8.0 + i as f64 * 1e-4)
+                            .collect::<Vec<_>>();
+    let track =
+      Track { timestamps: (0..40).map(f64::from).collect(),
+              positions:  longitudes.iter()
+                                    .map(|&lon| {
+                                      GeoPoint::new(50.0, lon).to_ecef()
+                                    })
+                                    .collect(), };
+
+    let gate = Gate::new(GeoPoint::new(50.001, 8.000_55),
+                         GeoPoint::new(49.999, 8.000_55));
+    let crossings = gate.crossings(&track);
+    assert_eq!(1, crossings.len());
+    assert_eq!(true, (crossings[0] - 5.5).abs() < 1e-3);
+
+    // the same line with its ends swapped faces west, so only the way back
+    // counts
+    let reversed = Gate::new(gate.right(), gate.left());
+    let crossings = reversed.crossings(&track);
+    assert_eq!(1, crossings.len());
+    assert_eq!(true, (crossings[0] - 33.5).abs() < 1e-3);
+
+    let missed =
+      Gate::new(GeoPoint::new(50.001, 7.9), GeoPoint::new(49.999, 7.9));
+    assert_eq!(0, missed.crossings(&track).len());
+  }
+}
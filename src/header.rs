@@ -0,0 +1,92 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+use super::{ChannelGroup, DataSource, Result};
+use chrono::NaiveDateTime;
+use getset::{CopyGetters, Getters};
+use std::path::PathBuf;
+
+
+/// Session metadata and counts of a run, as returned by `Run::peek`.
+///
+/// Reading these is cheap compared to loading a `Run`, since neither lap
+/// information nor channel names and units are read, which makes it a good
+/// fit for indexing lots of files by when and where they were recorded.
+#[derive(Debug, Clone, PartialEq, CopyGetters, Getters)]
+pub struct RunHeader {
+  #[getset(get = "pub")]
+  path:                   PathBuf,
+  #[getset(get = "pub")]
+  championship:           String,
+  #[getset(get = "pub")]
+  track:                  String,
+  #[getset(get = "pub")]
+  venue_type:             String,
+  #[getset(get = "pub")]
+  vehicle:                String,
+  #[getset(get = "pub")]
+  racer:                  String,
+  #[getset(get_copy = "pub")]
+  datetime:               NaiveDateTime,
+  #[getset(get_copy = "pub")]
+  number_of_laps:         usize,
+  /// Number of regular and GPS channels, like `Run::number_of_channels`.
+  #[getset(get_copy = "pub")]
+  number_of_channels:     usize,
+  #[getset(get_copy = "pub")]
+  channels_count:         usize,
+  #[getset(get_copy = "pub")]
+  gps_channels_count:     usize,
+  #[getset(get_copy = "pub")]
+  gps_raw_channels_count: usize,
+}
+
+impl RunHeader {
+  /// Reads the header from a data source.
+  pub(crate) fn read(source: &dyn DataSource) -> Result<Self> {
+    let channels_count = source.channels_count(ChannelGroup::Regular)?;
+    let gps_channels_count = source.channels_count(ChannelGroup::Gps)?;
+    Ok(Self { path: source.path().to_owned(),
+              championship: source.championship()?,
+              track: source.track()?,
+              venue_type: source.venue_type()?,
+              vehicle: source.vehicle()?,
+              racer: source.racer()?,
+              datetime: source.datetime()?,
+              number_of_laps: source.laps_count()?,
+              number_of_channels: channels_count + gps_channels_count,
+              channels_count,
+              gps_channels_count,
+              gps_raw_channels_count:
+                source.channels_count(ChannelGroup::GpsRaw)? })
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::{super::MemorySource, *};
+  use crate::{Channel, ChannelData, LapInfo};
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn header_test() {
+    let mut source = MemorySource::new(vec![LapInfo::new(0, 0.0, 1.0)]);
+    source.set_racer("072".to_string())
+          .add_channel(ChannelGroup::Gps,
+                       Channel::new("GPS Speed".to_string(),
+                                    "m/s".to_string(),
+                                    ChannelData::new(vec![0.0], vec![40.0])));
+
+    let header = RunHeader::read(&source).unwrap();
+    assert_eq!("072", header.racer());
+    assert_eq!(1, header.number_of_laps());
+    assert_eq!(1, header.number_of_channels());
+    assert_eq!(0, header.channels_count());
+    assert_eq!(1, header.gps_channels_count());
+  }
+}
@@ -4,8 +4,29 @@
 //   Florian Eich <florian@bmc-labs.com>
 //   Jonas Reitemeyer <alumni@bmc-labs.com>
 
-use super::Channel;
+use super::{Channel,
+            ChannelData,
+            Error,
+            Event,
+            EventDetector,
+            Expression,
+            Interpolation,
+            LapComparison,
+            LapKind,
+            MathChannel,
+            Period,
+            Result,
+            RunFrame,
+            Sectors,
+            TimeBase};
 use getset::{CopyGetters, Getters};
+use once_cell::sync::OnceCell;
+use std::path::PathBuf;
+
+
+/// Names of the wheel speed channels `Lap::distance` falls back to.
+const WHEEL_SPEEDS: [&str; 4] =
+  ["vWheelFL", "vWheelFR", "vWheelRL", "vWheelRR"];
 
 
 /// Hold all channels of a lap.
@@ -15,17 +36,65 @@ use getset::{CopyGetters, Getters};
 /// number (`.number()`), which is the counter and which is what is used in
 /// common parlance and starts at 1. In other words, `.number()` will always
 /// return `.idx() + 1`.
-#[derive(Debug, PartialEq, CopyGetters, Getters)]
+#[derive(Debug, CopyGetters, Getters)]
 #[getset(get = "pub")]
 pub struct Lap {
   info: LapInfo,
   data: Vec<Channel>,
+  /// Path of the file the lap was read from, empty for laps not read from a
+  /// file.
+  path: PathBuf,
+  /// Math channels with the channel computed from them or the reason it
+  /// can't be computed, once requested.
+  #[getset(skip)]
+  math: Vec<(MathChannel, OnceCell<std::result::Result<Channel, String>>)>,
+}
+
+impl PartialEq for Lap {
+  fn eq(&self, other: &Self) -> bool {
+    self.info == other.info
+    && self.data == other.data
+    && self.path == other.path
+    && self.math.len() == other.math.len()
+    && self.math
+           .iter()
+           .zip(other.math.iter())
+           .all(|((lhs, _), (rhs, _))| lhs == rhs)
+  }
 }
 
 impl Lap {
   /// Construct a new `Lap` from a `LapInfo` object and a set of channel data.
   pub fn new(info: LapInfo, data: Vec<Channel>) -> Self {
-    Self { info, data }
+    Self { info,
+           data,
+           path: PathBuf::new(),
+           math: Vec::new() }
+  }
+
+  /// This lap as read from the file at `path`, which errors refer to.
+  pub(crate) fn with_path(mut self, path: PathBuf) -> Self {
+    self.path = path;
+    self
+  }
+
+  /// This lap with `info`, e.g. carrying the kind of the lap.
+  pub(crate) fn with_info(mut self, info: LapInfo) -> Self {
+    self.info = info;
+    self
+  }
+
+  /// This lap with `math_channels`, which are computed from the channels of
+  /// the lap when first requested via `channel`. Fails if the math channels
+  /// reference each other in a cycle.
+  pub fn with_math_channels(mut self,
+                            math_channels: Vec<MathChannel>)
+                            -> Result<Self> {
+    crate::math_channel::check_references(&math_channels)?;
+    self.math = math_channels.into_iter()
+                             .map(|math| (math, OnceCell::new()))
+                             .collect();
+    Ok(self)
   }
 
   /// Index of the lap in the set, i.e. starting at 0.
@@ -49,17 +118,109 @@ impl Lap {
     self.info.time()
   }
 
-  /// List of the names of all channels in this `Lap`.
+  /// Kind of the lap, if it was read from classified laps, see
+  /// `Run::flying_laps`.
+  pub fn kind(&self) -> Option<LapKind> {
+    self.info.kind()
+  }
+
+  /// Whether the lap was marked as neutralised, see
+  /// `LapInfo::with_neutralised_by`.
+  pub fn neutralised(&self) -> bool {
+    self.info.neutralised()
+  }
+
+  /// List of the names of all channels in this `Lap`, followed by those of
+  /// its math channels.
   pub fn channel_names(&self) -> Vec<String> {
-    self.data
-        .iter()
-        .map(|channel| channel.name().clone())
-        .collect()
+    let mut names = self.data
+                        .iter()
+                        .map(|channel| channel.name().clone())
+                        .collect::<Vec<_>>();
+    for (math, _) in &self.math {
+      if !names.contains(math.name()) {
+        names.push(math.name().clone());
+      }
+    }
+    names
   }
 
-  /// Request a channel by name.
+  /// Request a channel by name. Math channels are computed when first
+  /// requested; a math channel which can't be computed yields `None`, use
+  /// `math_channel` to find out why.
   pub fn channel(&self, name: &str) -> Option<&Channel> {
-    self.data.iter().find(|c| c.name() == name)
+    self.required(name).ok()
+  }
+
+  /// Request a math channel by name, computed from the channels of this lap
+  /// when first requested. Fails with `Error::ChannelNotFound` if there is
+  /// no such math channel and with `Error::MathChannel` if it can't be
+  /// computed, e.g. because a channel it references is missing.
+  pub fn math_channel(&self, name: &str) -> Result<&Channel> {
+    let (math, cache) =
+      self.math
+          .iter()
+          .find(|(math, _)| math.name() == name)
+          .ok_or_else(|| {
+            Error::ChannelNotFound { path: self.path.clone(),
+                                     name: name.to_string(), }
+          })?;
+    cache.get_or_init(|| {
+           self.inputs(math.expression())
+               .and_then(|inputs| math.compute(&inputs))
+               .map_err(|error| error.to_string())
+         })
+         .as_ref()
+         .map_err(|reason| Error::MathChannel { path:   self.path.clone(),
+                                                name:   name.to_string(),
+                                                reason: reason.clone(), })
+  }
+
+  /// The channel named `name`, failing with `Error::ChannelNotFound` if there
+  /// is no such channel and like `math_channel` for math channels.
+  pub(crate) fn required(&self, name: &str) -> Result<&Channel> {
+    match self.data.iter().find(|channel| channel.name() == name) {
+      Some(channel) => Ok(channel),
+      None => self.math_channel(name),
+    }
+  }
+
+  /// Like `required`, but with `None` if there is no such channel, so only
+  /// math channels which can't be computed fail.
+  pub(crate) fn optional(&self, name: &str) -> Result<Option<&Channel>> {
+    match self.required(name) {
+      Ok(channel) => Ok(Some(channel)),
+      Err(Error::ChannelNotFound { .. }) => Ok(None),
+      Err(error) => Err(error),
+    }
+  }
+
+  /// Computes `expression` from the channels of this lap, including its math
+  /// channels, see `Expression::evaluate`.
+  pub fn evaluate(&self, expression: &Expression) -> Result<Channel> {
+    expression.evaluate(&self.inputs(expression)?)
+  }
+
+  /// The channels `expression` references, failing if one is missing.
+  fn inputs(&self, expression: &Expression) -> Result<Vec<Channel>> {
+    expression.channels()
+              .iter()
+              .map(|name| self.required(name).cloned())
+              .collect()
+  }
+
+  /// Finds the events `detector` looks for in the channel named `name`, see
+  /// `Channel::events`, marked as occurring in this lap. Fails if there is no
+  /// such channel.
+  pub fn events(&self,
+                name: &str,
+                detector: &EventDetector)
+                -> Result<Vec<Event>> {
+    let channel = self.required(name)?;
+    Ok(channel.events(detector)
+              .into_iter()
+              .map(|event| event.with_lap(self.idx()))
+              .collect())
   }
 
   /// Returns the highest frequency of any channel in this lap.
@@ -73,9 +234,84 @@ impl Lap {
         .unwrap()
         .frequency()
   }
+
+  /// Resamples all channels of this lap onto `time_base` using linear
+  /// interpolation, see `RunFrame::new`.
+  pub fn to_frame(&self, time_base: TimeBase) -> Result<RunFrame> {
+    RunFrame::new(&self.data, time_base, Interpolation::Linear)
+      .map_err(|error| error.in_file(&self.path))
+  }
+
+  /// Computes the distance travelled in this lap from `GPS Speed` or, if the
+  /// lap has no such channel, from the mean of the wheel speeds (`vWheelFL`
+  /// etc.), see `Channel::distance`. Fails if there are no such channels.
+  pub fn distance(&self) -> Result<Channel> {
+    if self.channel("GPS Speed").is_some() {
+      return self.distance_from(&["GPS Speed"]);
+    }
+    let wheel_speeds = WHEEL_SPEEDS.iter()
+                                   .copied()
+                                   .filter(|name| self.channel(name).is_some())
+                                   .collect::<Vec<_>>();
+    if wheel_speeds.is_empty() {
+      return Err(Error::ChannelNotFound { path: self.path.clone(),
+                                          name: "GPS Speed".to_string(), });
+    }
+    self.distance_from(&wheel_speeds)
+  }
+
+  /// Computes the distance travelled in this lap from the mean of the speed
+  /// channels named in `speeds`, which must share a unit, see
+  /// `Channel::distance`.
+  pub fn distance_from(&self, speeds: &[&str]) -> Result<Channel> {
+    let speeds = speeds.iter()
+                       .map(|name| self.required(name).cloned())
+                       .collect::<Result<Vec<_>>>()?;
+    if speeds.len() == 1 {
+      return speeds[0].distance();
+    }
+
+    let frame =
+      RunFrame::new(&speeds, TimeBase::MaxFrequency, Interpolation::Linear)?;
+    let mean = frame.rows()
+                    .map(|row| row.values().sum::<f64>() / speeds.len() as f64)
+                    .collect();
+    Channel::new("Speed".to_string(),
+                 speeds[0].unit().clone(),
+                 ChannelData::new(frame.timestamps().clone(), mean)).distance()
+  }
+
+  /// Compares this lap against `reference` over distance, with a point
+  /// every `step` m, see `LapComparison`.
+  pub fn compare_to<'a>(&'a self,
+                        reference: &'a Lap,
+                        step: f64)
+                        -> Result<LapComparison<'a>> {
+    LapComparison::new(self, reference, step)
+  }
+
+  /// Times of the sectors of this lap in s, `None` for sectors which couldn't
+  /// be timed, e.g. because a gate wasn't crossed. See `SectorTable::new` for
+  /// when this fails.
+  pub fn sector_times(&self, sectors: &Sectors) -> Result<Vec<Option<f64>>> {
+    sectors.times_of(self)
+  }
+
+  /// Resamples all channels of this lap onto a distance axis with a row every
+  /// `step` m, using the distance computed by `distance`. See
+  /// `Channel::over_distance`, also for `length`.
+  pub fn to_distance_frame(&self,
+                           step: f64,
+                           length: Option<f64>)
+                           -> Result<RunFrame> {
+    self.to_frame(TimeBase::MaxFrequency)?
+        .over_distance(&self.distance()?, step, length)
+  }
 }
 
-/// Stores the start time within the recording and the time of a lap.
+/// Stores the start time within the recording and the time of a lap, and its
+/// kind as returned by `Run::classify_laps` and whether it was neutralised,
+/// see `with_neutralised_by`.
 ///
 /// Please not the difference between the lap index (`.idx()`), which starts at
 /// 0 and indicates the lap position within the collection of laps, and the lap
@@ -85,15 +321,46 @@ impl Lap {
 #[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
 #[getset(get_copy = "pub")]
 pub struct LapInfo {
-  idx:   usize,
-  start: f64,
-  time:  f64,
+  idx:         usize,
+  start:       f64,
+  time:        f64,
+  kind:        Option<LapKind>,
+  neutralised: bool,
 }
 
 impl LapInfo {
   /// Construct a new `LapInfo` from an index, a starting time and a lap time.
   pub fn new(idx: usize, start: f64, time: f64) -> Self {
-    Self { idx, start, time }
+    Self { idx,
+           start,
+           time,
+           kind: None,
+           neutralised: false }
+  }
+
+  /// This `LapInfo` with the lap classified as `kind`.
+  pub fn with_kind(mut self, kind: LapKind) -> Self {
+    self.kind = Some(kind);
+    self
+  }
+
+  /// This `LapInfo` with the lap marked as neutralised, e.g. by a full
+  /// course yellow, or not.
+  pub fn with_neutralised(mut self, neutralised: bool) -> Self {
+    self.neutralised = neutralised;
+    self
+  }
+
+  /// This `LapInfo` marked as neutralised if the lap overlaps any of the
+  /// `periods` which neutralise the race, e.g. as found by
+  /// `Run::detect_periods`.
+  pub fn with_neutralised_by(self, periods: &[Period]) -> Self {
+    let (start, end) = (self.start, self.start + self.time);
+    let neutralised = periods.iter().any(|period| {
+                                      period.kind().neutralises()
+                                      && period.overlaps(start, end)
+                                    });
+    self.with_neutralised(neutralised)
   }
 
   /// Lap number as used in common motorsport parlance, starting at 1.
@@ -105,7 +372,7 @@ impl LapInfo {
 
 #[cfg(test)]
 mod tests {
-  use super::{super::Run, *};
+  use super::{super::{Run, Trigger}, *};
   use pretty_assertions::{assert_eq, assert_ne};
   use std::path::Path;
 
@@ -241,6 +508,43 @@ mod tests {
     assert_eq!(0.0, lap.max_frequency());
   }
 
+  #[test]
+  fn lap_distance_test() {
+    let wheel_speed = |name: &str, speed: f64| {
+      Channel::new(name.to_string(),
+                   "km/h".to_string(),
+                   ChannelData::new((0..=10).map(|i| i as f64 * 0.1)
+                                            .collect(),
+                                    vec![speed; 11]))
+    };
+    let lap = Lap::new(LapInfo::new(0, 0.0, 1.0),
+                       vec![wheel_speed("vWheelFL", 36.0),
+                            wheel_speed("vWheelFR", 72.0)]);
+
+    // without GPS Speed, the mean of the wheel speeds, 15 m/s, is used
+    let distance = lap.distance().unwrap();
+    assert_eq!(true, (distance.data().samples()[10] - 15.0).abs() < 1e-9);
+
+    let frame = lap.to_distance_frame(5.0, None).unwrap();
+    assert_eq!(Some(&vec![0.0, 5.0, 10.0, 15.0]), frame.distances());
+    assert_eq!(Some(10.0), frame.row(2).unwrap().distance());
+    assert_eq!(Some(36.0), frame.row(2).unwrap().get("vWheelFL"));
+
+    let empty = Lap::new(LapInfo::new(0, 0.0, 1.0), Vec::new());
+    assert_eq!(true,
+               matches!(empty.distance(), Err(Error::ChannelNotFound { .. })));
+
+    // lookups of laps read from a file name the file
+    let lap = lap.with_path(PathBuf::from("run.xrk"));
+    let detector = EventDetector::new(Trigger::Above(1.0));
+    assert_eq!(Some(Path::new("run.xrk")),
+               lap.events("GPS Speed", &detector).unwrap_err().path());
+    assert_eq!(Some(Path::new("run.xrk")),
+               lap.to_frame(TimeBase::Reference("GPS Speed".to_string()))
+                  .unwrap_err()
+                  .path());
+  }
+
   #[test]
   fn lap_info_test() {
     let lap_info = LapInfo::new(2, 145.156, 133.135);
@@ -248,5 +552,9 @@ mod tests {
     assert_eq!(3, lap_info.number());
     assert_eq!(145.156, lap_info.start());
     assert_eq!(133.135, lap_info.time());
+    assert_eq!(None, lap_info.kind());
+    assert_eq!(Some(LapKind::Out), lap_info.with_kind(LapKind::Out).kind());
+    assert_eq!(false, lap_info.neutralised());
+    assert_eq!(true, lap_info.with_neutralised(true).neutralised());
   }
 }
@@ -21,13 +21,62 @@
 //! This module wraps the original library and provides a safe, Rust-idiomatic
 //! interface to its functionality. Aside from the raw API provided by the
 //! library, higher level functions for retrieving data are provided.
+//!
+//! With the `native` feature enabled and the default `aim` feature disabled,
+//! files are read by a pure Rust implementation instead, which requires
+//! neither the AiM library nor any `unsafe` code.
+
+#[cfg(not(any(feature = "aim", feature = "native")))]
+compile_error!("at least one of the features `aim` and `native` is required");
 
+#[cfg(feature = "aim")]
 mod bindings;
 mod channel;
+mod classification;
+mod comparison;
+mod error;
+mod expression;
+mod frame;
+mod gps;
+mod header;
 mod lap;
+mod math_channel;
+mod period;
+mod registry;
 mod run;
+mod sector;
+mod source;
+mod staging;
+mod statistics;
+#[cfg(feature = "aim")]
 mod util;
 
-pub use channel::{Channel, ChannelData};
+pub use channel::{Band,
+                  Channel,
+                  ChannelData,
+                  Event,
+                  EventDetector,
+                  Filter,
+                  Interpolation,
+                  Trigger};
+pub use classification::{Classifier, LapKind};
+pub use comparison::{LapComparison, Overlay};
+pub use error::{Error, Result};
+pub use expression::Expression;
+pub use frame::{Column, Row, RunFrame, TimeBase};
+pub use gps::{Gate, GeoPoint};
+pub use header::RunHeader;
 pub use lap::{Lap, LapInfo};
+pub use math_channel::MathChannel;
+pub use period::{Period, PeriodDetector, PeriodKind};
+pub use registry::LoadedRun;
 pub use run::Run;
+pub use sector::{SectorRow, SectorTable, Sectors};
+#[cfg(feature = "aim")]
+pub use source::AimSource;
+#[cfg(feature = "native")]
+pub use source::NativeSource;
+#[cfg(feature = "sandbox")]
+pub use source::SandboxSource;
+pub use source::{ChannelGroup, ChannelSet, DataSource, MemorySource};
+pub use statistics::{Statistic, SummaryRow, SummaryTable};
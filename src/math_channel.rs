@@ -0,0 +1,298 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Named channels computed from an `Expression`, and files defining them, see
+//! `MathChannel::load`.
+
+use super::{Channel, ChannelData, Error, Expression, Result};
+use getset::Getters;
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+
+/// A channel computed from other channels by an `Expression`, e.g. the brake
+/// balance from `pBrakeF` and `pBrakeR`. Added to a `Run` via
+/// `Run::add_math_channels`, math channels are listed and requested like the
+/// channels recorded by the logger.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct MathChannel {
+  name:        String,
+  unit:        String,
+  expression:  Expression,
+  description: String,
+}
+
+impl MathChannel {
+  pub fn new(name: String, unit: String, expression: Expression) -> Self {
+    Self { name,
+           unit,
+           expression,
+           description: String::new() }
+  }
+
+  /// This math channel with `description`, e.g. what it is good for.
+  pub fn with_description(mut self, description: String) -> Self {
+    self.description = description;
+    self
+  }
+
+  /// Reads the math channels defined in the file at `path`, which is read as
+  /// TOML if its extension is .toml and as YAML if it is .yaml or .yml. Each
+  /// channel is defined by a `name`, an `expression` and optionally a `unit`
+  /// and a `description`, e.g.
+  ///
+  /// ```toml
+  /// [[channel]]
+  /// name = "Brake Balance"
+  /// unit = "%"
+  /// expression = "pBrakeF / (pBrakeF + pBrakeR) * 100"
+  /// description = "Share of the brake pressure on the front axle"
+  /// ```
+  ///
+  /// or, in YAML
+  ///
+  /// ```yaml
+  /// channel:
+  ///   - name: Brake Balance
+  ///     unit: "%"
+  ///     expression: pBrakeF / (pBrakeF + pBrakeR) * 100
+  /// ```
+  ///
+  /// Fails with `Error::InvalidPath` for any other extension, with
+  /// `Error::Io` if the file can't be read and with `Error::Unparsable` if it
+  /// isn't a valid definition file, including invalid expressions.
+  pub fn load(path: &Path) -> Result<Vec<Self>> {
+    let parse: fn(&str) -> Result<Vec<Self>> =
+      match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => Self::from_toml,
+        Some("yaml") | Some("yml") => Self::from_yaml,
+        _ => {
+          return Err(Error::InvalidPath { path:   path.to_owned(),
+                                          reason: "math channel files must \
+                                                   be .toml, .yaml or .yml", })
+        }
+      };
+    let text = fs::read_to_string(path).map_err(|source| {
+                                         Error::Io { path: path.to_owned(),
+                                                     source }
+                                       })?;
+    let definitions = parse(&text);
+    definitions.map_err(|error| {
+                 Error::Unparsable { path:   path.to_owned(),
+                                     reason: error.to_string(), }
+               })
+  }
+
+  /// Reads math channels defined in TOML, see `load`. Fails with
+  /// `Error::InvalidArgument` if `text` isn't a valid definition file.
+  pub fn from_toml(text: &str) -> Result<Vec<Self>> {
+    let file = toml::from_str::<File>(text).map_err(|error| {
+                                             invalid(error.to_string())
+                                           })?;
+    file.channels()
+  }
+
+  /// Reads math channels defined in YAML, see `load`. Fails with
+  /// `Error::InvalidArgument` if `text` isn't a valid definition file.
+  pub fn from_yaml(text: &str) -> Result<Vec<Self>> {
+    let file = serde_yaml::from_str::<File>(text).map_err(|error| {
+                                                   invalid(error.to_string())
+                                                 })?;
+    file.channels()
+  }
+
+  /// Computes this channel from `channels`, which must contain all channels
+  /// the expression references, see `Expression::evaluate`.
+  pub fn compute(&self, channels: &[Channel]) -> Result<Channel> {
+    let computed = self.expression.evaluate(channels)?;
+    Ok(Channel::new(self.name.clone(),
+                    self.unit.clone(),
+                    ChannelData::new(computed.data().timestamps().clone(),
+                                     computed.data().samples().clone())))
+  }
+}
+
+
+/// Fails if any of `channels` references itself, directly or via others, or
+/// if two of them have the same name.
+pub(crate) fn check_references(channels: &[MathChannel]) -> Result<()> {
+  for (i, channel) in channels.iter().enumerate() {
+    if channels[..i].iter().any(|other| other.name == channel.name) {
+      return Err(invalid(format!("math channel '{}' is defined twice",
+                                 channel.name)));
+    }
+  }
+
+  // depth first search from each channel, over the math channels only
+  fn visit<'a>(channels: &'a [MathChannel],
+               channel: &'a MathChannel,
+               path: &mut Vec<&'a str>)
+               -> Result<()> {
+    if path.contains(&channel.name.as_str()) {
+      path.push(&channel.name);
+      return Err(invalid(format!("math channel '{}' references itself via \
+                                  {}",
+                                 channel.name,
+                                 path.join(" -> "))));
+    }
+    path.push(&channel.name);
+    for name in channel.expression.channels() {
+      if let Some(input) = channels.iter().find(|c| c.name == name) {
+        visit(channels, input, path)?;
+      }
+    }
+    path.pop();
+    Ok(())
+  }
+  for channel in channels {
+    visit(channels, channel, &mut Vec::new())?;
+  }
+  Ok(())
+}
+
+fn invalid(reason: String) -> Error {
+  Error::InvalidArgument { name: "math channels",
+                           reason }
+}
+
+
+/// Layout of a math channel definition file, see `MathChannel::load`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+  #[serde(default)]
+  channel: Vec<Definition>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Definition {
+  name:        String,
+  #[serde(default)]
+  unit:        String,
+  expression:  String,
+  #[serde(default)]
+  description: String,
+}
+
+impl File {
+  fn channels(self) -> Result<Vec<MathChannel>> {
+    self.channel
+        .into_iter()
+        .map(|definition| {
+          let expression = Expression::parse(&definition.expression)
+                             .map_err(|error| {
+                               invalid(format!("math channel '{}': {}",
+                                               definition.name, error))
+                             })?;
+          Ok(MathChannel::new(definition.name, definition.unit, expression)
+               .with_description(definition.description))
+        })
+        .collect()
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  fn math(name: &str, expression: &str) -> MathChannel {
+    MathChannel::new(name.to_string(),
+                     String::new(),
+                     Expression::parse(expression).unwrap())
+  }
+
+  #[test]
+  fn load_test() {
+    let toml = r#"
+      [[channel]]
+      name = "Brake Balance"
+      unit = "%"
+      expression = "pBrakeF / (pBrakeF + pBrakeR) * 100"
+      description = "Share of the brake pressure on the front axle"
+
+      [[channel]]
+      name = "Full Throttle"
+      expression = "if(rThrottle > 95, 1, 0)"
+    "#;
+    let yaml = r#"
+      channel:
+        - name: Brake Balance
+          unit: "%"
+          expression: pBrakeF / (pBrakeF + pBrakeR) * 100
+          description: Share of the brake pressure on the front axle
+        - name: Full Throttle
+          expression: if(rThrottle > 95, 1, 0)
+    "#;
+    let channels = MathChannel::from_toml(toml).unwrap();
+    assert_eq!(channels, MathChannel::from_yaml(yaml).unwrap());
+    assert_eq!(2, channels.len());
+    assert_eq!("%", channels[0].unit());
+    assert_eq!("Share of the brake pressure on the front axle",
+               channels[0].description());
+    assert_eq!(vec!["rThrottle".to_string()],
+               channels[1].expression().channels());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("math.yml");
+    fs::write(&path, yaml).unwrap();
+    assert_eq!(channels, MathChannel::load(&path).unwrap());
+    fs::write(&path, toml).unwrap();
+    assert_eq!(true,
+               matches!(MathChannel::load(&path),
+                        Err(Error::Unparsable { .. })));
+    assert_eq!(true,
+               matches!(MathChannel::load(&dir.path().join("missing.toml")),
+                        Err(Error::Io { .. })));
+    assert_eq!(true,
+               matches!(MathChannel::load(&dir.path().join("math.txt")),
+                        Err(Error::InvalidPath { .. })));
+
+    for invalid in ["[[channel]]\nname = \"a\"",
+                    "[[channel]]\nname = \"a\"\nexpression = \"b +\"",
+                    "[[channel]]\nname = \"a\"\nexpression = \"b\"\nfoo = 1"]
+                   .iter()
+    {
+      assert_eq!(true,
+                 matches!(MathChannel::from_toml(invalid),
+                          Err(Error::InvalidArgument { .. })));
+    }
+  }
+
+  #[test]
+  fn compute_test() {
+    let channel = Channel::new("pBrakeF".to_string(),
+                               "bar".to_string(),
+                               ChannelData::new(vec![0.0, 1.0],
+                                                vec![10.0, 20.0]));
+    let double = MathChannel::new("Double".to_string(),
+                                  "bar".to_string(),
+                                  Expression::parse("2 * pBrakeF").unwrap());
+    assert_eq!(Channel::new("Double".to_string(),
+                            "bar".to_string(),
+                            ChannelData::new(vec![0.0, 1.0],
+                                             vec![20.0, 40.0])),
+               double.compute(&[channel]).unwrap());
+  }
+
+  #[test]
+  fn check_references_test() {
+    let channels = vec![math("a", "b + c"), math("b", "c")];
+    assert_eq!(true, check_references(&channels).is_ok());
+    for channels in [vec![math("a", "a")],
+                     vec![math("a", "b"), math("b", "c"), math("c", "a")],
+                     vec![math("a", "b"), math("a", "c")]].iter()
+    {
+      assert_eq!(true,
+                 matches!(check_references(channels),
+                          Err(Error::InvalidArgument { .. })));
+    }
+  }
+}
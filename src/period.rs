@@ -0,0 +1,249 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Periods of a run during which the vehicle doesn't race, e.g. under full
+//! course yellow, behind the safety car or with the pit limiter on.
+
+use super::{classification::{intervals, plateaus},
+            Channel};
+use getset::{CopyGetters, Getters, Setters};
+
+
+/// What kind of period a `Period` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeriodKind {
+  /// Full course yellow, i.e. the whole field at a fixed speed limit.
+  FullCourseYellow,
+  /// Safety car, i.e. the whole field at reduced pace.
+  SafetyCar,
+  /// Pit limiter on, e.g. in the pit lane.
+  PitLimiter,
+}
+
+impl PeriodKind {
+  /// Whether the race is neutralised during periods of this kind, which
+  /// makes lap times meaningless.
+  pub fn neutralises(self) -> bool {
+    matches!(self, Self::FullCourseYellow | Self::SafetyCar)
+  }
+}
+
+
+/// A time interval in run time, see `PeriodDetector`.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct Period {
+  kind:  PeriodKind,
+  start: f64,
+  end:   f64,
+}
+
+impl Period {
+  pub fn new(kind: PeriodKind, start: f64, end: f64) -> Self {
+    Self { kind, start, end }
+  }
+
+  /// Length of the period in s.
+  pub fn duration(&self) -> f64 {
+    self.end - self.start
+  }
+
+  /// Whether the period overlaps the interval from `start` to `end`.
+  pub fn overlaps(&self, start: f64, end: f64) -> bool {
+    self.start < end && start < self.end
+  }
+}
+
+
+/// Detects full course yellow, safety car and pit limiter periods from the
+/// switch channels and the speed of a run.
+///
+/// - full course yellow while the FCY switch is on, or while the speed is
+///   held at `fcy_speed_limit` for at least `min_duration`
+/// - pit limiter while the pit switch is on
+/// - safety car while the speed stays between 20 km/h and
+///   `safety_car_speed` for at least `min_safety_car`, outside of full course
+///   yellow and pit limiter periods
+///
+/// Switches are on while not 0. Channels which are missing are skipped.
+#[derive(Debug, Clone, PartialEq, CopyGetters, Getters, Setters)]
+pub struct PeriodDetector {
+  /// Name of the full course yellow switch channel.
+  #[getset(get = "pub", set = "pub")]
+  fcy_switch:       String,
+  /// Name of the pit switch channel.
+  #[getset(get = "pub", set = "pub")]
+  pit_switch:       String,
+  /// Name of the speed channel.
+  #[getset(get = "pub", set = "pub")]
+  speed:            String,
+  /// Speed limit under full course yellow in km/h, `None` to rely on the
+  /// switch only.
+  #[getset(get_copy = "pub", set = "pub")]
+  fcy_speed_limit:  Option<f64>,
+  /// Deviation from the speed limit still counted as holding it, in km/h.
+  #[getset(get_copy = "pub", set = "pub")]
+  tolerance:        f64,
+  /// Time in s the speed limit must be held for.
+  #[getset(get_copy = "pub", set = "pub")]
+  min_duration:     f64,
+  /// Speed in km/h the vehicle stays below behind the safety car.
+  #[getset(get_copy = "pub", set = "pub")]
+  safety_car_speed: f64,
+  /// Time in s the vehicle must stay below `safety_car_speed` for.
+  #[getset(get_copy = "pub", set = "pub")]
+  min_safety_car:   f64,
+}
+
+impl Default for PeriodDetector {
+  fn default() -> Self {
+    Self { fcy_switch:       "swRotFcy".to_string(),
+           pit_switch:       "swRotPit".to_string(),
+           speed:            "GPS Speed".to_string(),
+           fcy_speed_limit:  Some(80.0),
+           tolerance:        3.0,
+           min_duration:     10.0,
+           safety_car_speed: 120.0,
+           min_safety_car:   60.0, }
+  }
+}
+
+impl PeriodDetector {
+  /// Detects periods in `channels`, which are looked up by the names
+  /// configured. Returns the periods ordered by start, with overlapping ones
+  /// of the same kind merged.
+  pub fn detect(&self, channels: &[Channel]) -> Vec<Period> {
+    let channel = |name: &str| channels.iter().find(|c| c.name() == name);
+    let switched = |name: &str, kind| {
+      channel(name).map_or_else(Vec::new, |switch| {
+                     intervals(switch, |sample| sample != 0.0)
+                       .into_iter()
+                       .map(|(start, end)| Period::new(kind, start, end))
+                       .collect()
+                   })
+    };
+
+    let mut periods = switched(&self.fcy_switch, PeriodKind::FullCourseYellow);
+    periods.extend(switched(&self.pit_switch, PeriodKind::PitLimiter));
+    if let Some(speed) = channel(&self.speed).and_then(Channel::in_kmh) {
+      if let Some(limit) = self.fcy_speed_limit {
+        let held =
+          plateaus(&speed, Some(limit), self.tolerance, self.min_duration);
+        let fcy = PeriodKind::FullCourseYellow;
+        // speed held in the pit lane is the pit limiter's doing
+        let held = held.into_iter()
+                       .map(|(start, end)| Period::new(fcy, start, end))
+                       .filter(|fcy| {
+                         !periods.iter().any(|period| {
+                                          period.kind
+                                          == PeriodKind::PitLimiter
+                                          && period.overlaps(fcy.start,
+                                                             fcy.end)
+                                        })
+                       })
+                       .collect::<Vec<_>>();
+        periods.extend(held);
+      }
+      let slow = intervals(&speed, |v| {
+                   (20.0..self.safety_car_speed).contains(&v)
+                 });
+      let safety_car =
+        slow.into_iter()
+            .filter(|(start, end)| end - start >= self.min_safety_car)
+            .map(|(start, end)| Period::new(PeriodKind::SafetyCar, start, end))
+            .filter(|sc| {
+              !periods.iter()
+                      .any(|period| period.overlaps(sc.start, sc.end))
+            })
+            .collect::<Vec<_>>();
+      periods.extend(safety_car);
+    }
+    merge(periods)
+  }
+}
+
+
+/// Sorts `periods` by start and merges overlapping ones of the same kind.
+fn merge(mut periods: Vec<Period>) -> Vec<Period> {
+  periods.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap());
+  let mut merged: Vec<Period> = Vec::with_capacity(periods.len());
+  for period in periods {
+    let same = merged.iter_mut().rev().find(|m| m.kind == period.kind);
+    match same {
+      Some(last) if period.start <= last.end => {
+        last.end = last.end.max(period.end)
+      }
+      _ => merged.push(period),
+    }
+  }
+  merged
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ChannelData;
+  use pretty_assertions::assert_eq;
+
+
+  fn channel<F>(name: &str, unit: &str, f: F) -> Channel
+    where F: Fn(f64) -> f64 {
+    let timestamps = (0..600).map(f64::from).collect::<Vec<_>>();
+    let samples = timestamps.iter().map(|&t| f(t)).collect();
+    Channel::new(name.to_string(),
+                 unit.to_string(),
+                 ChannelData::new(timestamps, samples))
+  }
+
+  #[test]
+  fn detect_test() {
+    let racing = |t: f64| 150.0 + 30.0 * (t / 5.0).sin();
+    let channels =
+      vec![channel("swRotFcy", "#", |t| {
+             if (100.0..120.0).contains(&t) {
+               1.0
+             } else {
+               0.0
+             }
+           }),
+           channel("swRotPit", "#", |t| if t >= 560.0 { 1.0 } else { 0.0 }),
+           channel("GPS Speed", "km/h", |t| {
+             if (100.0..140.0).contains(&t) {
+               80.0
+             } else if (300.0..400.0).contains(&t) {
+               90.0 + 20.0 * (t / 3.0).sin()
+             } else if t >= 560.0 {
+               60.0
+             } else {
+               racing(t)
+             }
+           })];
+
+    assert_eq!(vec![Period::new(PeriodKind::FullCourseYellow, 100.0, 139.0),
+                    Period::new(PeriodKind::SafetyCar, 300.0, 399.0),
+                    Period::new(PeriodKind::PitLimiter, 560.0, 599.0)],
+               PeriodDetector::default().detect(&channels));
+
+    // relying on the switch only, the FCY ends when it is switched off
+    let mut detector = PeriodDetector::default();
+    detector.set_fcy_speed_limit(None);
+    let periods = detector.detect(&channels);
+    assert_eq!(Period::new(PeriodKind::FullCourseYellow, 100.0, 119.0),
+               periods[0]);
+    assert_eq!(true, periods[0].kind().neutralises());
+    assert_eq!(false, periods[2].kind().neutralises());
+    assert_eq!(true, periods[1].overlaps(390.0, 500.0));
+    assert_eq!(false, periods[1].overlaps(399.0, 500.0));
+
+    // speed held at the pit lane speed limit isn't a full course yellow
+    detector.set_fcy_speed_limit(Some(60.0));
+    assert_eq!(vec![Period::new(PeriodKind::FullCourseYellow, 100.0, 119.0),
+                    Period::new(PeriodKind::SafetyCar, 300.0, 399.0),
+                    Period::new(PeriodKind::PitLimiter, 560.0, 599.0)],
+               detector.detect(&channels));
+  }
+}
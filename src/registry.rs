@@ -0,0 +1,330 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Registry of the files opened by `Run::load` and friends.
+//!
+//! Runs are registered under the canonical path of their file, so loading the
+//! same file via different paths yields the same `Run`. The registry keeps
+//! track of which files are open and, if a maximum number of open files is
+//! set, closes the least recently used ones when another one is opened. A
+//! `Run` whose file was closed that way transparently opens it again on next
+//! access; one closed explicitly via `Run::close` errors instead.
+
+use super::{DataSource, Error, Result, Run};
+use getset::{CopyGetters, Getters};
+use lazy_static::lazy_static;
+use std::{collections::HashMap,
+          path::{Path, PathBuf},
+          sync::{atomic::{AtomicU64, AtomicUsize, Ordering},
+                 Arc,
+                 Mutex,
+                 MutexGuard,
+                 PoisonError,
+                 RwLock,
+                 Weak}};
+
+
+lazy_static! {
+  static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+/// Logical clock used to find the least recently used file.
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+
+/// A run registered with the registry, as returned by `Run::loaded`.
+#[derive(Debug, Clone, PartialEq, CopyGetters, Getters)]
+pub struct LoadedRun {
+  /// Canonical path of the file.
+  #[getset(get = "pub")]
+  path:     PathBuf,
+  /// Index of the file in the AiM library, as of when it was last opened.
+  #[getset(get_copy = "pub")]
+  idx:      usize,
+  /// Number of `Arc<Run>`s referring to the run.
+  #[getset(get_copy = "pub")]
+  refcount: usize,
+  /// Whether the file is currently open, i.e. not closed to stay within the
+  /// maximum number of open files.
+  #[getset(get_copy = "pub")]
+  open:     bool,
+}
+
+
+/// The data source of a `Run`, which the registry may close and open again.
+#[derive(Debug)]
+pub(crate) struct Handle {
+  path:      PathBuf,
+  source:    RwLock<Slot>,
+  idx:       AtomicUsize,
+  last_used: AtomicU64,
+}
+
+#[derive(Debug)]
+enum Slot {
+  Open(Box<dyn DataSource>),
+  /// Closed by the registry, opened again on next access.
+  Evicted,
+  /// Closed for good.
+  Closed,
+}
+
+impl Handle {
+  pub(crate) fn new(source: Box<dyn DataSource>) -> Self {
+    Self { path:      source.path().to_owned(),
+           idx:       AtomicUsize::new(source.idx()),
+           source:    RwLock::new(Slot::Open(source)),
+           last_used: AtomicU64::new(CLOCK.fetch_add(1, Ordering::Relaxed)), }
+  }
+
+  /// Index of the source, as of when it was last opened.
+  pub(crate) fn idx(&self) -> usize {
+    self.idx.load(Ordering::Relaxed)
+  }
+
+  /// Calls `f` with the source, opening it again first if the registry
+  /// closed it.
+  pub(crate) fn with<T, F>(self: &Arc<Self>, f: F) -> Result<T>
+    where F: FnOnce(&dyn DataSource) -> Result<T> {
+    self.last_used
+        .store(CLOCK.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+    loop {
+      match &*self.source.read().unwrap() {
+        Slot::Open(source) => return f(source.as_ref()),
+        Slot::Closed => return Err(Error::Closed { path: self.path.clone(), }),
+        Slot::Evicted => {}
+      }
+      // the read lock is released before taking the registry lock, since
+      // the registry takes the registry lock before locking sources
+      registry().reopen(self)?;
+    }
+  }
+
+  fn is_open(&self) -> bool {
+    matches!(*self.source.read().unwrap(), Slot::Open(_))
+  }
+
+  /// Replaces the source with `slot`, closing it unless it is closed
+  /// already.
+  fn close(&self, slot: Slot) {
+    let mut source = self.source.write().unwrap();
+    if !matches!(*source, Slot::Closed) {
+      *source = slot;
+    }
+  }
+}
+
+
+#[derive(Debug, Default)]
+struct Registry {
+  runs:           HashMap<PathBuf, Entry>,
+  /// Locks of the files being loaded, see `load`.
+  pending:        HashMap<PathBuf, Arc<Mutex<()>>>,
+  max_open_files: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Entry {
+  run:    Weak<Run>,
+  handle: Arc<Handle>,
+}
+
+impl Registry {
+  /// Returns the run registered for the file at `path`, if any.
+  fn get(&self, path: &Path) -> Option<Arc<Run>> {
+    self.runs.get(path).and_then(|entry| entry.run.upgrade())
+  }
+
+  /// Closes least recently used files until `room` more can be opened
+  /// without exceeding the maximum number of open files.
+  fn make_room(&mut self, room: usize) {
+    let max_open_files = match self.max_open_files {
+      Some(max_open_files) => max_open_files.max(1),
+      None => return,
+    };
+
+    let mut open = self.runs
+                       .values()
+                       .map(|entry| &entry.handle)
+                       .filter(|handle| handle.is_open())
+                       .collect::<Vec<_>>();
+    let excess = (open.len() + room).saturating_sub(max_open_files);
+    open.sort_by_key(|handle| handle.last_used.load(Ordering::Relaxed));
+    for handle in open.into_iter().take(excess) {
+      handle.close(Slot::Evicted);
+    }
+  }
+
+  /// Opens the source of `handle` again if it was closed by the registry.
+  fn reopen(&mut self, handle: &Arc<Handle>) -> Result<()> {
+    if !matches!(*handle.source.read().unwrap(), Slot::Evicted) {
+      return Ok(());
+    }
+    self.make_room(1);
+    let source = Run::open(&handle.path)?;
+    handle.idx.store(source.idx(), Ordering::Relaxed);
+    let mut slot = handle.source.write().unwrap();
+    if matches!(*slot, Slot::Evicted) {
+      *slot = Slot::Open(source);
+    }
+    Ok(())
+  }
+}
+
+fn registry() -> MutexGuard<'static, Registry> {
+  REGISTRY.lock().unwrap()
+}
+
+
+/// Returns the run registered for the file at `path` if there is one, or
+/// else registers the one returned by `create`. `path` must be canonical.
+///
+/// `create` is called without holding the registry lock, so opening a large
+/// file doesn't block access to the other runs. Concurrent loads of the same
+/// file wait for the first one on a lock of their own and then return its
+/// run instead of opening the file again.
+pub(crate) fn load<F>(path: &Path, create: F) -> Result<Arc<Run>>
+  where F: FnOnce() -> Result<Run> {
+  let pending = {
+    let mut registry = registry();
+    if let Some(run) = registry.get(path) {
+      // return Arc, incrementing the ref counter on the object
+      return Ok(run);
+    }
+    registry.pending.entry(path.to_owned()).or_default().clone()
+  };
+  // the lock guards nothing but the loading itself, so a panic in `create`
+  // leaves nothing inconsistent behind
+  let _loading = pending.lock().unwrap_or_else(PoisonError::into_inner);
+
+  let loaded = {
+    let mut registry = registry();
+    let loaded = registry.get(path);
+    if loaded.is_none() {
+      registry.make_room(1);
+    }
+    loaded
+  };
+  // a run loaded meanwhile is registered again as is
+  let run = match loaded {
+    Some(run) => Ok(run),
+    None => create().map(Arc::new),
+  };
+
+  let mut registry = registry();
+  if registry.pending
+             .get(path)
+             .is_some_and(|lock| Arc::ptr_eq(lock, &pending))
+  {
+    registry.pending.remove(path);
+  }
+  let run = run?;
+  registry.runs.insert(path.to_owned(),
+                       Entry { run:    Arc::downgrade(&run),
+                               handle: run.handle().clone(), });
+  Ok(run)
+}
+
+/// Returns the run registered for the file at `path`, if any. `path` must be
+/// canonical.
+pub(crate) fn get(path: &Path) -> Option<Arc<Run>> {
+  registry().get(path)
+}
+
+/// Closes the source of a `Run` being dropped and unregisters it.
+pub(crate) fn release(handle: &Arc<Handle>) {
+  let mut registry = registry();
+  // the source is closed while holding the lock, so a concurrent `load` of
+  // the same path can't observe a half-closed file
+  handle.close(Slot::Closed);
+  registry.runs
+          .retain(|_, entry| !Arc::ptr_eq(&entry.handle, handle));
+}
+
+/// Closes the file at `path` for good, see `Run::close`.
+pub(crate) fn close(path: &Path) -> bool {
+  let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+  let mut registry = registry();
+  match registry.runs.remove(&path) {
+    Some(entry) => {
+      entry.handle.close(Slot::Closed);
+      true
+    }
+    None => false,
+  }
+}
+
+/// All registered runs, ordered by path.
+pub(crate) fn loaded() -> Vec<LoadedRun> {
+  let registry = registry();
+  let mut runs = registry.runs
+                         .iter()
+                         .map(|(path, entry)| {
+                           LoadedRun { path:     path.clone(),
+                                       idx:      entry.handle.idx(),
+                                       refcount: entry.run.strong_count(),
+                                       open:     entry.handle.is_open(), }
+                         })
+                         .filter(|run| run.refcount > 0)
+                         .collect::<Vec<_>>();
+  runs.sort_by(|a, b| a.path.cmp(&b.path));
+  runs
+}
+
+pub(crate) fn max_open_files() -> Option<usize> {
+  registry().max_open_files
+}
+
+pub(crate) fn set_max_open_files(max_open_files: Option<usize>) {
+  let mut registry = registry();
+  registry.max_open_files = max_open_files;
+  registry.make_room(0);
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::{super::MemorySource, *};
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn registry_test() {
+    let handles = (0..3).map(|_| {
+                          let source = MemorySource::new(vec![]);
+                          Arc::new(Handle::new(Box::new(source)))
+                        })
+                        .collect::<Vec<_>>();
+    let mut registry =
+      Registry { runs:           handles.iter()
+                                        .enumerate()
+                                        .map(|(idx, handle)| {
+                                          (PathBuf::from(idx.to_string()),
+                                           Entry { run:    Weak::new(),
+                                                   handle: handle.clone(), })
+                                        })
+                                        .collect(),
+                 pending:        HashMap::new(),
+                 max_open_files: Some(2), };
+
+    // the first one was used last, so the second one is the least recently
+    // used, followed by the third one; the one to be opened needs room too
+    handles[0].with(|source| source.laps_count()).unwrap();
+    registry.make_room(0);
+    assert_eq!(vec![true, false, true],
+               handles.iter().map(|h| h.is_open()).collect::<Vec<_>>());
+    registry.make_room(1);
+    assert_eq!(vec![true, false, false],
+               handles.iter().map(|h| h.is_open()).collect::<Vec<_>>());
+
+    // closing is for good
+    handles[0].close(Slot::Closed);
+    handles[0].close(Slot::Evicted);
+    assert_eq!(true,
+               matches!(handles[0].with(|source| source.laps_count()),
+                        Err(Error::Closed { .. })));
+  }
+}
@@ -4,282 +4,427 @@
 //   Florian Eich <florian@bmc-labs.com>
 //   Jonas Reitemeyer <alumni@bmc-labs.com>
 
-use super::{bindings as aim, util, Channel, ChannelData, Lap, LapInfo};
-use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
-use eyre::{bail, ensure, eyre, Result};
+#[cfg(feature = "aim")]
+use super::AimSource;
+#[cfg(not(feature = "aim"))]
+use super::NativeSource;
+use super::{gps::{Track, ECEF_POSITIONS},
+            header::RunHeader,
+            registry::{self, Handle, LoadedRun},
+            staging::Staging,
+            Channel,
+            ChannelData,
+            ChannelGroup,
+            ChannelSet,
+            Classifier,
+            DataSource,
+            Error,
+            Event,
+            EventDetector,
+            Expression,
+            Gate,
+            Interpolation,
+            Lap,
+            LapInfo,
+            LapKind,
+            MathChannel,
+            Period,
+            PeriodDetector,
+            Result,
+            RunFrame,
+            TimeBase};
+use chrono::NaiveDateTime;
+#[cfg(feature = "aim")]
+use chrono::{NaiveDate, NaiveTime};
 use getset::{CopyGetters, Getters};
-use lazy_static::lazy_static;
-use std::{cmp::Ordering,
-          collections::HashMap,
-          ffi::CStr,
+use once_cell::sync::OnceCell;
+use std::{io::Read,
           path::{Path, PathBuf},
-          sync::{Arc, Mutex, Weak}};
-
-
-lazy_static! {
-  static ref LIBCALL_MTX: Mutex<HashMap<PathBuf, Weak<Run>>> =
-    Mutex::new(HashMap::new());
-}
+          sync::{Arc, RwLock}};
 
 
 /// Holds access information for the file and provides access to it.
-#[derive(Debug, PartialEq, CopyGetters, Getters)]
+///
+/// Counts of laps and channels are read when the `Run` is created, lap
+/// information and channel names and units when they are first needed. Laps
+/// are as split by the logger; laps recomputed via `split_at` are read via
+/// `lap_with`.
+#[derive(Debug, CopyGetters, Getters)]
 pub struct Run {
   #[getset(get = "pub")]
   path:                   PathBuf,
   #[getset(get_copy = "pub")]
-  idx:                    usize,
-  #[getset(get_copy = "pub")]
   number_of_laps:         usize,
-  #[getset(get = "pub")]
-  info_of_laps:           Vec<LapInfo>,
+  info_of_laps:           OnceCell<Vec<LapInfo>>,
+  /// Math channels added by `add_math_channels`.
+  math_channels:          RwLock<Vec<MathChannel>>,
   #[getset(get_copy = "pub")]
   number_of_channels:     usize,
-  #[getset(get = "pub")]
-  channel_names:          Vec<String>,
-  #[getset(get = "pub")]
-  channel_units:          Vec<String>,
+  channel_names:          OnceCell<Vec<String>>,
+  channel_units:          OnceCell<Vec<String>>,
   #[getset(get_copy = "pub")]
   channels_count:         usize,
   #[getset(get_copy = "pub")]
   gps_channels_count:     usize,
   #[getset(get_copy = "pub")]
   gps_raw_channels_count: usize,
+  handle:                 Arc<Handle>,
+  /// Temporary copy of the file for runs not loaded from a path, removed
+  /// after the source is closed.
+  staging:                Option<Staging>,
 }
 
 // DESTRUCTOR - CLOSES FILE ------------------------------------------------ //
 impl Drop for Run {
-  /// Close the drk/xrk file on `Run` destruction
+  /// Close the data source on `Run` destruction
   fn drop(&mut self) {
-    let mut loaded_paths = LIBCALL_MTX.lock().unwrap();
-    unsafe { aim::close_file_i(self.idx as i32) };
-    loaded_paths.remove(&self.path);
+    registry::release(&self.handle);
+  }
+}
+
+impl PartialEq for Run {
+  fn eq(&self, other: &Self) -> bool {
+    self.path == other.path
+    && self.idx() == other.idx()
+    && self.info_of_laps().ok() == other.info_of_laps().ok()
+    && self.channel_names().ok() == other.channel_names().ok()
+    && self.channel_units().ok() == other.channel_units().ok()
+    && self.channels_count == other.channels_count
+    && self.gps_channels_count == other.gps_channels_count
+    && self.gps_raw_channels_count == other.gps_raw_channels_count
   }
 }
 
 impl Run {
   // META FUNCTIONS -------------------------------------------------------- //
   /// Library compilation date.
+  #[cfg(feature = "aim")]
   pub fn library_date() -> Result<NaiveDate> {
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    Ok(NaiveDate::parse_from_str(unsafe {
-                                   CStr::from_ptr(aim::get_library_date())
-                                 }.to_str()?,
-                                 "%b %d %Y")?)
+    AimSource::library_date()
   }
 
   /// Library compilation time.
+  #[cfg(feature = "aim")]
   pub fn library_time() -> Result<NaiveTime> {
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    Ok(NaiveTime::parse_from_str(unsafe {
-                                   CStr::from_ptr(aim::get_library_time())
-                                 }.to_str()?,
-                                 "%H:%M:%S")?)
+    AimSource::library_time()
   }
 
   /// Library compilation date and time.
   ///
   /// This is a convenience function wrapping the functions `library_date` and
   /// `library_time` to produce a datetime object.
+  #[cfg(feature = "aim")]
   pub fn library_datetime() -> Result<NaiveDateTime> {
     Ok(Self::library_date()?.and_time(Self::library_time()?))
   }
 
+  /// Loads the AiM library from `path` (`runtime` feature only).
+  ///
+  /// With the `runtime` feature, the AiM library isn't linked at build time
+  /// but loaded when it is first used, from the path in the environment
+  /// variable `XDRK_LIBRARY_PATH` or, if that isn't set, by its file name
+  /// using the platform's search rules. Call this function before anything
+  /// else to load it from a specific path instead.
+  ///
+  /// The library can only be loaded once per process. Errors if it can't be
+  /// loaded, if it lacks any of the required symbols, or if it was already
+  /// loaded from a different path.
+  #[cfg(feature = "runtime")]
+  pub fn with_library(path: &Path) -> Result<()> {
+    AimSource::with_library(path)
+  }
+
   // FILE OPENING / CLOSING FUNCTIONS -------------------------------------- //
   /// Loads a drk/xrk file and creates an `Run` object.
+  ///
+  /// The file is opened using the AiM library, or using the pure Rust reader
+  /// if the crate is built with the `native` feature instead of `aim`.
+  ///
+  /// Runs are registered under the canonical path of their file: as long as
+  /// a `Run` for a file exists, loading that file again, via whatever path,
+  /// returns the same object. `path()` returns the canonical path.
   pub fn load(path: &Path) -> Result<Arc<Self>> {
+    let path = Self::check_path(path)?;
+    registry::load(&path, || Self::new(Self::open(&path)?))
+  }
+
+  /// Reads session metadata and counts of laps and channels of a drk/xrk
+  /// file, which is a lot cheaper than loading a `Run`. If the file is
+  /// loaded already, the data comes from that `Run`, otherwise the file is
+  /// opened just for this.
+  pub fn peek(path: &Path) -> Result<RunHeader> {
+    let path = Self::check_path(path)?;
+    match registry::get(&path) {
+      Some(run) => run.header(),
+      None => RunHeader::read(Self::open(&path)?.as_ref()),
+    }
+  }
+
+  /// Checks whether `path` points to a drk/xrk file, returning its canonical
+  /// path.
+  fn check_path(path: &Path) -> Result<PathBuf> {
+    let invalid = |reason| Error::InvalidPath { path: path.to_owned(),
+                                                reason };
     let extension =
       path.extension()
           .unwrap_or_default()
           .to_str()
-          .ok_or(eyre!("file extension is not valid unicode ({})",
-                       path.display()))?;
-
-    ensure!(path.exists() && path.is_file(),
-            "path does not exist or is not a valid file ({})",
-            path.display());
-    ensure!(["drk", "xrk"].contains(&extension),
-            "only files with extensions .xrk and .drk accepted ({})",
-            path.display());
-
-    let mut loaded_paths = LIBCALL_MTX.lock().unwrap();
-    let file = if !loaded_paths.contains_key(path) {
-      // we need to open the file up
-      let raw_idx =
-        unsafe { aim::open_file(util::path_to_cstring(path)?.as_ptr()) };
-
-      // match the index to check if opening file was a success
-      let idx = match raw_idx.cmp(&0) {
-        Ordering::Greater => raw_idx as usize,
-        Ordering::Equal => {
-          bail!("file is open but can't be parsed ({})", path.display())
-        }
-        Ordering::Less => bail!("an error occurred ({})", path.display()),
-      };
-
-      // get number of laps to cache it in `Run` object
-      let count = unsafe { aim::get_laps_count(idx as i32) };
-      let number_of_laps = match count.cmp(&0) {
-        Ordering::Greater => count as usize,
-        Ordering::Equal => bail!("file contains 0 laps ({})", path.display()),
-        Ordering::Less => {
-          bail!("error getting lap count ({})", path.display())
-        }
-      };
-
-      // get info of all laps to cache it in `Run` object
-      let mut info_of_laps = Vec::with_capacity(number_of_laps);
-      for lap_idx in 0..number_of_laps {
-        let (mut start, mut time) = (0.0f64, 0.0f64);
-        let err_code = unsafe {
-          aim::get_lap_info(idx as i32, lap_idx as i32, &mut start, &mut time)
-        };
-        ensure!(err_code == 1,
-                "could not fetch lap info ({})",
-                path.display());
-        info_of_laps.push(LapInfo::new(lap_idx, start, time));
-      }
+          .ok_or_else(|| invalid("file extension is not valid unicode"))?;
 
-      // get count of channels to cache it in `Run` object
-      let count = unsafe { aim::get_channels_count(idx as i32) };
-      let channels_count = match count.cmp(&0) {
-        Ordering::Greater => count as usize,
-        Ordering::Equal => {
-          bail!("file contains 0 channels ({})", path.display())
-        }
-        Ordering::Less => {
-          bail!("error getting channel count ({})", path.display())
-        }
-      };
-
-      // get count of gps channels to cache it in `Run` object
-      let count = unsafe { aim::get_GPS_channels_count(idx as i32) };
-      let gps_channels_count = match count.cmp(&0) {
-        Ordering::Greater | Ordering::Equal => count as usize,
-        Ordering::Less => {
-          bail!("error getting GPS channel count ({})", path.display())
-        }
-      };
-
-      // get count of gps raw channels to cache it in `Run` object
-      let count = unsafe { aim::get_GPS_raw_channels_count(idx as i32) };
-      let gps_raw_channels_count = match count.cmp(&0) {
-        Ordering::Greater | Ordering::Equal => count as usize,
-        Ordering::Less => {
-          bail!("error getting GPS raw channel count ({})", path.display())
-        }
-      };
-
-      // the magic in the following section is this:
-      //
-      // WE ONLY TAKE THE REGULAR CHANNELS AND THE GPS CHANNELS AND OMIT THE
-      // "RAW" GPS CHANNELS FROM THE DATA, SINCE WE ARE GENERALLY NOT
-      // INTERESTED IN THOSE. to make that happen we simply don't count the raw
-      // channels and don't get their names nor units. as such, when the `Run`
-      // object is asked for `number_of_channels`, or `channel_names` or
-      // `channel_units`, the raw channels are simply not included.
-      //
-      // get total number of channels to cache it in `Run` object
-      let number_of_channels = channels_count + gps_channels_count;
-
-      // get channel names to cache it in `Run` object
-      let mut channel_names = Vec::with_capacity(number_of_channels);
-
-      for channel_idx in 0..number_of_channels {
-        let name = util::strptr_to_string(unsafe {
-          if channel_idx < channels_count {
-            aim::get_channel_name(idx as i32, channel_idx as i32)
-          } else {
-            let channel_idx = channel_idx % channels_count;
-            aim::get_GPS_channel_name(idx as i32, channel_idx as i32)
-          }
-        })?;
-        channel_names.push(name);
+    if !(path.exists() && path.is_file()) {
+      return Err(invalid("path does not exist or is not a valid file"));
+    }
+    if !["drk", "xrk"].contains(&extension) {
+      return Err(invalid("only files with extensions .xrk and .drk \
+                          accepted"));
+    }
+
+    path.canonicalize()
+        .map_err(|source| Error::Io { path: path.to_owned(),
+                                      source })
+  }
+
+  /// Loads a drk/xrk file from its contents, e.g. fetched from object storage
+  /// into memory.
+  ///
+  /// Since the AiM library can only open files by path and drops temporary
+  /// files next to them, `bytes` are written to a private temporary directory
+  /// first, which is removed again once the `Run` is dropped. `path()`
+  /// returns the path of that temporary file. Every call creates a new `Run`
+  /// object.
+  pub fn from_bytes(bytes: &[u8]) -> Result<Arc<Self>> {
+    Self::from_reader(bytes)
+  }
+
+  /// Loads a drk/xrk file from everything `reader` yields, staging it like
+  /// `from_bytes`. Useful for files in read-only locations, which the AiM
+  /// library can't open in place.
+  pub fn from_reader<R: Read>(reader: R) -> Result<Arc<Self>> {
+    let staging = Staging::new(reader)?;
+    let path = staging.path().to_owned();
+    registry::load(&path, || {
+      let mut run = Self::new(Self::open(&path)?)?;
+      run.staging = Some(staging);
+      Ok(run)
+    })
+  }
+
+  /// Creates a `Run` object from any `DataSource`, e.g. a `MemorySource`
+  /// holding synthetic data.
+  ///
+  /// Unlike `load`, this does not check whether a `Run` for the same path
+  /// exists already, every call creates a new object.
+  pub fn from_source<S>(source: S) -> Result<Arc<Self>>
+    where S: DataSource + 'static {
+    Ok(Arc::new(Self::new(Box::new(source))?))
+  }
+
+  /// Closes the file at `path` (of a `Run` created by `load`, `from_bytes`
+  /// or `from_reader`) right away, even if `Run` objects referring to it
+  /// still exist. Accessing the file through them errors from then on, and
+  /// loading it again creates a new `Run`. Returns `false` if no such `Run`
+  /// exists.
+  pub fn close(path: &Path) -> bool {
+    registry::close(path)
+  }
+
+  /// All runs created by `load`, `from_bytes` or `from_reader` which are
+  /// still around, ordered by path.
+  pub fn loaded() -> Vec<LoadedRun> {
+    registry::loaded()
+  }
+
+  /// Maximum number of files kept open at the same time, if any.
+  pub fn max_open_files() -> Option<usize> {
+    registry::max_open_files()
+  }
+
+  /// Limits the number of files kept open at the same time, which is
+  /// unlimited by default.
+  ///
+  /// When another file is to be opened and the limit is reached, the least
+  /// recently used file is closed. Its `Run` stays valid and opens the file
+  /// again on next access, which in turn may close another one. Files beyond
+  /// the limit are closed right away. A limit of 0 is treated as 1.
+  pub fn set_max_open_files(max_open_files: Option<usize>) {
+    registry::set_max_open_files(max_open_files)
+  }
+
+  /// Opens the file at `path` with the AiM library, or the pure Rust reader
+  /// if the crate is built without the `aim` feature.
+  pub(crate) fn open(path: &Path) -> Result<Box<dyn DataSource>> {
+    #[cfg(feature = "aim")]
+    let source = AimSource::open(path)?;
+    #[cfg(not(feature = "aim"))]
+    let source = NativeSource::open(path)?;
+    Ok(Box::new(source))
+  }
+
+  /// Creates a `Run` from a data source, caching the counts of laps and
+  /// channels.
+  fn new(source: Box<dyn DataSource>) -> Result<Self> {
+    let path = source.path().to_owned();
+
+    // get number of laps to cache it in `Run` object
+    let number_of_laps = source.laps_count()?;
+    if number_of_laps == 0 {
+      return Err(Error::NoLaps { path });
+    }
+
+    // get count of channels to cache it in `Run` object
+    let channels_count = source.channels_count(ChannelGroup::Regular)?;
+    if channels_count == 0 {
+      return Err(Error::NoChannels { path });
+    }
+    let gps_channels_count = source.channels_count(ChannelGroup::Gps)?;
+    let gps_raw_channels_count = source.channels_count(ChannelGroup::GpsRaw)?;
+
+    // the magic in the following section is this:
+    //
+    // WE ONLY TAKE THE REGULAR CHANNELS AND THE GPS CHANNELS AND OMIT THE
+    // "RAW" GPS CHANNELS FROM THE DATA, SINCE WE ARE GENERALLY NOT
+    // INTERESTED IN THOSE. to make that happen we simply don't count the raw
+    // channels and don't get their names nor units. as such, when the `Run`
+    // object is asked for `number_of_channels`, or `channel_names` or
+    // `channel_units`, the raw channels are simply not included.
+    //
+    // get total number of channels to cache it in `Run` object
+    let number_of_channels = channels_count + gps_channels_count;
+
+    Ok(Self { path,
+              number_of_laps,
+              info_of_laps: OnceCell::new(),
+              math_channels: RwLock::new(Vec::new()),
+              number_of_channels,
+              channel_names: OnceCell::new(),
+              channel_units: OnceCell::new(),
+              channels_count,
+              gps_channels_count,
+              gps_raw_channels_count,
+              handle: Arc::new(Handle::new(source)),
+              staging: None })
+  }
+
+  /// Index of the file in the AiM library. May change if the file is closed
+  /// and opened again to stay within `max_open_files`.
+  pub fn idx(&self) -> usize {
+    self.handle.idx()
+  }
+
+  pub(crate) fn handle(&self) -> &Arc<Handle> {
+    &self.handle
+  }
+
+  /// Info of all laps as split by the logger, which is read on first access.
+  pub fn info_of_laps(&self) -> Result<&Vec<LapInfo>> {
+    self.info_of_laps.get_or_try_init(|| {
+                       self.handle.with(|source| {
+                                    (0..self.number_of_laps)
+                                      .map(|lap_idx| source.lap_info(lap_idx))
+                                      .collect()
+                                  })
+                     })
+  }
+
+  /// Names of all regular and GPS channels, followed by those of the math
+  /// channels, see `add_math_channels`. Math channels with the same name as
+  /// a channel of the file are left out, since they are shadowed by it.
+  ///
+  /// Unlike in `channel_names`, the position of a name isn't a channel index:
+  /// math channels are requested by name only, e.g. via `channel_by_name`.
+  pub fn all_channel_names(&self) -> Result<Vec<String>> {
+    let mut names = self.channel_names()?.clone();
+    for math in self.math_channels.read().unwrap().iter() {
+      if !names.contains(math.name()) {
+        names.push(math.name().clone());
       }
+    }
+    Ok(names)
+  }
 
-      // get channel units to cache it in `Run` object
-      let mut channel_units = Vec::with_capacity(number_of_channels);
-      for channel_idx in 0..number_of_channels {
-        let unit = util::strptr_to_string(unsafe {
-          if channel_idx < channels_count {
-            aim::get_channel_units(idx as i32, channel_idx as i32)
-          } else {
-            let channel_idx = channel_idx % channels_count;
-            aim::get_GPS_channel_units(idx as i32, channel_idx as i32)
-          }
-        })?;
-        channel_units.push(unit);
+  /// Units of all regular and GPS channels, followed by those of the math
+  /// channels, in the order of `all_channel_names`.
+  pub fn all_channel_units(&self) -> Result<Vec<String>> {
+    let names = self.channel_names()?;
+    let mut units = self.channel_units()?.clone();
+    for math in self.math_channels.read().unwrap().iter() {
+      if !names.contains(math.name()) {
+        units.push(math.unit().clone());
       }
+    }
+    Ok(units)
+  }
 
-      // create object in Arc, pass it as weak pointer into global mutex
-      let file = Arc::new(Self { path: path.to_owned(),
-                                 idx,
-                                 number_of_laps,
-                                 info_of_laps,
-                                 number_of_channels,
-                                 channel_names,
-                                 channel_units,
-                                 channels_count,
-                                 gps_channels_count,
-                                 gps_raw_channels_count });
-
-      loaded_paths.insert(path.to_owned(), Arc::downgrade(&file));
-      file
-    } else {
-      loaded_paths.get(path)
-                  .expect("failed to get Arc object in load function")
-                  .upgrade()
-                  .unwrap()
-    };
+  /// Names of all regular and GPS channels, read on first access, indexed
+  /// like in `channel`.
+  pub fn channel_names(&self) -> Result<&Vec<String>> {
+    self.channel_names.get_or_try_init(|| {
+                        self.channel_table(|source, group, channel_idx| {
+                              source.channel_name(group, channel_idx)
+                            })
+                      })
+  }
 
-    // return Arc, incrementing the ref counter on the object
-    Ok(file)
+  /// Units of all regular and GPS channels, read on first access, indexed
+  /// like in `channel`.
+  pub fn channel_units(&self) -> Result<&Vec<String>> {
+    self.channel_units.get_or_try_init(|| {
+                        self.channel_table(|source, group, channel_idx| {
+                              source.channel_unit(group, channel_idx)
+                            })
+                      })
+  }
+
+  /// Session metadata and counts, see `Run::peek`.
+  pub fn header(&self) -> Result<RunHeader> {
+    self.handle.with(RunHeader::read)
+  }
+
+  /// Collects `get` for all regular and GPS channels.
+  ///
+  /// The "raw" GPS channels are omitted, since we are generally not
+  /// interested in those; see `new`.
+  fn channel_table<F>(&self, get: F) -> Result<Vec<String>>
+    where F: Fn(&dyn DataSource, ChannelGroup, usize) -> Result<String> {
+    self.handle.with(|source| {
+                 let mut table = Vec::with_capacity(self.number_of_channels);
+                 for (group, count) in
+                   [(ChannelGroup::Regular, self.channels_count),
+                    (ChannelGroup::Gps, self.gps_channels_count)].iter()
+                 {
+                   for channel_idx in 0..*count {
+                     table.push(get(source, *group, channel_idx)?);
+                   }
+                 }
+                 Ok(table)
+               })
   }
 
   // RUN LEVEL FUNCTIONS --------------------------------------------------- //
   pub fn championship(&self) -> Result<String> {
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    util::strptr_to_string(unsafe {
-      aim::get_championship_name(self.idx as i32)
-    })
+    self.handle.with(|source| source.championship())
   }
 
   pub fn track(&self) -> Result<String> {
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    util::strptr_to_string(unsafe { aim::get_track_name(self.idx as i32) })
+    self.handle.with(|source| source.track())
   }
 
   pub fn venue_type(&self) -> Result<String> {
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    util::strptr_to_string(unsafe {
-      aim::get_venue_type_name(self.idx as i32)
-    })
+    self.handle.with(|source| source.venue_type())
   }
 
   pub fn vehicle(&self) -> Result<String> {
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    util::strptr_to_string(unsafe { aim::get_vehicle_name(self.idx as i32) })
+    self.handle.with(|source| source.vehicle())
   }
 
   pub fn racer(&self) -> Result<String> {
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    util::strptr_to_string(unsafe { aim::get_racer_name(self.idx as i32) })
+    self.handle.with(|source| source.racer())
   }
 
   /// On success, the `Result` contains a datetime object which defines when
   /// this `Run` was recorded.
   pub fn datetime(&self) -> Result<NaiveDateTime> {
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let tm: *const aim::tm =
-      unsafe { aim::get_date_and_time(self.idx as i32) };
-    ensure!(!tm.is_null(), "could not fetch datetime object");
-
-    let tm = unsafe { *tm };
-    Ok(NaiveDate::from_ymd(tm.tm_year + 1900,
-                           (tm.tm_mon + 1) as u32,
-                           tm.tm_mday as u32).and_hms(tm.tm_hour as u32,
-                                                      tm.tm_min as u32,
-                                                      tm.tm_sec as u32))
+    self.handle.with(|source| source.datetime())
   }
 
   /// For lap with index `lap_idx`, request `LapInfo`. Returns an error if
@@ -290,8 +435,8 @@ impl Run {
   /// run recorded in this file (via the `start()` getter) and the lap duration
   /// (via the `duration()` getter).
   pub fn lap_info(&self, lap_idx: usize) -> Result<LapInfo> {
-    ensure!(lap_idx < self.number_of_laps, "lap_idx out of range");
-    Ok(self.info_of_laps[lap_idx])
+    self.check_lap(lap_idx)?;
+    Ok(self.info_of_laps()?[lap_idx])
   }
 
   /// For lap with index `idx`, request all channels. Returns a Lap object or
@@ -301,51 +446,254 @@ impl Run {
   /// `Lap` objects contain a `LapInfo` object and a `Vec<Channel>` containing
   /// all data recorded in the lap.
   pub fn lap(&self, lap_idx: usize) -> Result<Lap> {
-    let len = self.number_of_channels;
+    self.lap_in(lap_idx, ChannelSet::DEFAULT)
+  }
+
+  /// Like `lap`, but with the channels of the groups in `set`, e.g. including
+  /// the GPS raw channels with `ChannelSet::ALL`.
+  pub fn lap_in(&self, lap_idx: usize, set: ChannelSet) -> Result<Lap> {
+    let len = self.number_of_channels_in(set);
+    let mut channels = Vec::with_capacity(len);
+    for channel_idx in 0..len {
+      channels.push(self.channel_in(channel_idx, Some(lap_idx), set)?);
+    }
+    Lap::new(self.lap_info(lap_idx)?, channels)
+      .with_path(self.path.clone())
+      .with_math_channels(self.math_channels())
+  }
+
+  /// Request all channels for the lap described by `info`, cut from the data
+  /// of the whole `Run`, e.g. for a lap returned by `split_at`. Fails if a
+  /// channel has no samples within the lap or the library call fails for any
+  /// reason.
+  pub fn lap_with(&self, info: &LapInfo) -> Result<Lap> {
+    self.lap_with_in(info, ChannelSet::DEFAULT)
+  }
+
+  /// Like `lap_with`, but with the channels of the groups in `set`.
+  pub fn lap_with_in(&self, info: &LapInfo, set: ChannelSet) -> Result<Lap> {
+    let (start, end) = (info.start(), info.start() + info.time());
+    let len = self.number_of_channels_in(set);
     let mut channels = Vec::with_capacity(len);
     for channel_idx in 0..len {
-      channels.push(self.channel(channel_idx, Some(lap_idx))?);
+      let (group, channel_idx) = self.resolve(channel_idx, set)?;
+      let data = self.samples(group, channel_idx, None)?.between(start, end);
+      if data.is_empty() {
+        return Err(Error::EmptyChannel { path:    self.path.clone(),
+                                         group,
+                                         channel: channel_idx,
+                                         lap:     Some(info.idx()), });
+      }
+      channels.push(Channel::new(self.name_in(group, channel_idx)?,
+                                 self.unit_in(group, channel_idx)?,
+                                 data));
     }
-    Ok(Lap::new(self.lap_info(lap_idx)?, channels))
+    Lap::new(*info, channels).with_path(self.path.clone())
+                             .with_math_channels(self.math_channels())
   }
 
   /// Request all channels for all laps contained in this `Run`. Fails if
   /// the library call fails for any reason, either on finding all laps or on
   /// requesting `Lap`s.
   pub fn all_laps(&self) -> Result<Vec<Lap>> {
+    self.all_laps_in(ChannelSet::DEFAULT)
+  }
+
+  /// Like `all_laps`, but with the channels of the groups in `set`.
+  pub fn all_laps_in(&self, set: ChannelSet) -> Result<Vec<Lap>> {
     let len = self.number_of_laps();
     let mut laps = Vec::with_capacity(len);
     for lap_idx in 0..len {
-      laps.push(self.lap(lap_idx)?);
+      laps.push(self.lap_in(lap_idx, set)?);
     }
     Ok(laps)
   }
 
+  /// Recomputes the laps from the GPS position, starting another lap
+  /// whenever the vehicle crosses `finish` in the direction of travel (see
+  /// `Gate`), e.g. for sessions recorded with a misplaced beacon. Crossing
+  /// times are interpolated between the GPS samples. Data recorded before
+  /// the first crossing forms the first lap, data after the last one the last
+  /// lap, just like with the logger's laps.
+  ///
+  /// Returns the recomputed laps, leaving the `Run` as it is: `lap`,
+  /// `lap_info` and all other lap-wise functions keep serving the logger's
+  /// laps, while `lap_with` reads the recomputed ones.
+  ///
+  /// ## Fails if
+  ///
+  /// - the run lacks the ECEF position channels among its GPS raw channels
+  /// - the vehicle never crosses `finish`
+  pub fn split_at(&self, finish: &Gate) -> Result<Vec<LapInfo>> {
+    let channels =
+      ECEF_POSITIONS.iter()
+                    .map(|name| {
+                      let channel_idx =
+                        self.channel_idx_in(name, ChannelSet::ALL)?;
+                      self.channel_in(channel_idx, None, ChannelSet::ALL)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+    let logged = self.info_of_laps()?;
+    let start = logged[0].start();
+    let last = logged[logged.len() - 1];
+    let end = last.start() + last.time();
+
+    let crossings = finish.crossings(&Track::new(&channels)?)
+                          .into_iter()
+                          .filter(|&t| start < t && t < end)
+                          .collect::<Vec<_>>();
+    if crossings.is_empty() {
+      let reason = "the vehicle never crosses the line".to_string();
+      return Err(Error::InvalidArgument { name: "finish",
+                                          reason });
+    }
+    let boundaries = Some(start).into_iter()
+                                .chain(crossings)
+                                .chain(Some(end))
+                                .collect::<Vec<_>>();
+    let laps = boundaries.windows(2)
+                         .enumerate()
+                         .map(|(idx, w)| LapInfo::new(idx, w[0], w[1] - w[0]))
+                         .collect::<Vec<_>>();
+    Ok(laps)
+  }
+
+  /// Classifies all laps using `classifier`, see `Classifier`. Returns the
+  /// info of all laps carrying the kind of the lap, e.g. to pass on to
+  /// `flying_laps`; the `Run` itself is left as is, since it may be shared
+  /// (see `load`).
+  ///
+  /// Only the pit switch and speed channels named by `classifier` are read;
+  /// laps lacking them are classified by position and lap time only.
+  pub fn classify_laps(&self,
+                       classifier: &Classifier) -> Result<Vec<LapInfo>> {
+    let channel_idxs =
+      [classifier.pit_switch(), classifier.speed()].iter()
+                                                   .filter_map(|name| {
+                                                     self.channel_idx(name)
+                                                         .ok()
+                                                   })
+                                                   .collect::<Vec<_>>();
+    let mut laps = Vec::with_capacity(self.number_of_laps());
+    for lap_idx in 0..self.number_of_laps() {
+      let mut channels = Vec::with_capacity(channel_idxs.len());
+      for &channel_idx in &channel_idxs {
+        match self.channel(channel_idx, Some(lap_idx)) {
+          Ok(channel) => channels.push(channel),
+          Err(Error::EmptyChannel { .. }) => {}
+          Err(err) => return Err(err),
+        }
+      }
+      laps.push(Lap::new(self.lap_info(lap_idx)?, channels)
+                  .with_path(self.path.clone()));
+    }
+    let kinds = classifier.classify(&laps)?;
+    Ok(laps.iter()
+           .zip(kinds)
+           .map(|(lap, kind)| lap.info().with_kind(kind))
+           .collect())
+  }
+
+  /// The flying laps among `laps` as returned by `classify_laps`, read one by
+  /// one while iterating and carrying their `LapInfo`. Laps marked as
+  /// neutralised, e.g. via `LapInfo::with_neutralised_by`, are left out.
+  pub fn flying_laps<'a>(&'a self,
+                         laps: &'a [LapInfo])
+                         -> impl Iterator<Item = Result<Lap>> + 'a {
+    laps.iter()
+        .filter(|info| info.kind() == Some(LapKind::Flying))
+        .filter(|info| !info.neutralised())
+        .map(move |&info| {
+          self.lap(info.idx()).map(|lap| lap.with_info(info))
+        })
+  }
+
+  /// Detects full course yellow, safety car and pit limiter periods in the
+  /// data of the whole run using `detector`, see `PeriodDetector`. Returns
+  /// the periods found, which `LapInfo::with_neutralised_by` marks the laps
+  /// overlapping them with; the `Run` itself is left as is, since it may be
+  /// shared (see `load`).
+  pub fn detect_periods(&self,
+                        detector: &PeriodDetector)
+                        -> Result<Vec<Period>> {
+    let channels = [detector.fcy_switch(), detector.pit_switch(),
+                    detector.speed()].iter()
+                                     .filter_map(|name| {
+                                       self.channel_idx(name).ok()
+                                     })
+                                     .map(|idx| self.channel(idx, None))
+                                     .collect::<Result<Vec<_>>>()?;
+    Ok(detector.detect(&channels))
+  }
+
+  /// Number of channels in the groups in `set`.
+  pub fn number_of_channels_in(&self, set: ChannelSet) -> usize {
+    set.groups().map(|group| self.group_count(group)).sum()
+  }
+
+  /// Names of all channels in the groups in `set`, indexed like in
+  /// `channel_in`.
+  pub fn channel_names_in(&self, set: ChannelSet) -> Result<Vec<String>> {
+    (0..self.number_of_channels_in(set)).map(|channel_idx| {
+                                          let (group, channel_idx) =
+                                            self.resolve(channel_idx, set)?;
+                                          self.name_in(group, channel_idx)
+                                        })
+                                        .collect()
+  }
+
+  /// Units of all channels in the groups in `set`, indexed like in
+  /// `channel_in`.
+  pub fn channel_units_in(&self, set: ChannelSet) -> Result<Vec<String>> {
+    (0..self.number_of_channels_in(set)).map(|channel_idx| {
+                                          let (group, channel_idx) =
+                                            self.resolve(channel_idx, set)?;
+                                          self.unit_in(group, channel_idx)
+                                        })
+                                        .collect()
+  }
+
   /// For channel with index `idx`, request the channel name.
   pub fn channel_name(&self, channel_idx: usize) -> Result<String> {
-    ensure!(channel_idx < self.number_of_channels,
-            "channel_idx out of range");
-    Ok(self.channel_names[channel_idx].clone())
+    self.check_channel(channel_idx)?;
+    Ok(self.channel_names()?[channel_idx].clone())
   }
 
   /// Request index of channel with name `channel_name`.
   pub fn channel_idx(&self, channel_name: &str) -> Result<usize> {
     let channel_idx =
-      self.channel_names
+      self.channel_names()?
           .iter()
           .position(|name| name == channel_name)
-          .ok_or(eyre!("no channel '{}' found", channel_name))?;
+          .ok_or_else(|| {
+            Error::ChannelNotFound { path: self.path.clone(),
+                                     name: channel_name.to_owned(), }
+          })?;
 
     assert!(channel_idx < self.number_of_channels,
             "channel index out of range");
     Ok(channel_idx)
   }
 
+  /// Request index of channel with name `channel_name` among the channels in
+  /// the groups in `set`.
+  pub fn channel_idx_in(&self,
+                        channel_name: &str,
+                        set: ChannelSet)
+                        -> Result<usize> {
+    self.channel_names_in(set)?
+        .iter()
+        .position(|name| name == channel_name)
+        .ok_or_else(|| {
+          Error::ChannelNotFound { path: self.path.clone(),
+                                   name: channel_name.to_owned(), }
+        })
+  }
+
   /// For channel with index `channel_idx`, request the channel unit.
   pub fn channel_unit(&self, channel_idx: usize) -> Result<String> {
-    ensure!(channel_idx < self.number_of_channels,
-            "channel_idx out of range");
-    Ok(self.channel_units[channel_idx].clone())
+    self.check_channel(channel_idx)?;
+    Ok(self.channel_units()?[channel_idx].clone())
   }
 
   /// Request a `Channel` object by name and lap index. Fails if no channel
@@ -356,9 +704,164 @@ impl Run {
                  channel_idx: usize,
                  lap_idx: Option<usize>)
                  -> Result<Channel> {
-    Ok(Channel::new(self.channel_name(channel_idx)?,
-                    self.channel_unit(channel_idx)?,
-                    self.channel_data(channel_idx, lap_idx)?))
+    self.channel_in(channel_idx, lap_idx, ChannelSet::DEFAULT)
+  }
+
+  /// Like `channel`, but with `channel_idx` indexing the channels in the
+  /// groups in `set`, one group after the other. With `ChannelSet::ALL`,
+  /// the GPS raw channels follow the regular and GPS channels, i.e. the first
+  /// of them has index `number_of_channels()`.
+  pub fn channel_in(&self,
+                    channel_idx: usize,
+                    lap_idx: Option<usize>,
+                    set: ChannelSet)
+                    -> Result<Channel> {
+    let (group, channel_idx) = self.resolve(channel_idx, set)?;
+    Ok(Channel::new(self.name_in(group, channel_idx)?,
+                    self.unit_in(group, channel_idx)?,
+                    self.samples(group, channel_idx, lap_idx)?))
+  }
+
+  /// Finds the events `detector` looks for in the channel with index
+  /// `channel_idx`, in the lap with index `lap_idx` or, if `None`, in the
+  /// whole run, see `Channel::events`. Events are marked with the index of
+  /// the lap they occur in, if any. Fails like `channel`.
+  pub fn events(&self,
+                channel_idx: usize,
+                lap_idx: Option<usize>,
+                detector: &EventDetector)
+                -> Result<Vec<Event>> {
+    let events = self.channel(channel_idx, lap_idx)?.events(detector);
+    if let Some(lap_idx) = lap_idx {
+      return Ok(events.into_iter()
+                      .map(|event| event.with_lap(lap_idx))
+                      .collect());
+    }
+
+    let laps = self.info_of_laps()?;
+    Ok(events.into_iter()
+             .map(|event| {
+               let t = event.timestamp();
+               match laps.iter().find(|lap| {
+                              lap.start() <= t && t < lap.start() + lap.time()
+                            }) {
+                 Some(lap) => event.with_lap(lap.idx()),
+                 None => event,
+               }
+             })
+             .collect())
+  }
+
+  /// Request the channels named in `channels` for the lap with index
+  /// `lap_idx` (or the whole run if `None`) as a `RunFrame`, resampled onto
+  /// `time_base` using linear interpolation. Channels are looked up among
+  /// all channel groups, including the GPS raw channels. Fails if a channel
+  /// can't be found or the frame can't be built, see `RunFrame::new`.
+  pub fn frame(&self,
+               channels: &[&str],
+               lap_idx: Option<usize>,
+               time_base: TimeBase)
+               -> Result<RunFrame> {
+    let channels = channels.iter()
+                           .map(|name| self.channel_by_name(name, lap_idx))
+                           .collect::<Result<Vec<_>>>()?;
+    RunFrame::new(&channels, time_base, Interpolation::Linear)
+      .map_err(|error| error.in_file(&self.path))
+  }
+
+  /// Computes `expression` from the channels it references in the lap with
+  /// index `lap_idx` (or the whole run if `None`), see
+  /// `Expression::evaluate`. Channels are looked up among all channel
+  /// groups, like in `frame`.
+  pub fn evaluate(&self,
+                  expression: &Expression,
+                  lap_idx: Option<usize>)
+                  -> Result<Channel> {
+    let channels = expression.channels()
+                             .iter()
+                             .map(|name| self.channel_by_name(name, lap_idx))
+                             .collect::<Result<Vec<_>>>()?;
+    expression.evaluate(&channels)
+  }
+
+  /// Request a `Channel` by name for the lap with index `lap_idx` (or the
+  /// whole run if `None`). Channels are looked up among all channel groups,
+  /// including the GPS raw channels, and then among the math channels, which
+  /// are computed from the channels they reference. Fails if there is no
+  /// such channel or a math channel can't be computed.
+  pub fn channel_by_name(&self,
+                         name: &str,
+                         lap_idx: Option<usize>)
+                         -> Result<Channel> {
+    match self.channel_idx_in(name, ChannelSet::ALL) {
+      Ok(channel_idx) => {
+        self.channel_in(channel_idx, lap_idx, ChannelSet::ALL)
+      }
+      Err(Error::ChannelNotFound { .. }) => {
+        let math = self.math_channels
+                       .read()
+                       .unwrap()
+                       .iter()
+                       .find(|math| math.name() == name)
+                       .cloned()
+                       .ok_or_else(|| {
+                         Error::ChannelNotFound { path: self.path.clone(),
+                                                  name: name.to_owned(), }
+                       })?;
+        let inputs = math.expression()
+                         .channels()
+                         .iter()
+                         .map(|name| self.channel_by_name(name, lap_idx))
+                         .collect::<Result<Vec<_>>>()?;
+        math.compute(&inputs)
+      }
+      Err(error) => Err(error),
+    }
+  }
+
+  // MATH CHANNELS --------------------------------------------------------- //
+  /// Adds `math_channels` to this run, replacing math channels of the same
+  /// name. Math channels are listed in `all_channel_names` and can be
+  /// requested by name like the channels of the file, e.g. via
+  /// `channel_by_name` or `Lap::channel`, and are computed when requested.
+  /// They have no channel index, so `channel_idx` and `channel` don't know of
+  /// them. Fails, leaving the math channels as they were, if they reference
+  /// each other in a cycle.
+  ///
+  /// Math channels belong to the `Run`, which `load` shares between everyone
+  /// loading the same file, so they are seen by all of them. To keep math
+  /// channels to yourself, add them to your laps via
+  /// `Lap::with_math_channels` instead.
+  pub fn add_math_channels(&self,
+                           math_channels: Vec<MathChannel>)
+                           -> Result<()> {
+    let mut current = self.math_channels.write().unwrap();
+    let mut updated = current.clone();
+    for math in math_channels {
+      match updated.iter_mut().find(|m| m.name() == math.name()) {
+        Some(existing) => *existing = math,
+        None => updated.push(math),
+      }
+    }
+    crate::math_channel::check_references(&updated)?;
+    *current = updated;
+    Ok(())
+  }
+
+  /// Adds the math channels defined in the file at `path`, see
+  /// `MathChannel::load` and `add_math_channels`.
+  pub fn load_math_channels(&self, path: &Path) -> Result<()> {
+    self.add_math_channels(MathChannel::load(path)?)
+  }
+
+  /// Math channels added to this run, in the order they were added.
+  pub fn math_channels(&self) -> Vec<MathChannel> {
+    self.math_channels.read().unwrap().clone()
+  }
+
+  /// Removes all math channels from this run.
+  pub fn clear_math_channels(&self) {
+    self.math_channels.write().unwrap().clear();
   }
 
   /// For channel with id `channel_id`, collect the measurement samples in a
@@ -367,42 +870,173 @@ impl Run {
                       channel_idx: usize,
                       lap_idx: Option<usize>)
                       -> Result<ChannelData> {
-    if let Some(lap_idx) = lap_idx {
-      if channel_idx < self.channels_count {
-        self.lap_channel_samples(lap_idx, channel_idx)
-      } else {
-        let channel_idx = channel_idx % self.channels_count;
-        self.lap_gps_channel_samples(lap_idx, channel_idx)
-      }
+    if channel_idx < self.channels_count {
+      self.samples(ChannelGroup::Regular, channel_idx, lap_idx)
     } else {
-      if channel_idx < self.channels_count {
-        self.channel_samples(channel_idx)
-      } else {
-        let channel_idx = channel_idx % self.channels_count;
-        self.gps_channel_samples(channel_idx)
+      let channel_idx = channel_idx - self.channels_count;
+      self.samples(ChannelGroup::Gps, channel_idx, lap_idx)
+    }
+  }
+
+  // DATA SOURCE ACCESS ---------------------------------------------------- //
+  /// Errors if there is no lap with index `lap_idx`.
+  fn check_lap(&self, lap_idx: usize) -> Result<()> {
+    if lap_idx >= self.number_of_laps {
+      return Err(Error::LapOutOfRange { path:  self.path.clone(),
+                                        lap:   lap_idx,
+                                        count: self.number_of_laps, });
+    }
+    Ok(())
+  }
+
+  /// Errors if there is no channel with index `channel_idx`, where regular
+  /// and GPS channels are counted together like in `channel_names`.
+  fn check_channel(&self, channel_idx: usize) -> Result<()> {
+    if channel_idx >= self.number_of_channels {
+      return Err(Error::ChannelOutOfRange { path:    self.path.clone(),
+                                            group:   ChannelGroup::Regular,
+                                            channel: channel_idx,
+                                            count:
+                                              self.number_of_channels, });
+    }
+    Ok(())
+  }
+
+  /// Maps an index into the channels in the groups in `set` to a channel
+  /// group and the index within it.
+  fn resolve(&self,
+             channel_idx: usize,
+             set: ChannelSet)
+             -> Result<(ChannelGroup, usize)> {
+    let mut idx = channel_idx;
+    for group in set.groups() {
+      let count = self.group_count(group);
+      if idx < count {
+        return Ok((group, idx));
+      }
+      idx -= count;
+    }
+    Err(Error::ChannelOutOfRange { path:    self.path.clone(),
+                                   group:   ChannelGroup::Regular,
+                                   channel: channel_idx,
+                                   count:   self.number_of_channels_in(set), })
+  }
+
+  /// Name of a channel in channel group `group`, from the cached table
+  /// unless it is a GPS raw channel.
+  fn name_in(&self,
+             group: ChannelGroup,
+             channel_idx: usize)
+             -> Result<String> {
+    match group {
+      ChannelGroup::Regular => self.channel_name(channel_idx),
+      ChannelGroup::Gps => {
+        self.channel_name(self.channels_count + channel_idx)
       }
+      ChannelGroup::GpsRaw => self.gps_raw_channel_name(channel_idx),
     }
   }
 
+  /// Unit of a channel in channel group `group`, from the cached table
+  /// unless it is a GPS raw channel.
+  fn unit_in(&self,
+             group: ChannelGroup,
+             channel_idx: usize)
+             -> Result<String> {
+    match group {
+      ChannelGroup::Regular => self.channel_unit(channel_idx),
+      ChannelGroup::Gps => {
+        self.channel_unit(self.channels_count + channel_idx)
+      }
+      ChannelGroup::GpsRaw => self.gps_raw_channel_unit(channel_idx),
+    }
+  }
+
+  /// Errors if there is no channel with index `channel_idx` in channel group
+  /// `group`.
+  fn check_group_channel(&self,
+                         group: ChannelGroup,
+                         channel_idx: usize)
+                         -> Result<()> {
+    let count = self.group_count(group);
+    if channel_idx >= count {
+      return Err(Error::ChannelOutOfRange { path: self.path.clone(),
+                                            group,
+                                            channel: channel_idx,
+                                            count });
+    }
+    Ok(())
+  }
+
+  /// Number of channels in channel group `group`.
+  fn group_count(&self, group: ChannelGroup) -> usize {
+    match group {
+      ChannelGroup::Regular => self.channels_count,
+      ChannelGroup::Gps => self.gps_channels_count,
+      ChannelGroup::GpsRaw => self.gps_raw_channels_count,
+    }
+  }
+
+  /// Range checked request for the number of samples of a channel in channel
+  /// group `group`, either for the whole `Run` or a single lap.
+  fn samples_count(&self,
+                   group: ChannelGroup,
+                   channel_idx: usize,
+                   lap_idx: Option<usize>)
+                   -> Result<usize> {
+    if let Some(lap_idx) = lap_idx {
+      self.check_lap(lap_idx)?;
+    }
+    self.check_group_channel(group, channel_idx)?;
+
+    let count =
+      self.handle.with(|source| {
+                    source.channel_samples_count(group, channel_idx, lap_idx)
+                  })?;
+    if count == 0 {
+      return Err(Error::EmptyChannel { path: self.path.clone(),
+                                       group,
+                                       channel: channel_idx,
+                                       lap: lap_idx });
+    }
+    Ok(count)
+  }
+
+  /// Range checked request for the samples of a channel in channel group
+  /// `group`, either for the whole `Run` or a single lap.
+  fn samples(&self,
+             group: ChannelGroup,
+             channel_idx: usize,
+             lap_idx: Option<usize>)
+             -> Result<ChannelData> {
+    self.samples_count(group, channel_idx, lap_idx)?;
+    self.handle
+        .with(|source| source.channel_samples(group, channel_idx, lap_idx))
+  }
+
+  /// Range checked request for the name of a channel in channel group
+  /// `group`.
+  fn name(&self, group: ChannelGroup, channel_idx: usize) -> Result<String> {
+    self.check_group_channel(group, channel_idx)?;
+    self.handle
+        .with(|source| source.channel_name(group, channel_idx))
+  }
+
+  /// Range checked request for the unit of a channel in channel group
+  /// `group`.
+  fn unit(&self, group: ChannelGroup, channel_idx: usize) -> Result<String> {
+    self.check_group_channel(group, channel_idx)?;
+    self.handle
+        .with(|source| source.channel_unit(group, channel_idx))
+  }
+
   // ----------------------------------------------------------------------- //
   // RAW LIBRARY FUNCTIONS ------------------------------------------------- //
   // ----------------------------------------------------------------------- //
   /// For channel with index `channel_idx`, request the number of samples
   /// contained in this `Run`.
   pub fn channel_samples_count(&self, channel_idx: usize) -> Result<usize> {
-    ensure!(channel_idx < self.channels_count,
-            "channel_idx out of range");
-
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let count = unsafe {
-      aim::get_channel_samples_count(self.idx as i32, channel_idx as i32)
-    };
-
-    match count.cmp(&0) {
-      Ordering::Greater => Ok(count as usize),
-      Ordering::Equal => bail!("channel contains 0 samples"),
-      Ordering::Less => bail!("error getting channel samples count"),
-    }
+    self.samples_count(ChannelGroup::Regular, channel_idx, None)
   }
 
   /// For channel with index `channel_idx`, request the samples contained in
@@ -413,22 +1047,7 @@ impl Run {
   /// returns a `&Vec<f64>`) and a corresponding set of samples (the
   /// `samples()` getter returns another `&Vec<f64>`).
   pub fn channel_samples(&self, channel_idx: usize) -> Result<ChannelData> {
-    ensure!(channel_idx < self.channels_count,
-            "channel_idx out of range");
-
-    let count = self.channel_samples_count(channel_idx)?;
-    let (mut timestamps, mut samples) = ChannelData::allocate(count);
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let read = unsafe {
-      aim::get_channel_samples(self.idx as i32,
-                               channel_idx as i32,
-                               timestamps.as_mut_ptr(),
-                               samples.as_mut_ptr(),
-                               count as i32)
-    };
-    ensure!(read == count as i32, "error reading channel samples");
-
-    Ok(ChannelData::from_tsc(timestamps, samples, count))
+    self.samples(ChannelGroup::Regular, channel_idx, None)
   }
 
   /// For lap with index `lap_idx` and channel with index `channel_idx`,
@@ -437,21 +1056,7 @@ impl Run {
                                    lap_idx: usize,
                                    channel_idx: usize)
                                    -> Result<usize> {
-    ensure!(lap_idx < self.number_of_laps, "lap_idx out of range");
-    ensure!(channel_idx < self.channels_count,
-            "channel_idx out of range");
-
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let count = unsafe {
-      aim::get_lap_channel_samples_count(self.idx as i32,
-                                         lap_idx as i32,
-                                         channel_idx as i32)
-    };
-    match count.cmp(&0) {
-      Ordering::Greater => Ok(count as usize),
-      Ordering::Equal => bail!("channel contains 0 samples in this lap"),
-      Ordering::Less => bail!("error getting lap channel samples count"),
-    }
+    self.samples_count(ChannelGroup::Regular, channel_idx, Some(lap_idx))
   }
 
   /// For lap with index `lap_idx` and channel with index `channel_idx`,
@@ -465,24 +1070,7 @@ impl Run {
                              lap_idx: usize,
                              channel_idx: usize)
                              -> Result<ChannelData> {
-    ensure!(lap_idx < self.number_of_laps, "lap_idx out of range");
-    ensure!(channel_idx < self.channels_count,
-            "channel_idx out of range");
-
-    let count = self.lap_channel_samples_count(lap_idx, channel_idx)?;
-    let (mut timestamps, mut samples) = ChannelData::allocate(count);
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let read = unsafe {
-      aim::get_lap_channel_samples(self.idx as i32,
-                                   lap_idx as i32,
-                                   channel_idx as i32,
-                                   timestamps.as_mut_ptr(),
-                                   samples.as_mut_ptr(),
-                                   count as i32)
-    };
-    ensure!(read == count as i32, "error reading lap channel samples");
-
-    Ok(ChannelData::from_tsc(timestamps, samples, count))
+    self.samples(ChannelGroup::Regular, channel_idx, Some(lap_idx))
   }
 
   // GPS INFORMATION FUNCTIONS --------------------------------------------- //
@@ -493,24 +1081,12 @@ impl Run {
   //
   /// For GPS channel with index `channel_idx`, request the channel name.
   pub fn gps_channel_name(&self, channel_idx: usize) -> Result<String> {
-    ensure!(channel_idx < self.gps_channels_count,
-            "channel_idx out of range");
-
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    util::strptr_to_string(unsafe {
-      aim::get_GPS_channel_name(self.idx as i32, channel_idx as i32)
-    })
+    self.name(ChannelGroup::Gps, channel_idx)
   }
 
   /// For GPS channel with index `channel_idx`, request the GPS channel unit.
   pub fn gps_channel_unit(&self, channel_idx: usize) -> Result<String> {
-    ensure!(channel_idx < self.gps_channels_count,
-            "channel_idx out of range");
-
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    util::strptr_to_string(unsafe {
-      aim::get_GPS_channel_units(self.idx as i32, channel_idx as i32)
-    })
+    self.unit(ChannelGroup::Gps, channel_idx)
   }
 
   /// For GPS channel with index `channel_idx`, request the number of samples
@@ -518,19 +1094,7 @@ impl Run {
   pub fn gps_channel_samples_count(&self,
                                    channel_idx: usize)
                                    -> Result<usize> {
-    ensure!(channel_idx < self.gps_channels_count,
-            "channel_idx out of range");
-
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let count = unsafe {
-      aim::get_GPS_channel_samples_count(self.idx as i32, channel_idx as i32)
-    };
-
-    match count.cmp(&0) {
-      Ordering::Greater => Ok(count as usize),
-      Ordering::Equal => bail!("GPS channel contains 0 samples"),
-      Ordering::Less => bail!("error getting GPS channel samples count"),
-    }
+    self.samples_count(ChannelGroup::Gps, channel_idx, None)
   }
 
   /// For GPS channel with index `channel_idx`, request the samples contained
@@ -543,22 +1107,7 @@ impl Run {
   pub fn gps_channel_samples(&self,
                              channel_idx: usize)
                              -> Result<ChannelData> {
-    ensure!(channel_idx < self.gps_channels_count,
-            "channel_idx out of range");
-
-    let count = self.gps_channel_samples_count(channel_idx)?;
-    let (mut timestamps, mut samples) = ChannelData::allocate(count);
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let read = unsafe {
-      aim::get_GPS_channel_samples(self.idx as i32,
-                                   channel_idx as i32,
-                                   timestamps.as_mut_ptr(),
-                                   samples.as_mut_ptr(),
-                                   count as i32)
-    };
-    ensure!(read == count as i32, "error reading GPS channel samples");
-
-    Ok(ChannelData::from_tsc(timestamps, samples, count))
+    self.samples(ChannelGroup::Gps, channel_idx, None)
   }
 
   /// For lap with index `lap_idx` and GPS channel with index `channel_idx`,
@@ -567,21 +1116,7 @@ impl Run {
                                        lap_idx: usize,
                                        channel_idx: usize)
                                        -> Result<usize> {
-    ensure!(lap_idx < self.number_of_laps, "lap_idx out of range");
-    ensure!(channel_idx < self.gps_channels_count,
-            "channel_idx out of range");
-
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let count = unsafe {
-      aim::get_lap_GPS_channel_samples_count(self.idx as i32,
-                                             lap_idx as i32,
-                                             channel_idx as i32)
-    };
-    match count.cmp(&0) {
-      Ordering::Greater => Ok(count as usize),
-      Ordering::Equal => bail!("GPS channel contains 0 samples in this lap"),
-      Ordering::Less => bail!("error getting lap GPS channel samples count"),
-    }
+    self.samples_count(ChannelGroup::Gps, channel_idx, Some(lap_idx))
   }
 
   /// For lap with index `lap_idx` and GPS channel with index `channel_idx`,
@@ -595,24 +1130,7 @@ impl Run {
                                  lap_idx: usize,
                                  channel_idx: usize)
                                  -> Result<ChannelData> {
-    ensure!(lap_idx < self.number_of_laps, "lap_idx out of range");
-    ensure!(channel_idx < self.gps_channels_count,
-            "channel_idx out of range");
-
-    let count = self.lap_gps_channel_samples_count(lap_idx, channel_idx)?;
-    let (mut timestamps, mut samples) = ChannelData::allocate(count);
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let read = unsafe {
-      aim::get_lap_GPS_channel_samples(self.idx as i32,
-                                       lap_idx as i32,
-                                       channel_idx as i32,
-                                       timestamps.as_mut_ptr(),
-                                       samples.as_mut_ptr(),
-                                       count as i32)
-    };
-    ensure!(read == count as i32, "error reading GPS channel samples");
-
-    Ok(ChannelData::from_tsc(timestamps, samples, count))
+    self.samples(ChannelGroup::Gps, channel_idx, Some(lap_idx))
   }
 
   // ----------------------------------------------------------------------- //
@@ -620,55 +1138,30 @@ impl Run {
   // ----------------------------------------------------------------------- //
   /// For GPS raw channel with index `channel_idx`, request the channel name.
   ///
-  /// THIS SHOULD NEVER BE USED DIRECTLY AND IS ONLY PROVIDED AS AN INTERFACE
-  /// TO THE UNDERLYING LIBRARY FUNCTION.
+  /// To get GPS raw channels along with the others, use `channel_in` or
+  /// `lap_in` with a `ChannelSet` including them instead.
   pub fn gps_raw_channel_name(&self, channel_idx: usize) -> Result<String> {
-    ensure!(channel_idx < self.gps_raw_channels_count,
-            "channel_idx out of range");
-
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    util::strptr_to_string(unsafe {
-      aim::get_GPS_raw_channel_name(self.idx as i32, channel_idx as i32)
-    })
+    self.name(ChannelGroup::GpsRaw, channel_idx)
   }
 
   /// For GPS raw channel with index `channel_idx`, request the GPS channel
   /// unit.
   ///
-  /// THIS SHOULD NEVER BE USED DIRECTLY AND IS ONLY PROVIDED AS AN INTERFACE
-  /// TO THE UNDERLYING LIBRARY FUNCTION.
+  /// To get GPS raw channels along with the others, use `channel_in` or
+  /// `lap_in` with a `ChannelSet` including them instead.
   pub fn gps_raw_channel_unit(&self, channel_idx: usize) -> Result<String> {
-    ensure!(channel_idx < self.gps_raw_channels_count,
-            "channel_idx out of range");
-
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    util::strptr_to_string(unsafe {
-      aim::get_GPS_raw_channel_units(self.idx as i32, channel_idx as i32)
-    })
+    self.unit(ChannelGroup::GpsRaw, channel_idx)
   }
 
   /// For GPS raw channel with index `channel_idx`, request the number of
   /// samples contained in this `Run`.
   ///
-  /// THIS SHOULD NEVER BE USED DIRECTLY AND IS ONLY PROVIDED AS AN INTERFACE
-  /// TO THE UNDERLYING LIBRARY FUNCTION.
+  /// To get GPS raw channels along with the others, use `channel_in` or
+  /// `lap_in` with a `ChannelSet` including them instead.
   pub fn gps_raw_channel_samples_count(&self,
                                        channel_idx: usize)
                                        -> Result<usize> {
-    ensure!(channel_idx < self.gps_raw_channels_count,
-            "channel_idx out of range");
-
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let count = unsafe {
-      aim::get_GPS_raw_channel_samples_count(self.idx as i32,
-                                             channel_idx as i32)
-    };
-
-    match count.cmp(&0) {
-      Ordering::Greater => Ok(count as usize),
-      Ordering::Equal => bail!("GPS channel contains 0 samples"),
-      Ordering::Less => bail!("error getting GPS channel samples count"),
-    }
+    self.samples_count(ChannelGroup::GpsRaw, channel_idx, None)
   }
 
   /// For GPS raw channel with index `channel_idx`, request the samples
@@ -679,54 +1172,25 @@ impl Run {
   /// returns a `&Vec<f64>`) and a corresponding set of samples (the
   /// `samples()` getter returns another `&Vec<f64>`).
   ///
-  /// THIS SHOULD NEVER BE USED DIRECTLY AND IS ONLY PROVIDED AS AN INTERFACE
-  /// TO THE UNDERLYING LIBRARY FUNCTION.
+  /// To get GPS raw channels along with the others, use `channel_in` or
+  /// `lap_in` with a `ChannelSet` including them instead.
   pub fn gps_raw_channel_samples(&self,
                                  channel_idx: usize)
                                  -> Result<ChannelData> {
-    ensure!(channel_idx < self.gps_raw_channels_count,
-            "channel_idx out of range");
-
-    let count = self.gps_raw_channel_samples_count(channel_idx)?;
-    let (mut timestamps, mut samples) = ChannelData::allocate(count);
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let read = unsafe {
-      aim::get_GPS_raw_channel_samples(self.idx as i32,
-                                       channel_idx as i32,
-                                       timestamps.as_mut_ptr(),
-                                       samples.as_mut_ptr(),
-                                       count as i32)
-    };
-    ensure!(read == count as i32, "error reading GPS channel samples");
-
-    Ok(ChannelData::from_tsc(timestamps, samples, count))
+    self.samples(ChannelGroup::GpsRaw, channel_idx, None)
   }
 
   /// For lap with index `lap_idx` and GPS raw channel with index
   /// `channel_idx`, request the number of samples contained in this
   /// `Run`.
   ///
-  /// THIS SHOULD NEVER BE USED DIRECTLY AND IS ONLY PROVIDED AS AN INTERFACE
-  /// TO THE UNDERLYING LIBRARY FUNCTION.
+  /// To get GPS raw channels along with the others, use `channel_in` or
+  /// `lap_in` with a `ChannelSet` including them instead.
   pub fn lap_gps_raw_channel_samples_count(&self,
                                            lap_idx: usize,
                                            channel_idx: usize)
                                            -> Result<usize> {
-    ensure!(lap_idx < self.number_of_laps, "lap_idx out of range");
-    ensure!(channel_idx < self.gps_raw_channels_count,
-            "channel_idx out of range");
-
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let count = unsafe {
-      aim::get_lap_GPS_raw_channel_samples_count(self.idx as i32,
-                                                 lap_idx as i32,
-                                                 channel_idx as i32)
-    };
-    match count.cmp(&0) {
-      Ordering::Greater => Ok(count as usize),
-      Ordering::Equal => bail!("GPS channel contains 0 samples in this lap"),
-      Ordering::Less => bail!("error getting lap GPS channel samples count"),
-    }
+    self.samples_count(ChannelGroup::GpsRaw, channel_idx, Some(lap_idx))
   }
 
   /// For lap with index `lap_idx` and GPS raw channel with index
@@ -737,30 +1201,13 @@ impl Run {
   /// returns a `&Vec<f64>`) and a corresponding set of samples (the
   /// `samples()` getter returns another `&Vec<f64>`).
   ///
-  /// THIS SHOULD NEVER BE USED DIRECTLY AND IS ONLY PROVIDED AS AN INTERFACE
-  /// TO THE UNDERLYING LIBRARY FUNCTION.
+  /// To get GPS raw channels along with the others, use `channel_in` or
+  /// `lap_in` with a `ChannelSet` including them instead.
   pub fn lap_gps_raw_channel_samples(&self,
                                      lap_idx: usize,
                                      channel_idx: usize)
                                      -> Result<ChannelData> {
-    ensure!(lap_idx < self.number_of_laps, "lap_idx out of range");
-    ensure!(channel_idx < self.gps_raw_channels_count,
-            "channel_idx out of range");
-
-    let count = self.lap_gps_raw_channel_samples_count(lap_idx, channel_idx)?;
-    let (mut timestamps, mut samples) = ChannelData::allocate(count);
-    let _guard = LIBCALL_MTX.lock().unwrap();
-    let read = unsafe {
-      aim::get_lap_GPS_raw_channel_samples(self.idx as i32,
-                                           lap_idx as i32,
-                                           channel_idx as i32,
-                                           timestamps.as_mut_ptr(),
-                                           samples.as_mut_ptr(),
-                                           count as i32)
-    };
-    ensure!(read == count as i32, "error reading GPS channel samples");
-
-    Ok(ChannelData::from_tsc(timestamps, samples, count))
+    self.samples(ChannelGroup::GpsRaw, channel_idx, Some(lap_idx))
   }
   // ----------------------------------------------------------------------- //
 }
@@ -769,18 +1216,31 @@ impl Run {
 
 #[cfg(test)]
 mod tests {
-  use super::*;
+  use super::{super::{GeoPoint,
+                      MemorySource,
+                      PeriodKind,
+                      Statistic,
+                      SummaryTable,
+                      Trigger},
+              *};
+  use chrono::NaiveDate;
   use pretty_assertions::assert_eq;
+  #[cfg(feature = "aim")]
   use std::fs;
 
 
   const XRK_PATH: &str =
     "./testdata/032/TCR_EU-21_E02-LCA_Q1_AU-RS3-R5-S-S_032_A_1375.xrk";
 
+  #[cfg(not(feature = "aim"))]
+  const NATIVE_XRK_PATH: &str =
+    "./testdata/072/TCR_EU-21_E02-LCA_Q1_HO-FK7-M5-X-S_072_A_1252.xrk";
+
   #[cfg(target_family = "windows")]
   const DRK_PATH: &str =
     "./testdata/032/TCR_EU-21_E02-LCA_Q1_AU-RS3-R5-S-S_032_A_1375.drk";
 
+  #[cfg(feature = "aim")]
   #[test]
   #[ignore]
   fn drop_test() {
@@ -942,7 +1402,7 @@ mod tests {
                                    "",
                                    "",];
 
-    assert_eq!(&channel_names, xdrk_file.channel_names());
+    assert_eq!(&channel_names, xdrk_file.channel_names().unwrap());
 
     assert_eq!("Logger Temperature", &xdrk_file.channel_name(0).unwrap());
     assert_eq!("pManifoldScrut", &xdrk_file.channel_name(2).unwrap());
@@ -1190,7 +1650,7 @@ mod tests {
                                    "GPS_PosAccuracy",
                                    "GPS_SpdAccuracy",];
 
-    assert_eq!(&channel_names, xdrk_file.channel_names());
+    assert_eq!(&channel_names, xdrk_file.channel_names().unwrap());
 
     assert_eq!("Logger Temperature", &xdrk_file.channel_name(0).unwrap());
     assert_eq!("pManifoldScrut", &xdrk_file.channel_name(2).unwrap());
@@ -1294,6 +1754,7 @@ mod tests {
     }
   }
 
+  #[cfg(feature = "aim")]
   #[test]
   fn meta_fn() {
     let (date, time) = {
@@ -1314,4 +1775,443 @@ mod tests {
     assert_eq!(date, Run::library_date().unwrap());
     assert_eq!(time, Run::library_time().unwrap());
   }
+
+  #[test]
+  fn memory_source_test() {
+    let mut source = MemorySource::new(vec![LapInfo::new(0, 0.0, 1.0),
+                                            LapInfo::new(1, 1.0, 1.0)]);
+    source.set_racer("072".to_string())
+          .add_channel(ChannelGroup::Regular,
+                       Channel::new("fEngRpm".to_string(),
+                                    "rpm".to_string(),
+                                    ChannelData::new(vec![0.0, 0.5, 1.0],
+                                                     vec![7e3, 7.5e3, 8e3])))
+          .add_channel(ChannelGroup::Gps,
+                       Channel::new("GPS Speed".to_string(),
+                                    "m/s".to_string(),
+                                    ChannelData::new(vec![1.0, 1.5],
+                                                     vec![40.0, 42.0])));
+
+    let run = Run::from_source(source).unwrap();
+    assert_eq!("072", &run.racer().unwrap());
+    assert_eq!(2, run.number_of_laps());
+    assert_eq!(2, run.number_of_channels());
+    assert_eq!(&vec!["fEngRpm".to_string(), "GPS Speed".to_string()],
+               run.channel_names().unwrap());
+    assert_eq!(2, run.gps_channel_samples_count(0).unwrap());
+    assert_eq!(2, run.lap_channel_samples_count(0, 0).unwrap());
+
+    let lap = run.lap(1).unwrap();
+    assert_eq!(vec![8e3], *lap.channel("fEngRpm").unwrap().data().samples());
+    assert_eq!(vec![40.0, 42.0],
+               *lap.channel("GPS Speed").unwrap().data().samples());
+
+    assert_eq!(true,
+               matches!(run.lap_channel_samples_count(2, 0),
+                        Err(Error::LapOutOfRange { lap: 2,
+                                                   count: 2,
+                                                   .. })));
+    assert_eq!(true,
+               matches!(run.gps_raw_channel_samples(0),
+                        Err(Error::ChannelOutOfRange { channel: 0,
+                                                       count: 0,
+                                                       .. })));
+    assert_eq!(true,
+               matches!(run.lap_gps_channel_samples(0, 0),
+                        Err(Error::EmptyChannel { lap: Some(0), .. })));
+    assert_eq!(true,
+               matches!(run.channel_idx("fEngTemp"),
+                        Err(Error::ChannelNotFound { .. })));
+  }
+
+  #[test]
+  fn channel_set_test() {
+    let channel = |name: &str, unit: &str| {
+      Channel::new(name.to_string(),
+                   unit.to_string(),
+                   ChannelData::new(vec![0.0, 0.5], vec![1.0, 2.0]))
+    };
+    let mut source = MemorySource::new(vec![LapInfo::new(0, 0.0, 1.0)]);
+    source.add_channel(ChannelGroup::Regular, channel("fEngRpm", "rpm"))
+          .add_channel(ChannelGroup::Gps, channel("GPS Speed", "m/s"))
+          .add_channel(ChannelGroup::GpsRaw, channel("ECEF position_X", "m"))
+          .add_channel(ChannelGroup::GpsRaw, channel("ECEF position_Y", "m"));
+    let run = Run::from_source(source).unwrap();
+
+    assert_eq!(2, run.number_of_channels());
+    assert_eq!(4, run.number_of_channels_in(ChannelSet::ALL));
+    let set =
+      ChannelSet::only(ChannelGroup::Regular).with(ChannelGroup::GpsRaw);
+    assert_eq!(vec!["fEngRpm".to_string(),
+                    "ECEF position_X".to_string(),
+                    "ECEF position_Y".to_string()],
+               run.channel_names_in(set).unwrap());
+    assert_eq!(ChannelSet::DEFAULT,
+               ChannelSet::ALL.without(ChannelGroup::GpsRaw));
+    assert_eq!(vec!["m".to_string(), "m".to_string()],
+               run.channel_units_in(ChannelGroup::GpsRaw.into()).unwrap());
+    assert_eq!(3,
+               run.channel_idx_in("ECEF position_Y", ChannelSet::ALL)
+                  .unwrap());
+    assert_eq!(true, run.channel_idx("ECEF position_Y").is_err());
+
+    // GPS raw channels follow the others
+    let raw = run.channel_in(2, None, ChannelSet::ALL).unwrap();
+    assert_eq!("ECEF position_X", raw.name());
+    assert_eq!("m", raw.unit());
+    assert_eq!(run.channel(1, None).unwrap(),
+               run.channel_in(1, None, ChannelSet::ALL).unwrap());
+    assert_eq!(true,
+               matches!(run.channel_in(4, None, ChannelSet::ALL),
+                        Err(Error::ChannelOutOfRange { channel: 4,
+                                                       count: 4,
+                                                       .. })));
+
+    let lap = run.lap_in(0, ChannelSet::ALL).unwrap();
+    assert_eq!(4, lap.data().len());
+    assert_eq!(Some(&vec![1.0, 2.0]),
+               lap.channel("ECEF position_Y").map(|c| c.data().samples()));
+    assert_eq!(None, run.lap(0).unwrap().channel("ECEF position_Y"));
+  }
+
+  #[test]
+  fn split_test() {
+    // two and a half laps east and back along a parallel at one sample per
+    // second, with the logger knowing of a single lap
+    let longitudes = (0..10).chain((1..10).rev())
+                            .chain(0..10)
+                            .chain((5..10).rev())
+                            .map(|i| 8.0 + i as f64 * 1e-4)
+                            .collect::<Vec<_>>();
+    let timestamps = (0..longitudes.len()).map(|i| i as f64)
+                                          .collect::<Vec<_>>();
+    let mut source =
+      MemorySource::new(vec![LapInfo::new(0, 0.0, longitudes.len() as f64)]);
+    source.add_channel(ChannelGroup::Regular,
+                       Channel::new("fEngRpm".to_string(),
+                                    "rpm".to_string(),
+                                    ChannelData::new(timestamps.clone(),
+                                                     vec![7e3;
+                                                          timestamps.len()])));
+    for (axis, name) in ECEF_POSITIONS.iter().enumerate() {
+      let positions = longitudes.iter()
+                                .map(|&lon| {
+                                  GeoPoint::new(50.0, lon).to_ecef()[axis]
+                                })
+                                .collect();
+      source.add_channel(ChannelGroup::GpsRaw,
+                         Channel::new(name.to_string(),
+                                      "m".to_string(),
+                                      ChannelData::new(timestamps.clone(),
+                                                       positions)));
+    }
+    let run = Run::from_source(source).unwrap();
+
+    // crossed eastbound at 2.5 s and 21.5 s; crossing westbound at 16.5 s
+    // doesn't start a lap
+    let finish = Gate::new(GeoPoint::new(50.001, 8.000_25),
+                           GeoPoint::new(49.999, 8.000_25));
+    let laps = run.split_at(&finish).unwrap();
+    assert_eq!(3, laps.len());
+    assert_eq!(true, (laps[1].start() - 2.5).abs() < 1e-3);
+    assert_eq!(true, (laps[1].time() - 19.0).abs() < 1e-3);
+
+    // the run itself keeps the logger's laps
+    assert_eq!(1, run.number_of_laps());
+    assert_eq!(true,
+               matches!(run.lap(1),
+                        Err(Error::LapOutOfRange { count: 1, .. })));
+    let lap = run.lap_with(&laps[1]).unwrap();
+    assert_eq!(&laps[1], lap.info());
+    assert_eq!(19, lap.channel("fEngRpm").unwrap().len());
+    let lap = run.lap_with_in(&laps[2], ChannelSet::ALL).unwrap();
+    assert_eq!(12, lap.channel("ECEF position_X").unwrap().len());
+
+    assert_eq!(true,
+               matches!(run.split_at(&Gate::new(GeoPoint::new(50.001, 7.0),
+                                                GeoPoint::new(49.999, 7.0))),
+                        Err(Error::InvalidArgument { .. })));
+    assert_eq!(1, run.number_of_laps());
+  }
+
+  #[test]
+  fn classify_test() {
+    let laps = (0..4).map(|idx| LapInfo::new(idx, idx as f64 * 10.0, 10.0))
+                     .collect();
+    let mut source = MemorySource::new(laps);
+    let timestamps = (0..40).map(f64::from).collect::<Vec<_>>();
+    let fcy = timestamps.iter()
+                        .map(|t| {
+                          if (25.0..28.0).contains(t) {
+                            1.0
+                          } else {
+                            0.0
+                          }
+                        })
+                        .collect();
+    source.add_channel(ChannelGroup::Regular,
+                       Channel::new("swRotFcy".to_string(),
+                                    "#".to_string(),
+                                    ChannelData::new(timestamps.clone(), fcy)))
+          .add_channel(ChannelGroup::Gps,
+                       Channel::new("GPS Speed".to_string(),
+                                    "km/h".to_string(),
+                                    ChannelData::new(timestamps,
+                                                     vec![150.0; 40])));
+    let run = Run::from_source(source).unwrap();
+
+    let laps = run.classify_laps(&Classifier::default()).unwrap();
+    assert_eq!(vec![Some(LapKind::Out),
+                    Some(LapKind::Flying),
+                    Some(LapKind::Flying),
+                    Some(LapKind::Incomplete)],
+               laps.iter().map(LapInfo::kind).collect::<Vec<_>>());
+    let flying = run.flying_laps(&laps)
+                    .collect::<Result<Vec<_>>>()
+                    .unwrap();
+    assert_eq!(vec![1, 2], flying.iter().map(Lap::idx).collect::<Vec<_>>());
+    assert_eq!(Some(LapKind::Flying), flying[1].kind());
+    // the run itself is left as is
+    assert_eq!(None, run.lap_info(1).unwrap().kind());
+    assert_eq!(None, run.lap(2).unwrap().kind());
+
+    // a full course yellow in lap 2 neutralises it
+    let periods = run.detect_periods(&PeriodDetector::default()).unwrap();
+    assert_eq!(vec![Period::new(PeriodKind::FullCourseYellow, 25.0, 27.0)],
+               periods);
+    let laps = laps.into_iter()
+                   .map(|info| info.with_neutralised_by(&periods))
+                   .collect::<Vec<_>>();
+    assert_eq!(vec![false, false, true, false],
+               laps.iter().map(LapInfo::neutralised).collect::<Vec<_>>());
+    assert_eq!(false, run.lap_info(2).unwrap().neutralised());
+    let flying = run.flying_laps(&laps)
+                    .map(|lap| lap.unwrap().idx())
+                    .collect::<Vec<_>>();
+    assert_eq!(vec![1], flying);
+  }
+
+  #[test]
+  fn events_test() {
+    let mut source = MemorySource::new(vec![LapInfo::new(0, 0.0, 2.0),
+                                            LapInfo::new(1, 2.0, 2.0)]);
+    let gear = ChannelData::new(vec![0.0, 1.0, 2.0, 3.0],
+                                vec![2.0, 3.0, 3.0, 4.0]);
+    source.add_channel(ChannelGroup::Regular,
+                       Channel::new("posGear".to_string(),
+                                    "#".to_string(),
+                                    gear));
+    let run = Run::from_source(source).unwrap();
+    let upshift = EventDetector::new(Trigger::RisingEdge);
+
+    let events = run.events(0, None, &upshift).unwrap();
+    assert_eq!(vec![Event::new(1, 1.0, 3.0).with_lap(0),
+                    Event::new(3, 3.0, 4.0).with_lap(1)],
+               events);
+    assert_eq!(vec![Some(1)],
+               run.events(0, Some(1), &upshift)
+                  .unwrap()
+                  .iter()
+                  .map(Event::lap)
+                  .collect::<Vec<_>>());
+    // in the lap's channel, the sample index is relative to the lap start
+    assert_eq!(vec![Event::new(1, 3.0, 4.0).with_lap(1)],
+               run.lap(1).unwrap().events("posGear", &upshift).unwrap());
+    assert_eq!(true,
+               matches!(run.lap(1).unwrap().events("fEngRpm", &upshift),
+                        Err(Error::ChannelNotFound { .. })));
+  }
+
+  #[test]
+  fn math_channels_test() {
+    let mut source = MemorySource::new(vec![LapInfo::new(0, 0.0, 2.0),
+                                            LapInfo::new(1, 2.0, 2.0)]);
+    let pressure = |name: &str, samples: Vec<f64>| {
+      Channel::new(name.to_string(),
+                   "bar".to_string(),
+                   ChannelData::new(vec![0.0, 1.0, 2.0, 3.0], samples))
+    };
+    source.add_channel(ChannelGroup::Regular,
+                       pressure("pBrakeF", vec![6.0, 3.0, 0.0, 9.0]));
+    source.add_channel(ChannelGroup::Regular,
+                       pressure("pBrakeR", vec![4.0, 1.0, 1.0, 1.0]));
+    let run = Run::from_source(source).unwrap();
+    let math = |name: &str, unit: &str, expression: &str| {
+      MathChannel::new(name.to_string(),
+                       unit.to_string(),
+                       Expression::parse(expression).unwrap())
+    };
+    run.add_math_channels(vec![math("Brake Balance",
+                                    "%",
+                                    "pBrakeF / (pBrakeF + pBrakeR) * 100"),
+                               math("Balance Offset",
+                                    "%",
+                                    "\"Brake Balance\" - 50"),
+                               math("pBrakeR", "bar", "0")])
+       .unwrap();
+
+    // math channels follow the channels of the file, unless shadowed by one
+    assert_eq!(vec!["pBrakeF", "pBrakeR", "Brake Balance", "Balance Offset"],
+               run.all_channel_names().unwrap());
+    assert_eq!(vec!["bar", "bar", "%", "%"],
+               run.all_channel_units().unwrap());
+    // while names in `channel_names` keep matching the channel indices
+    assert_eq!(vec!["pBrakeF", "pBrakeR"], *run.channel_names().unwrap());
+    assert_eq!(2, run.number_of_channels());
+    assert_eq!(true,
+               matches!(run.channel_idx("Brake Balance"),
+                        Err(Error::ChannelNotFound { .. })));
+
+    let offset = run.channel_by_name("Balance Offset", None).unwrap();
+    assert_eq!("%", offset.unit());
+    assert_eq!(&vec![10.0, 25.0, -50.0, 40.0], offset.data().samples());
+    let balance = run.channel_by_name("Brake Balance", Some(1)).unwrap();
+    assert_eq!(&vec![2.0, 3.0], balance.data().timestamps());
+    assert_eq!(&vec![0.0, 90.0], balance.data().samples());
+    assert_eq!(true,
+               matches!(run.channel_by_name("rThrottle", None),
+                        Err(Error::ChannelNotFound { .. })));
+
+    let lap = run.lap(0).unwrap();
+    assert_eq!(&vec![60.0, 75.0],
+               lap.channel("Brake Balance").unwrap().data().samples());
+    assert_eq!(4, lap.channel_names().len());
+    let frame = run.frame(&["pBrakeF", "Balance Offset"],
+                          None,
+                          TimeBase::Reference("pBrakeF".to_string()))
+                   .unwrap();
+    assert_eq!(&vec![10.0, 25.0, -50.0, 40.0],
+               frame.columns()[1].samples());
+
+    // cycles are rejected, leaving the math channels as they were
+    let cycle = math("Brake Balance", "%", "\"Balance Offset\"");
+    assert_eq!(true,
+               matches!(run.add_math_channels(vec![cycle]),
+                        Err(Error::InvalidArgument { .. })));
+    assert_eq!(3, run.math_channels().len());
+    run.clear_math_channels();
+    assert_eq!(vec!["pBrakeF", "pBrakeR"], run.all_channel_names().unwrap());
+
+    // math channels which can't be computed fail instead of going missing
+    run.add_math_channels(vec![math("Throttle", "%", "rThrottle * 100")])
+       .unwrap();
+    let lap = run.lap(0).unwrap();
+    assert_eq!(None, lap.channel("Throttle"));
+    assert_eq!(true,
+               matches!(lap.math_channel("Throttle"),
+                        Err(Error::MathChannel { .. })));
+    assert_eq!(true,
+               matches!(SummaryTable::new(&run, &[Statistic::Max]),
+                        Err(Error::MathChannel { .. })));
+  }
+
+  #[cfg(not(feature = "aim"))]
+  #[test]
+  fn from_bytes_test() {
+    let bytes = std::fs::read(NATIVE_XRK_PATH).unwrap();
+    let run = Run::from_bytes(&bytes).unwrap();
+    assert_eq!("072", &run.racer().unwrap());
+    assert_eq!(2, run.number_of_laps());
+
+    // the staged copy lives outside the source directory and is gone along
+    // with the run
+    let dir = run.path().parent().unwrap().to_owned();
+    assert_eq!(true, dir.starts_with(std::env::temp_dir()));
+    drop(run);
+    assert_eq!(false, dir.exists());
+
+    assert_eq!(true,
+               matches!(Run::from_reader(&bytes[..64]),
+                        Err(Error::Unparsable { .. })));
+  }
+
+  #[cfg(not(feature = "aim"))]
+  #[test]
+  fn peek_test() {
+    let header = Run::peek(Path::new(NATIVE_XRK_PATH)).unwrap();
+    assert_eq!("072", header.racer());
+    assert_eq!("TCR_LCA_2.0", header.track());
+    assert_eq!(2, header.number_of_laps());
+    assert_eq!(79, header.number_of_channels());
+    assert_eq!(9, header.gps_raw_channels_count());
+
+    let run = Run::load(Path::new(NATIVE_XRK_PATH)).unwrap();
+    assert_eq!(header, run.header().unwrap());
+    assert_eq!(header, Run::peek(Path::new(NATIVE_XRK_PATH)).unwrap());
+    assert_eq!(79, run.channel_units().unwrap().len());
+
+    assert_eq!(true,
+               matches!(Run::peek(Path::new("./testdata/072")),
+                        Err(Error::InvalidPath { .. })));
+  }
+
+  /// Lifts the limit of open files set by a test when dropped, also if the
+  /// test fails.
+  #[cfg(not(feature = "aim"))]
+  struct Unlimited;
+
+  #[cfg(not(feature = "aim"))]
+  impl Drop for Unlimited {
+    fn drop(&mut self) {
+      Run::set_max_open_files(None);
+    }
+  }
+
+  #[cfg(not(feature = "aim"))]
+  #[test]
+  fn registry_test() {
+    // a staged copy of its own, since closing it would break other tests
+    // loading the same file
+    let bytes = std::fs::read(NATIVE_XRK_PATH).unwrap();
+    let staged = Run::from_bytes(&bytes).unwrap();
+    let path = staged.path().to_owned();
+    let run = Run::load(&path).unwrap();
+    let other = Run::load(&path.parent()
+                               .unwrap()
+                               .join(".")
+                               .join(path.file_name().unwrap())).unwrap();
+    assert_eq!(true, Arc::ptr_eq(&staged, &run));
+    assert_eq!(true, Arc::ptr_eq(&run, &other));
+    assert_eq!(&path.canonicalize().unwrap(), run.path());
+
+    let loaded = Run::loaded();
+    let entry = loaded.iter().find(|entry| entry.path() == run.path());
+    assert_eq!(Some(3), entry.map(|entry| entry.refcount()));
+    assert_eq!(Some(true), entry.map(|entry| entry.open()));
+
+    // closed runs error, loading the file again creates a new one
+    assert_eq!(true, Run::close(&path));
+    assert_eq!(false, Run::close(&path));
+    assert_eq!(true, matches!(other.racer(), Err(Error::Closed { .. })));
+    assert_eq!(false,
+               Run::loaded().iter().any(|entry| entry.path() == run.path()));
+    let reloaded = Run::load(&path).unwrap();
+    assert_eq!(false, Arc::ptr_eq(&run, &reloaded));
+    assert_eq!("072", &reloaded.racer().unwrap());
+
+    // with a limit of one open file, runs close and reopen their files in
+    // turn as they are used
+    let _unlimited = Unlimited;
+    Run::set_max_open_files(Some(1));
+    let another = Run::from_bytes(&bytes).unwrap();
+    assert_eq!("072", &reloaded.racer().unwrap());
+    assert_eq!("072", &another.racer().unwrap());
+    let open = Run::loaded().iter()
+                            .filter(|entry| {
+                              entry.open()
+                              && (entry.path() == reloaded.path()
+                                  || entry.path() == another.path())
+                            })
+                            .count();
+    assert_eq!(true, open <= 1);
+  }
+
+  #[cfg(feature = "runtime")]
+  #[test]
+  fn with_library_test() {
+    // whether or not another test loaded the library already, this path is
+    // not the one it was loaded from
+    let path = Path::new("./aim/libxdrk-does-not-exist.so");
+    assert_eq!(true, Run::with_library(path).is_err());
+  }
 }
@@ -0,0 +1,332 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Sectors of a lap, and sector times of many laps, possibly from many runs.
+
+use super::{gps::Track, ChannelSet, Error, Gate, Lap, Result, Run};
+use getset::{CopyGetters, Getters};
+use std::iter;
+
+
+/// Where the sectors of a lap end, except for the last one, which ends where
+/// the lap does. `n` boundaries make `n + 1` sectors.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sectors {
+  /// Fractions of the distance travelled in the lap, strictly increasing
+  /// and between 0 and 1, see `Lap::distance`.
+  Distance(Vec<f64>),
+  /// Gates crossed in order, e.g. at the sector markers of the track. Laps
+  /// need the ECEF position channels for these, see `Run::lap_in`.
+  Gates(Vec<Gate>),
+}
+
+impl Sectors {
+  /// Number of sectors.
+  pub fn count(&self) -> usize {
+    match self {
+      Self::Distance(fractions) => fractions.len() + 1,
+      Self::Gates(gates) => gates.len() + 1,
+    }
+  }
+
+  /// The channel groups laps need to be split into these sectors.
+  pub fn channel_set(&self) -> ChannelSet {
+    match self {
+      Self::Distance(_) => ChannelSet::DEFAULT,
+      Self::Gates(_) => ChannelSet::ALL,
+    }
+  }
+
+  /// Fails if the fractions of the lap distance are out of order or range.
+  fn check(&self) -> Result<()> {
+    if let Self::Distance(fractions) = self {
+      let in_range = fractions.iter().all(|&f| f > 0.0 && f < 1.0);
+      let increasing = fractions.windows(2).all(|w| w[0] < w[1]);
+      if !in_range || !increasing {
+        let reason = "fractions must increase strictly within (0, 1)";
+        return Err(Error::InvalidArgument { name:   "sectors",
+                                            reason: reason.to_string(), });
+      }
+    }
+    Ok(())
+  }
+
+  /// Times of the sectors of `lap`, see `Lap::sector_times`.
+  pub(crate) fn times_of(&self, lap: &Lap) -> Result<Vec<Option<f64>>> {
+    self.check()?;
+    let (start, end) = (lap.start(), lap.start() + lap.time());
+    let splits = match self {
+      Self::Distance(fractions) => {
+        let distance = lap.distance()?;
+        let samples = distance.data().samples();
+        let length = samples[samples.len() - 1];
+        fractions.iter()
+                 .map(|fraction| {
+                   Some(distance.time_at_distance(fraction * length))
+                 })
+                 .collect::<Vec<_>>()
+      }
+      Self::Gates(gates) => {
+        let track = Track::of(lap)?;
+        // a gate which isn't crossed leaves its split empty, the next one is
+        // searched for after the last split found
+        let mut last = start;
+        gates.iter()
+             .map(|gate| {
+               let split = gate.crossings(&track)
+                               .into_iter()
+                               .find(|&t| t > last && t < end);
+               if let Some(split) = split {
+                 last = split;
+               }
+               split
+             })
+             .collect()
+      }
+    };
+
+    let boundaries = iter::once(Some(start)).chain(splits)
+                                            .chain(iter::once(Some(end)))
+                                            .collect::<Vec<_>>();
+    Ok(boundaries.windows(2)
+                 .map(|w| Some(w[1]? - w[0]?))
+                 .collect())
+  }
+}
+
+
+/// Sector times of a lap in a `SectorTable`.
+#[derive(Debug, Clone, PartialEq, CopyGetters, Getters)]
+pub struct SectorRow {
+  /// Index of the run among the runs the table was built from.
+  #[getset(get_copy = "pub")]
+  run:   usize,
+  /// Index of the lap in its run.
+  #[getset(get_copy = "pub")]
+  lap:   usize,
+  /// Time of each sector in s, `None` where it couldn't be timed, e.g. because
+  /// a gate wasn't crossed in an out-lap.
+  #[getset(get = "pub")]
+  times: Vec<Option<f64>>,
+}
+
+impl SectorRow {
+  /// Sum of the sector times, if all of them are known.
+  pub fn total(&self) -> Option<f64> {
+    self.times.iter().copied().sum()
+  }
+}
+
+
+/// Sector times of all laps of one or many `Run`s, split by the same
+/// `Sectors`, along with the best sectors and the laps they add up to.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct SectorTable {
+  sectors: Sectors,
+  rows:    Vec<SectorRow>,
+}
+
+impl SectorTable {
+  /// Splits all laps of `run` into `sectors`.
+  ///
+  /// ## Fails if
+  ///
+  /// - the laps can't be read from the run
+  /// - the fractions of `Sectors::Distance` are out of order or range, or
+  ///   the distance can't be computed for a lap, see `Lap::distance`
+  /// - a lap lacks the ECEF position channels for `Sectors::Gates`
+  pub fn new(run: &Run, sectors: Sectors) -> Result<Self> {
+    Self::from_runs(&[run], sectors)
+  }
+
+  /// Splits all laps of all `runs` into `sectors`, see `new`.
+  pub fn from_runs(runs: &[&Run], sectors: Sectors) -> Result<Self> {
+    let mut rows = Vec::new();
+    for (run_idx, run) in runs.iter().enumerate() {
+      for lap in run.all_laps_in(sectors.channel_set())? {
+        rows.push(SectorRow { run:   run_idx,
+                              lap:   lap.idx(),
+                              times: sectors.times_of(&lap)?, });
+      }
+    }
+    Ok(Self { sectors, rows })
+  }
+
+  /// Splits `laps`, which are taken to be of a single run, into `sectors`,
+  /// see `new`.
+  pub fn from_laps(laps: &[Lap], sectors: Sectors) -> Result<Self> {
+    let rows = laps.iter()
+                   .map(|lap| {
+                     Ok(SectorRow { run:   0,
+                                    lap:   lap.idx(),
+                                    times: sectors.times_of(lap)?, })
+                   })
+                   .collect::<Result<_>>()?;
+    Ok(Self { sectors, rows })
+  }
+
+  /// Sector times of the lap with index `lap` in the run with index `run`.
+  pub fn row(&self, run: usize, lap: usize) -> Option<&SectorRow> {
+    self.rows.iter().find(|row| row.run == run && row.lap == lap)
+  }
+
+  /// Best time of each sector across all laps, `None` for sectors which
+  /// weren't timed in any lap.
+  pub fn best_sectors(&self) -> Vec<Option<f64>> {
+    (0..self.sectors.count()).map(|sector| {
+                                self.rows
+                                    .iter()
+                                    .filter_map(|row| row.times[sector])
+                                    .fold(None, |best: Option<f64>, time| {
+                                      Some(best.map_or(time, |b| b.min(time)))
+                                    })
+                              })
+                              .collect()
+  }
+
+  /// Sum of the best sectors, i.e. the lap time if the best of every sector
+  /// were driven in the same lap. `None` if a sector wasn't timed at all.
+  pub fn theoretical_best(&self) -> Option<f64> {
+    self.best_sectors().into_iter().sum()
+  }
+
+  /// Fastest time for a lap's worth of consecutive sectors, starting at any
+  /// sector, not just at the finish line. Sectors are consecutive within a
+  /// run and between laps following each other, so this is never slower
+  /// than the fastest lap and never faster than `theoretical_best`. `None`
+  /// if no lap's worth of consecutive sectors was timed.
+  pub fn rolling_optimal(&self) -> Option<f64> {
+    let count = self.sectors.count();
+    // sector times in the order they were driven, with a gap between laps
+    // not following each other
+    let mut driven = Vec::new();
+    for (i, row) in self.rows.iter().enumerate() {
+      if i > 0 {
+        let previous = &self.rows[i - 1];
+        if previous.run != row.run || previous.lap + 1 != row.lap {
+          driven.push(None);
+        }
+      }
+      driven.extend(row.times.iter().copied());
+    }
+    driven.windows(count)
+          .filter_map(|window| window.iter().copied().sum::<Option<f64>>())
+          .fold(None, |best: Option<f64>, time| {
+            Some(best.map_or(time, |b| b.min(time)))
+          })
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Channel, ChannelData, GeoPoint, LapInfo};
+  use pretty_assertions::assert_eq;
+
+
+  /// A lap of 100 m at constant `speed` m/s, sampled at 100 Hz.
+  fn lap(idx: usize, start: f64, speed: f64) -> Lap {
+    let time = 100.0 / speed;
+    let count = (time * 100.0).round() as usize + 1;
+    let timestamps = (0..count).map(|i| start + i as f64 * 0.01)
+                               .collect::<Vec<_>>();
+    Lap::new(LapInfo::new(idx, start, time),
+             vec![Channel::new("GPS Speed".to_string(),
+                               "m/s".to_string(),
+                               ChannelData::new(timestamps,
+                                                vec![speed; count]))])
+  }
+
+  fn rounded(times: &[Option<f64>]) -> Vec<Option<f64>> {
+    times.iter()
+         .map(|time| time.map(|t| (t * 1e6).round() / 1e6))
+         .collect()
+  }
+
+  #[test]
+  fn distance_sectors_test() {
+    let sectors = Sectors::Distance(vec![0.25, 0.5]);
+    assert_eq!(3, sectors.count());
+    let times = sectors.times_of(&lap(0, 0.0, 10.0)).unwrap();
+    assert_eq!(vec![Some(2.5), Some(2.5), Some(5.0)], rounded(&times));
+
+    for fractions in [vec![0.5, 0.25], vec![0.0], vec![1.0]].iter() {
+      let sectors = Sectors::Distance(fractions.clone());
+      assert_eq!(true,
+                 matches!(sectors.times_of(&lap(0, 0.0, 10.0)),
+                          Err(Error::InvalidArgument { .. })));
+    }
+  }
+
+  #[test]
+  fn gate_sectors_test() {
+    // driving east along a parallel at one sample per second
+    let ecef = |axis: usize| {
+      Channel::new(crate::gps::ECEF_POSITIONS[axis].to_string(),
+                   "m".to_string(),
+                   ChannelData::new((0..20).map(f64::from).collect(),
+                                    (0..20).map(|i| {
+                                             GeoPoint::new(50.0,
+                                                           8.0 + i as f64
+                                                                 * 1e-4)
+                                             .to_ecef()[axis]
+                                           })
+                                           .collect()))
+    };
+    let lap = Lap::new(LapInfo::new(0, 0.0, 19.0),
+                       (0..3).map(ecef).collect());
+    let gate = |lon: f64| {
+      Gate::new(GeoPoint::new(50.001, lon), GeoPoint::new(49.999, lon))
+    };
+
+    let sectors = Sectors::Gates(vec![gate(8.000_55), gate(8.7)]);
+    let times = sectors.times_of(&lap).unwrap();
+    assert_eq!(3, times.len());
+    assert_eq!(true, (times[0].unwrap() - 5.5).abs() < 1e-3);
+    assert_eq!(vec![None, None], times[1..].to_vec());
+
+    let lap = Lap::new(LapInfo::new(0, 0.0, 1.0), Vec::new());
+    assert_eq!(true,
+               matches!(sectors.times_of(&lap),
+                        Err(Error::ChannelNotFound { .. })));
+  }
+
+  #[test]
+  fn sector_table_test() {
+    let sectors = Sectors::Distance(vec![0.5]);
+    let mut laps = vec![lap(0, 0.0, 10.0), lap(1, 10.0, 20.0)];
+    laps.push(lap(2, 15.0, 10.0));
+    let mut table = SectorTable::from_laps(&laps, sectors).unwrap();
+
+    assert_eq!(3, table.rows().len());
+    let row = table.row(0, 1).unwrap();
+    assert_eq!(vec![Some(2.5), Some(2.5)], rounded(row.times()));
+    assert_eq!(Some(5.0), row.total());
+    assert_eq!(None, table.row(1, 0));
+
+    // lap 1 is the fastest in both sectors
+    assert_eq!(vec![Some(2.5), Some(2.5)], rounded(&table.best_sectors()));
+    assert_eq!(true, (table.theoretical_best().unwrap() - 5.0).abs() < 1e-6);
+    assert_eq!(true, (table.rolling_optimal().unwrap() - 5.0).abs() < 1e-6);
+
+    // with a slow second sector in lap 1, the best sectors come from
+    // different laps, and the second half of lap 0 and the first of lap 1
+    // make the rolling optimal lap
+    table.rows[1].times[1] = Some(6.0);
+    table.rows[0].times[1] = Some(4.0);
+    assert_eq!(true, (table.theoretical_best().unwrap() - 6.5).abs() < 1e-6);
+    assert_eq!(true, (table.rolling_optimal().unwrap() - 6.5).abs() < 1e-6);
+
+    // but not if the laps don't follow each other
+    table.rows[1].lap = 5;
+    assert_eq!(true, (table.rolling_optimal().unwrap() - 8.5).abs() < 1e-6);
+    table.rows[0].times[0] = None;
+    table.rows[2].times[1] = None;
+    assert_eq!(true, (table.rolling_optimal().unwrap() - 8.5).abs() < 1e-6);
+  }
+}
@@ -0,0 +1,205 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Backends providing the data a `Run` is built from.
+//!
+//! A `Run` does not talk to the AiM library directly, but to a `DataSource`.
+//! The AiM library is one such source (`AimSource`), the pure Rust reader
+//! for XRK/DRK files another (`NativeSource`) and an in-memory collection of
+//! laps and channels a third (`MemorySource`). The latter is mostly useful for
+//! testing code built on top of this library with synthetic data. Finally,
+//! `SandboxSource` runs the AiM library in a helper process, so crashes and
+//! hangs in it don't affect the caller.
+
+#[cfg(feature = "aim")]
+mod aim;
+mod memory;
+#[cfg(feature = "native")]
+mod native;
+#[cfg(feature = "sandbox")]
+mod sandbox;
+
+#[cfg(feature = "aim")]
+pub use aim::AimSource;
+pub use memory::MemorySource;
+#[cfg(feature = "native")]
+pub use native::NativeSource;
+#[cfg(feature = "sandbox")]
+pub use sandbox::SandboxSource;
+
+use super::{ChannelData, LapInfo, Result};
+use chrono::NaiveDateTime;
+use std::{fmt, path::Path};
+
+
+/// The groups of channels a `DataSource` provides.
+///
+/// Regular channels are the ones recorded by the logger, GPS channels are the
+/// ones derived from the GPS module (speed, heading and so on), and GPS raw
+/// channels are the unprocessed measurements of the GPS module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelGroup {
+  Regular,
+  Gps,
+  GpsRaw,
+}
+
+impl fmt::Display for ChannelGroup {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Regular => write!(f, "channel"),
+      Self::Gps => write!(f, "GPS channel"),
+      Self::GpsRaw => write!(f, "GPS raw channel"),
+    }
+  }
+}
+
+
+/// A selection of channel groups.
+///
+/// `Run` indexes the channels of the groups in a set one after the other, in
+/// the order regular, GPS, GPS raw. By default, i.e. in `Run::channel`,
+/// `Run::lap` and friends, that is `ChannelSet::DEFAULT`, which leaves out the
+/// GPS raw channels; their `_in` counterparts take a set to include them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChannelSet {
+  regular: bool,
+  gps:     bool,
+  gps_raw: bool,
+}
+
+impl ChannelSet {
+  /// Regular, GPS and GPS raw channels.
+  pub const ALL: Self = Self { regular: true,
+                               gps:     true,
+                               gps_raw: true, };
+  /// Regular and GPS channels.
+  pub const DEFAULT: Self = Self { regular: true,
+                                   gps:     true,
+                                   gps_raw: false, };
+  /// No channels at all.
+  pub const NONE: Self = Self { regular: false,
+                                gps:     false,
+                                gps_raw: false, };
+
+  /// The set containing only `group`.
+  pub fn only(group: ChannelGroup) -> Self {
+    Self::NONE.with(group)
+  }
+
+  /// This set plus `group`.
+  pub fn with(mut self, group: ChannelGroup) -> Self {
+    *self.flag(group) = true;
+    self
+  }
+
+  /// This set minus `group`.
+  pub fn without(mut self, group: ChannelGroup) -> Self {
+    *self.flag(group) = false;
+    self
+  }
+
+  /// Whether `group` is part of this set.
+  pub fn contains(&self, group: ChannelGroup) -> bool {
+    match group {
+      ChannelGroup::Regular => self.regular,
+      ChannelGroup::Gps => self.gps,
+      ChannelGroup::GpsRaw => self.gps_raw,
+    }
+  }
+
+  /// The groups in this set, in the order their channels are indexed in.
+  pub fn groups(&self) -> impl Iterator<Item = ChannelGroup> {
+    let set = *self;
+    [ChannelGroup::Regular,
+     ChannelGroup::Gps,
+     ChannelGroup::GpsRaw].iter()
+                          .copied()
+                          .filter(move |group| set.contains(*group))
+  }
+
+  fn flag(&mut self, group: ChannelGroup) -> &mut bool {
+    match group {
+      ChannelGroup::Regular => &mut self.regular,
+      ChannelGroup::Gps => &mut self.gps,
+      ChannelGroup::GpsRaw => &mut self.gps_raw,
+    }
+  }
+}
+
+impl Default for ChannelSet {
+  fn default() -> Self {
+    Self::DEFAULT
+  }
+}
+
+impl From<ChannelGroup> for ChannelSet {
+  fn from(group: ChannelGroup) -> Self {
+    Self::only(group)
+  }
+}
+
+
+/// Provides access to the data of a single recording.
+///
+/// Implementors only have to report what they have; range checks and the
+/// caching of lap info and channel names are done by `Run`. Counts of zero
+/// are not an error on this level, `Run` decides how to treat them.
+pub trait DataSource: fmt::Debug + Send + Sync {
+  /// Path of the file backing this source. May be empty for sources which are
+  /// not backed by a file.
+  fn path(&self) -> &Path;
+
+  /// Internal index of the file within the backend. Sources without such a
+  /// concept return `0`.
+  fn idx(&self) -> usize;
+
+  fn championship(&self) -> Result<String>;
+  fn track(&self) -> Result<String>;
+  fn venue_type(&self) -> Result<String>;
+  fn vehicle(&self) -> Result<String>;
+  fn racer(&self) -> Result<String>;
+
+  /// When the recording was made.
+  fn datetime(&self) -> Result<NaiveDateTime>;
+
+  /// Number of laps contained in the recording.
+  fn laps_count(&self) -> Result<usize>;
+
+  /// Start and duration of lap with index `lap_idx`.
+  fn lap_info(&self, lap_idx: usize) -> Result<LapInfo>;
+
+  /// Number of channels in channel group `group`.
+  fn channels_count(&self, group: ChannelGroup) -> Result<usize>;
+
+  /// Name of channel with index `channel_idx` in channel group `group`.
+  fn channel_name(&self,
+                  group: ChannelGroup,
+                  channel_idx: usize)
+                  -> Result<String>;
+
+  /// Unit of channel with index `channel_idx` in channel group `group`.
+  fn channel_unit(&self,
+                  group: ChannelGroup,
+                  channel_idx: usize)
+                  -> Result<String>;
+
+  /// Number of samples of a channel, either for the whole recording (pass
+  /// `None` for `lap_idx`) or for the lap with index `lap_idx`.
+  fn channel_samples_count(&self,
+                           group: ChannelGroup,
+                           channel_idx: usize,
+                           lap_idx: Option<usize>)
+                           -> Result<usize>;
+
+  /// Samples of a channel, either for the whole recording (pass `None` for
+  /// `lap_idx`) or for the lap with index `lap_idx`.
+  fn channel_samples(&self,
+                     group: ChannelGroup,
+                     channel_idx: usize,
+                     lap_idx: Option<usize>)
+                     -> Result<ChannelData>;
+}
@@ -0,0 +1,407 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+mod worker;
+
+use super::{ChannelGroup, DataSource};
+use crate::{bindings as aim, util, ChannelData, Error, LapInfo, Result};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use std::{cmp::Ordering,
+          os::raw::c_char,
+          path::{Path, PathBuf}};
+use worker::Handles;
+
+
+/// `DataSource` backed by the shared library provided by AiM.
+///
+/// The library itself makes no guarantees regarding thread safety, so none
+/// of its functions is called from the calling thread. Instead, every call
+/// is executed on a dedicated worker thread which owns the library and the
+/// files opened in it; concurrent calls are queued. The file is closed when
+/// the `AimSource` is dropped.
+///
+/// With the `runtime` feature, the library is loaded when it is first needed,
+/// i.e. by `open`, `library_date` or `library_time`, unless it was loaded
+/// explicitly using `with_library` before.
+#[derive(Debug)]
+pub struct AimSource {
+  path: PathBuf,
+  idx:  usize,
+}
+
+impl Drop for AimSource {
+  /// Close the drk/xrk file on `AimSource` destruction
+  fn drop(&mut self) {
+    let idx = self.idx as i32;
+    // waits for the file to be closed, failing here would only leak it
+    let _ = worker::execute(&self.path, move |handles| {
+      if handles.closed(idx) {
+        unsafe { aim::close_file_i(idx) };
+      }
+    });
+  }
+}
+
+impl AimSource {
+  // META FUNCTIONS -------------------------------------------------------- //
+  /// Loads the AiM library from `path` (`runtime` feature only). See
+  /// `Run::with_library` for details.
+  #[cfg(feature = "runtime")]
+  pub fn with_library(path: &Path) -> Result<()> {
+    aim::load(Some(path))
+  }
+
+  /// Library compilation date.
+  pub fn library_date() -> Result<NaiveDate> {
+    let date =
+      meta_string("get_library_date", || unsafe { aim::get_library_date() })?;
+    NaiveDate::parse_from_str(&date, "%b %d %Y").map_err(|_| {
+                                                  Error::LibraryData {
+                                                    path: PathBuf::new(),
+                                                    call: "get_library_date",
+                                                  }
+                                                })
+  }
+
+  /// Library compilation time.
+  pub fn library_time() -> Result<NaiveTime> {
+    let time =
+      meta_string("get_library_time", || unsafe { aim::get_library_time() })?;
+    NaiveTime::parse_from_str(&time, "%H:%M:%S").map_err(|_| {
+                                                  Error::LibraryData {
+                                                    path: PathBuf::new(),
+                                                    call: "get_library_time",
+                                                  }
+                                                })
+  }
+
+  // FILE OPENING FUNCTIONS ------------------------------------------------ //
+  /// Opens a drk/xrk file using the AiM library.
+  pub fn open(path: &Path) -> Result<Self> {
+    #[cfg(feature = "runtime")]
+    aim::load(None)?;
+    let path_cstring = util::path_to_cstring(path)?;
+    let raw_idx = worker::execute(path, move |handles| {
+      let raw_idx = unsafe { aim::open_file(path_cstring.as_ptr()) };
+      if raw_idx > 0 {
+        handles.opened(raw_idx);
+      }
+      raw_idx
+    })?;
+
+    // match the index to check if opening file was a success
+    let idx = match raw_idx.cmp(&0) {
+      Ordering::Greater => raw_idx as usize,
+      Ordering::Equal => {
+        return Err(Error::Unparsable { path:   path.to_owned(),
+                                       reason: "rejected by the AiM library"
+                                               .to_string(), })
+      }
+      Ordering::Less => {
+        return Err(Error::Library { path: path.to_owned(),
+                                    call: "open_file",
+                                    code: raw_idx, })
+      }
+    };
+
+    Ok(Self { path: path.to_owned(),
+              idx })
+  }
+
+  /// Runs `call` on the worker thread, passing it the file index.
+  fn execute<T, F>(&self, call: F) -> Result<T>
+    where T: Send + 'static,
+          F: FnOnce(i32) -> T + Send + 'static {
+    let idx = self.idx as i32;
+    worker::execute(&self.path, move |_: &mut Handles| call(idx))
+  }
+
+  /// Error for a failed call into the library.
+  fn failed(&self, call: &'static str, code: i32) -> Error {
+    Error::Library { path: self.path.clone(),
+                     call,
+                     code }
+  }
+
+  /// Runs `call` returning a count on the worker thread, treating negative
+  /// values as errors. `call` also returns the name of the library function
+  /// it used, for context.
+  fn count<F>(&self, call: F) -> Result<usize>
+    where F: FnOnce(i32) -> (&'static str, i32) + Send + 'static {
+    let (name, count) = self.execute(call)?;
+    match count.cmp(&0) {
+      Ordering::Greater | Ordering::Equal => Ok(count as usize),
+      Ordering::Less => Err(self.failed(name, count)),
+    }
+  }
+
+  /// Runs `call` returning a string on the worker thread, treating `NULL` as
+  /// an error. `call` also returns the name of the library function it used,
+  /// for context.
+  fn string<F>(&self, call: F) -> Result<String>
+    where F: FnOnce(i32) -> (&'static str, *const c_char) + Send + 'static {
+    let (name, string) = self.execute(move |idx| {
+                               let (name, strptr) = call(idx);
+                               (name, util::strptr_to_string(strptr))
+                             })?;
+    string.ok_or_else(|| self.failed(name, 0))
+  }
+}
+
+impl DataSource for AimSource {
+  fn path(&self) -> &Path {
+    &self.path
+  }
+
+  fn idx(&self) -> usize {
+    self.idx
+  }
+
+  // RUN LEVEL FUNCTIONS --------------------------------------------------- //
+  fn championship(&self) -> Result<String> {
+    self.string(|idx| {
+          ("get_championship_name", unsafe { aim::get_championship_name(idx) })
+        })
+  }
+
+  fn track(&self) -> Result<String> {
+    self.string(|idx| ("get_track_name", unsafe { aim::get_track_name(idx) }))
+  }
+
+  fn venue_type(&self) -> Result<String> {
+    self.string(|idx| {
+          ("get_venue_type_name", unsafe { aim::get_venue_type_name(idx) })
+        })
+  }
+
+  fn vehicle(&self) -> Result<String> {
+    self.string(|idx| {
+          ("get_vehicle_name", unsafe { aim::get_vehicle_name(idx) })
+        })
+  }
+
+  fn racer(&self) -> Result<String> {
+    self.string(|idx| ("get_racer_name", unsafe { aim::get_racer_name(idx) }))
+  }
+
+  fn datetime(&self) -> Result<NaiveDateTime> {
+    let tm = self.execute(|idx| {
+                   let tm: *const aim::tm =
+                     unsafe { aim::get_date_and_time(idx) };
+                   if tm.is_null() {
+                     None
+                   } else {
+                     Some(unsafe { *tm })
+                   }
+                 })?
+                 .ok_or_else(|| self.failed("get_date_and_time", 0))?;
+
+    Ok(NaiveDate::from_ymd(tm.tm_year + 1900,
+                           (tm.tm_mon + 1) as u32,
+                           tm.tm_mday as u32).and_hms(tm.tm_hour as u32,
+                                                      tm.tm_min as u32,
+                                                      tm.tm_sec as u32))
+  }
+
+  fn laps_count(&self) -> Result<usize> {
+    self.count(|idx| ("get_laps_count", unsafe { aim::get_laps_count(idx) }))
+  }
+
+  fn lap_info(&self, lap_idx: usize) -> Result<LapInfo> {
+    let (err_code, start, time) =
+      self.execute(move |idx| {
+            let (mut start, mut time) = (0.0f64, 0.0f64);
+            let err_code = unsafe {
+              aim::get_lap_info(idx, lap_idx as i32, &mut start, &mut time)
+            };
+            (err_code, start, time)
+          })?;
+    if err_code != 1 {
+      return Err(self.failed("get_lap_info", err_code));
+    }
+    Ok(LapInfo::new(lap_idx, start, time))
+  }
+
+  // CHANNEL INFORMATION FUNCTIONS ----------------------------------------- //
+  fn channels_count(&self, group: ChannelGroup) -> Result<usize> {
+    self.count(move |idxf| unsafe {
+          match group {
+            ChannelGroup::Regular => {
+              ("get_channels_count", aim::get_channels_count(idxf))
+            }
+            ChannelGroup::Gps => {
+              ("get_GPS_channels_count", aim::get_GPS_channels_count(idxf))
+            }
+            ChannelGroup::GpsRaw => ("get_GPS_raw_channels_count",
+                                     aim::get_GPS_raw_channels_count(idxf)),
+          }
+        })
+  }
+
+  fn channel_name(&self,
+                  group: ChannelGroup,
+                  channel_idx: usize)
+                  -> Result<String> {
+    let idxc = channel_idx as i32;
+    self.string(move |idxf| unsafe {
+          match group {
+            ChannelGroup::Regular => {
+              ("get_channel_name", aim::get_channel_name(idxf, idxc))
+            }
+            ChannelGroup::Gps => {
+              ("get_GPS_channel_name", aim::get_GPS_channel_name(idxf, idxc))
+            }
+            ChannelGroup::GpsRaw => ("get_GPS_raw_channel_name",
+                                     aim::get_GPS_raw_channel_name(idxf,
+                                                                   idxc)),
+          }
+        })
+  }
+
+  fn channel_unit(&self,
+                  group: ChannelGroup,
+                  channel_idx: usize)
+                  -> Result<String> {
+    let idxc = channel_idx as i32;
+    self.string(move |idxf| unsafe {
+          match group {
+            ChannelGroup::Regular => {
+              ("get_channel_units", aim::get_channel_units(idxf, idxc))
+            }
+            ChannelGroup::Gps => {
+              ("get_GPS_channel_units", aim::get_GPS_channel_units(idxf, idxc))
+            }
+            ChannelGroup::GpsRaw => ("get_GPS_raw_channel_units",
+                                     aim::get_GPS_raw_channel_units(idxf,
+                                                                    idxc)),
+          }
+        })
+  }
+
+  fn channel_samples_count(&self,
+                           group: ChannelGroup,
+                           channel_idx: usize,
+                           lap_idx: Option<usize>)
+                           -> Result<usize> {
+    let idxc = channel_idx as i32;
+    self.count(move |idxf| unsafe {
+          match (group, lap_idx) {
+            (ChannelGroup::Regular, None) => {
+              ("get_channel_samples_count",
+               aim::get_channel_samples_count(idxf, idxc))
+            }
+            (ChannelGroup::Regular, Some(idxl)) => {
+              ("get_lap_channel_samples_count",
+               aim::get_lap_channel_samples_count(idxf, idxl as i32, idxc))
+            }
+            (ChannelGroup::Gps, None) => {
+              ("get_GPS_channel_samples_count",
+               aim::get_GPS_channel_samples_count(idxf, idxc))
+            }
+            (ChannelGroup::Gps, Some(idxl)) => {
+              ("get_lap_GPS_channel_samples_count",
+               aim::get_lap_GPS_channel_samples_count(idxf, idxl as i32, idxc))
+            }
+            (ChannelGroup::GpsRaw, None) => {
+              ("get_GPS_raw_channel_samples_count",
+               aim::get_GPS_raw_channel_samples_count(idxf, idxc))
+            }
+            (ChannelGroup::GpsRaw, Some(idxl)) => {
+              ("get_lap_GPS_raw_channel_samples_count",
+               aim::get_lap_GPS_raw_channel_samples_count(idxf,
+                                                          idxl as i32,
+                                                          idxc))
+            }
+          }
+        })
+  }
+
+  fn channel_samples(&self,
+                     group: ChannelGroup,
+                     channel_idx: usize,
+                     lap_idx: Option<usize>)
+                     -> Result<ChannelData> {
+    let count = self.channel_samples_count(group, channel_idx, lap_idx)?;
+    if count == 0 {
+      let (timestamps, samples) = ChannelData::allocate(count);
+      return Ok(ChannelData::from_tsc(timestamps, samples, count));
+    }
+
+    // the buffers are allocated and filled on the worker thread and handed
+    // back afterwards, so no pointers cross thread boundaries
+    let (idxc, cnt) = (channel_idx as i32, count as i32);
+    let (call, read, timestamps, samples) =
+      self.execute(move |idxf| {
+            let (mut timestamps, mut samples) = ChannelData::allocate(count);
+            let (ptimes, pvalues) =
+              (timestamps.as_mut_ptr(), samples.as_mut_ptr());
+            let (call, read) = unsafe {
+              match (group, lap_idx) {
+                (ChannelGroup::Regular, None) => {
+                  ("get_channel_samples",
+                   aim::get_channel_samples(idxf, idxc, ptimes, pvalues, cnt))
+                }
+                (ChannelGroup::Regular, Some(idxl)) => {
+                  ("get_lap_channel_samples",
+                   aim::get_lap_channel_samples(idxf,
+                                                idxl as i32,
+                                                idxc,
+                                                ptimes,
+                                                pvalues,
+                                                cnt))
+                }
+                (ChannelGroup::Gps, None) => {
+                  ("get_GPS_channel_samples",
+                   aim::get_GPS_channel_samples(idxf, idxc, ptimes, pvalues,
+                                                cnt))
+                }
+                (ChannelGroup::Gps, Some(idxl)) => {
+                  ("get_lap_GPS_channel_samples",
+                   aim::get_lap_GPS_channel_samples(idxf,
+                                                    idxl as i32,
+                                                    idxc,
+                                                    ptimes,
+                                                    pvalues,
+                                                    cnt))
+                }
+                (ChannelGroup::GpsRaw, None) => {
+                  ("get_GPS_raw_channel_samples",
+                   aim::get_GPS_raw_channel_samples(idxf, idxc, ptimes,
+                                                    pvalues, cnt))
+                }
+                (ChannelGroup::GpsRaw, Some(idxl)) => {
+                  ("get_lap_GPS_raw_channel_samples",
+                   aim::get_lap_GPS_raw_channel_samples(idxf,
+                                                        idxl as i32,
+                                                        idxc,
+                                                        ptimes,
+                                                        pvalues,
+                                                        cnt))
+                }
+              }
+            };
+            (call, read, timestamps, samples)
+          })?;
+    if read != cnt {
+      return Err(self.failed(call, read));
+    }
+
+    Ok(ChannelData::from_tsc(timestamps, samples, count))
+  }
+}
+
+
+/// Runs `call` returning a string on the worker thread for one of the library
+/// meta functions, which don't relate to a file.
+fn meta_string<F>(name: &'static str, call: F) -> Result<String>
+  where F: FnOnce() -> *const c_char + Send + 'static {
+  #[cfg(feature = "runtime")]
+  aim::load(None)?;
+  worker::execute(Path::new(""), move |_| util::strptr_to_string(call()))?
+    .ok_or(Error::Library { path: PathBuf::new(),
+                            call: name,
+                            code: 0 })
+}
@@ -0,0 +1,132 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! The thread owning the AiM library.
+//!
+//! The library makes no guarantees regarding thread safety, so it is only
+//! ever called from a single, dedicated worker thread. Callers send jobs
+//! (closures doing the actual library calls) over a channel and block until
+//! the result comes back; concurrent callers are queued in the order their
+//! jobs arrive. Since jobs are executed strictly in that order, closing a
+//! file can't overtake opening it again or vice versa.
+
+use crate::{Error, Result};
+use lazy_static::lazy_static;
+use std::{collections::HashMap,
+          panic::{self, AssertUnwindSafe},
+          path::Path,
+          sync::mpsc::{self, Sender},
+          thread};
+
+
+lazy_static! {
+  static ref WORKER: Sender<Job> = spawn();
+}
+
+
+/// Work to be done on the worker thread.
+type Job = Box<dyn FnOnce(&mut Handles) + Send>;
+
+
+/// Files currently opened in the library, owned by the worker thread.
+///
+/// Maps the index handed out by the library to the number of `AimSource`
+/// objects using it. If the library hands out the same index when a file is
+/// opened twice, that file is only closed once the last of them is dropped.
+#[derive(Debug, Default)]
+pub(super) struct Handles(HashMap<i32, usize>);
+
+impl Handles {
+  /// Registers a file index returned by `open_file`.
+  pub(super) fn opened(&mut self, idx: i32) {
+    *self.0.entry(idx).or_default() += 1;
+  }
+
+  /// Unregisters a file index, returning `true` if it is no longer used and
+  /// should be closed.
+  pub(super) fn closed(&mut self, idx: i32) -> bool {
+    match self.0.get_mut(&idx) {
+      Some(users) if *users > 1 => {
+        *users -= 1;
+        false
+      }
+      Some(_) => {
+        self.0.remove(&idx);
+        true
+      }
+      None => false,
+    }
+  }
+}
+
+
+/// Runs `job` on the worker thread and waits for its result. `path` is only
+/// used for context in case the job fails, i.e. panics.
+pub(super) fn execute<T, F>(path: &Path, job: F) -> Result<T>
+  where T: Send + 'static,
+        F: FnOnce(&mut Handles) -> T + Send + 'static {
+  let failed = || Error::Worker { path: path.to_owned(), };
+  let (result_sender, result_receiver) = mpsc::sync_channel(1);
+  WORKER.send(Box::new(move |handles| {
+                // the receiving end only goes away if the caller panicked
+                let _ = result_sender.send(job(handles));
+              }))
+        .map_err(|_| failed())?;
+  result_receiver.recv().map_err(|_| failed())
+}
+
+/// Spawns the worker thread, which lives as long as the process does.
+fn spawn() -> Sender<Job> {
+  let (sender, receiver) = mpsc::channel::<Job>();
+  thread::Builder::new().name("xdrk-aim".to_string())
+                        .spawn(move || {
+                          let mut handles = Handles::default();
+                          for job in receiver {
+                            // a panicking job must not take the worker down
+                            // with it; its caller gets an error since the
+                            // result is never sent
+                            let _ =
+                              panic::catch_unwind(AssertUnwindSafe(|| {
+                                                    job(&mut handles)
+                                                  }));
+                          }
+                        })
+                        .expect("unable to spawn AiM worker thread");
+  sender
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn worker_test() {
+    let path = Path::new("run.xrk");
+    let name = execute(path, |_| thread::current().name().map(String::from));
+    assert_eq!(Some("xdrk-aim".to_string()), name.unwrap());
+
+    // jobs panicking don't kill the worker
+    assert_eq!(true,
+               matches!(execute(path, |_| panic!("oops")),
+                        Err::<(), _>(Error::Worker { .. })));
+    assert_eq!(42, execute(path, |_| 42).unwrap());
+  }
+
+  #[test]
+  fn handles_test() {
+    let mut handles = Handles::default();
+    handles.opened(1);
+    handles.opened(1);
+    handles.opened(2);
+    assert_eq!(false, handles.closed(1));
+    assert_eq!(true, handles.closed(1));
+    assert_eq!(false, handles.closed(1));
+    assert_eq!(true, handles.closed(2));
+  }
+}
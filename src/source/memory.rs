@@ -0,0 +1,246 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+use super::{ChannelGroup, DataSource};
+use crate::{Channel, ChannelData, Error, LapInfo, Result};
+use chrono::NaiveDateTime;
+use getset::{Getters, Setters};
+use std::path::{Path, PathBuf};
+
+
+/// `DataSource` holding laps and channels in memory.
+///
+/// Channels are stored with timestamps relative to the start of the
+/// recording, just like the AiM library reports them. Lap-wise data is cut
+/// from the channels using the `LapInfo` objects passed on construction, i.e.
+/// a sample belongs to a lap if `start <= timestamp < start + time`.
+#[derive(Debug, Clone, PartialEq, Getters, Setters)]
+pub struct MemorySource {
+  #[getset(set = "pub")]
+  path:             PathBuf,
+  #[getset(set = "pub")]
+  championship:     String,
+  #[getset(set = "pub")]
+  track:            String,
+  #[getset(set = "pub")]
+  venue_type:       String,
+  #[getset(set = "pub")]
+  vehicle:          String,
+  #[getset(set = "pub")]
+  racer:            String,
+  #[getset(set = "pub")]
+  datetime:         NaiveDateTime,
+  #[getset(get = "pub")]
+  info_of_laps:     Vec<LapInfo>,
+  #[getset(get = "pub")]
+  channels:         Vec<Channel>,
+  #[getset(get = "pub")]
+  gps_channels:     Vec<Channel>,
+  #[getset(get = "pub")]
+  gps_raw_channels: Vec<Channel>,
+}
+
+impl MemorySource {
+  /// Construct a new `MemorySource` without any channels from a set of laps.
+  /// Metadata is empty and can be filled in using the setters.
+  pub fn new(info_of_laps: Vec<LapInfo>) -> Self {
+    Self { path: PathBuf::new(),
+           championship: String::new(),
+           track: String::new(),
+           venue_type: String::new(),
+           vehicle: String::new(),
+           racer: String::new(),
+           datetime: NaiveDateTime::default(),
+           info_of_laps,
+           channels: Vec::new(),
+           gps_channels: Vec::new(),
+           gps_raw_channels: Vec::new() }
+  }
+
+  /// Add a channel to channel group `group`. Channels keep the order in which
+  /// they are added.
+  pub fn add_channel(&mut self,
+                     group: ChannelGroup,
+                     channel: Channel)
+                     -> &mut Self {
+    self.group_mut(group).push(channel);
+    self
+  }
+
+  fn group(&self, group: ChannelGroup) -> &Vec<Channel> {
+    match group {
+      ChannelGroup::Regular => &self.channels,
+      ChannelGroup::Gps => &self.gps_channels,
+      ChannelGroup::GpsRaw => &self.gps_raw_channels,
+    }
+  }
+
+  fn group_mut(&mut self, group: ChannelGroup) -> &mut Vec<Channel> {
+    match group {
+      ChannelGroup::Regular => &mut self.channels,
+      ChannelGroup::Gps => &mut self.gps_channels,
+      ChannelGroup::GpsRaw => &mut self.gps_raw_channels,
+    }
+  }
+
+  fn get(&self, group: ChannelGroup, channel_idx: usize) -> Result<&Channel> {
+    let channels = self.group(group);
+    channels.get(channel_idx).ok_or_else(|| {
+                               Error::ChannelOutOfRange { path: self.path
+                                                                    .clone(),
+                                                          group,
+                                                          channel:
+                                                            channel_idx,
+                                                          count:
+                                                            channels.len() }
+                             })
+  }
+
+  /// Cuts the data of a channel down to the lap with index `lap_idx`, or
+  /// returns all of it if `lap_idx` is `None`.
+  fn slice(&self,
+           group: ChannelGroup,
+           channel_idx: usize,
+           lap_idx: Option<usize>)
+           -> Result<ChannelData> {
+    let data = self.get(group, channel_idx)?.data();
+    let lap_idx = match lap_idx {
+      Some(lap_idx) => lap_idx,
+      None => return Ok(data.clone()),
+    };
+
+    let info = self.lap_info(lap_idx)?;
+    Ok(data.between(info.start(), info.start() + info.time()))
+  }
+}
+
+impl DataSource for MemorySource {
+  fn path(&self) -> &Path {
+    &self.path
+  }
+
+  fn idx(&self) -> usize {
+    0
+  }
+
+  // RUN LEVEL FUNCTIONS --------------------------------------------------- //
+  fn championship(&self) -> Result<String> {
+    Ok(self.championship.clone())
+  }
+
+  fn track(&self) -> Result<String> {
+    Ok(self.track.clone())
+  }
+
+  fn venue_type(&self) -> Result<String> {
+    Ok(self.venue_type.clone())
+  }
+
+  fn vehicle(&self) -> Result<String> {
+    Ok(self.vehicle.clone())
+  }
+
+  fn racer(&self) -> Result<String> {
+    Ok(self.racer.clone())
+  }
+
+  fn datetime(&self) -> Result<NaiveDateTime> {
+    Ok(self.datetime)
+  }
+
+  fn laps_count(&self) -> Result<usize> {
+    Ok(self.info_of_laps.len())
+  }
+
+  fn lap_info(&self, lap_idx: usize) -> Result<LapInfo> {
+    let count = self.info_of_laps.len();
+    self.info_of_laps
+        .get(lap_idx)
+        .copied()
+        .ok_or_else(|| Error::LapOutOfRange { path: self.path.clone(),
+                                              lap: lap_idx,
+                                              count })
+  }
+
+  // CHANNEL INFORMATION FUNCTIONS ----------------------------------------- //
+  fn channels_count(&self, group: ChannelGroup) -> Result<usize> {
+    Ok(self.group(group).len())
+  }
+
+  fn channel_name(&self,
+                  group: ChannelGroup,
+                  channel_idx: usize)
+                  -> Result<String> {
+    Ok(self.get(group, channel_idx)?.name().clone())
+  }
+
+  fn channel_unit(&self,
+                  group: ChannelGroup,
+                  channel_idx: usize)
+                  -> Result<String> {
+    Ok(self.get(group, channel_idx)?.unit().clone())
+  }
+
+  fn channel_samples_count(&self,
+                           group: ChannelGroup,
+                           channel_idx: usize,
+                           lap_idx: Option<usize>)
+                           -> Result<usize> {
+    Ok(self.slice(group, channel_idx, lap_idx)?.len())
+  }
+
+  fn channel_samples(&self,
+                     group: ChannelGroup,
+                     channel_idx: usize,
+                     lap_idx: Option<usize>)
+                     -> Result<ChannelData> {
+    self.slice(group, channel_idx, lap_idx)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn memory_source_test() {
+    let data = ChannelData::new(vec![0.0, 0.5, 1.0, 1.5, 2.0, 2.5],
+                                vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    let mut source = MemorySource::new(vec![LapInfo::new(0, 0.0, 1.0),
+                                            LapInfo::new(1, 1.0, 1.5)]);
+    source.set_vehicle("HO-FK7-M5-X-S".to_string())
+          .add_channel(ChannelGroup::Regular,
+                       Channel::new("fEngRpm".to_string(),
+                                    "rpm".to_string(),
+                                    data.clone()));
+
+    assert_eq!("HO-FK7-M5-X-S", &source.vehicle().unwrap());
+    assert_eq!(2, source.laps_count().unwrap());
+    assert_eq!(1, source.channels_count(ChannelGroup::Regular).unwrap());
+    assert_eq!(0, source.channels_count(ChannelGroup::Gps).unwrap());
+    assert_eq!("rpm",
+               &source.channel_unit(ChannelGroup::Regular, 0).unwrap());
+    assert_eq!(true, source.channel_name(ChannelGroup::Gps, 0).is_err());
+
+    assert_eq!(data,
+               source.channel_samples(ChannelGroup::Regular, 0, None)
+                     .unwrap());
+    assert_eq!(ChannelData::new(vec![0.0, 0.5], vec![1.0, 2.0]),
+               source.channel_samples(ChannelGroup::Regular, 0, Some(0))
+                     .unwrap());
+    assert_eq!(3,
+               source.channel_samples_count(ChannelGroup::Regular,
+                                            0,
+                                            Some(1))
+                     .unwrap());
+    assert_eq!(true,
+               source.channel_samples(ChannelGroup::Regular, 0, Some(2))
+                     .is_err());
+  }
+}
@@ -0,0 +1,941 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+use super::{ChannelGroup, DataSource, MemorySource};
+use crate::{gps::{ecef_to_enu, ecef_to_geodetic},
+            Channel,
+            ChannelData,
+            Error,
+            Interpolation,
+            LapInfo,
+            Result};
+use chrono::NaiveDateTime;
+use std::{collections::{BTreeMap, HashMap},
+          fs,
+          path::Path};
+
+
+/// Units as encoded in the channel descriptions of XRK files. The high bit of
+/// the unit byte carries display information and is masked out.
+const UNITS: [(u8, &str); 16] = [(1, "%"),
+                                 (3, "g"),
+                                 (4, "deg"),
+                                 (5, "deg/s"),
+                                 (11, "#"),
+                                 (14, "bar"),
+                                 (15, "rpm"),
+                                 (16, "km/h"),
+                                 (17, "C"),
+                                 (18, "ms"),
+                                 (20, "m/s"),
+                                 (21, "V"),
+                                 (22, "l"),
+                                 (24, "l/s"),
+                                 (30, "lambda"),
+                                 (31, "gear")];
+
+/// Channel sources which are not exposed as regular channels: 4 are channels
+/// computed by the logger itself (master clock, lap time, odometers), 5 are
+/// GPS channels, which are decoded from the GPS records instead.
+const HIDDEN_SOURCES: [u8; 2] = [4, 5];
+
+/// Names and units of the GPS raw channels, in the order the AiM library
+/// provides them.
+const GPS_RAW_CHANNELS: [(&str, &str); 9] = [("ECEF position_X", "m"),
+                                             ("ECEF position_Y", "m"),
+                                             ("ECEF position_Z", "m"),
+                                             ("ECEF velocity_X", "m/s"),
+                                             ("ECEF velocity_Y", "m/s"),
+                                             ("ECEF velocity_Z", "m/s"),
+                                             ("N Satellites", "#"),
+                                             ("Time of Week", "ms"),
+                                             ("Week N", "#")];
+
+/// Names and units of the GPS channels, in the order the AiM library
+/// provides them.
+const GPS_CHANNELS: [(&str, &str); 11] = [("GPS Speed", "m/s"),
+                                          ("GPS Nsat", "#"),
+                                          ("GPS LatAcc", "g"),
+                                          ("GPS LonAcc", "g"),
+                                          ("GPS Slope", "deg"),
+                                          ("GPS Heading", "deg"),
+                                          ("GPS Gyro", "deg/s"),
+                                          ("GPS Altitude", "m"),
+                                          ("GPS PosAccuracy", "m"),
+                                          ("GPS SpdAccuracy", "m/s"),
+                                          ("GPS Radius", "m")];
+
+/// Standard gravity, used to express GPS accelerations in g.
+const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// Rate in Hz the AiM library provides GPS channels at.
+const GPS_RATE: f64 = 100.0;
+
+
+/// Parsing fails with a description of what's wrong, `NativeSource::open`
+/// adds the path.
+type ParseResult<T> = std::result::Result<T, String>;
+
+
+/// `DataSource` reading XRK/DRK files without the AiM library.
+///
+/// The whole file is parsed on construction and held in memory, so there is
+/// neither FFI nor a global lock involved in accessing the data.
+///
+/// ## Differences to the AiM library
+///
+/// - GPS channels are computed from the GPS records (usually 10 Hz) and
+///   interpolated linearly to 100 Hz, so they may differ slightly from the
+///   ones of the AiM library in between fixes. Accuracies are reported in m
+///   and m/s.
+/// - Record types other than the ones found in files written by MXx/EVO
+///   loggers are rejected, so some DRK files may fail to open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NativeSource(MemorySource);
+
+impl NativeSource {
+  /// Reads and parses a drk/xrk file.
+  pub fn open(path: &Path) -> Result<Self> {
+    let bytes = fs::read(path).map_err(|source| Error::Io { path:
+                                                              path.to_owned(),
+                                                            source })?;
+    let mut source = Parser::new(&bytes).parse().map_err(|reason| {
+                                                   Error::Unparsable {
+                                                     path: path.to_owned(),
+                                                     reason,
+                                                   }
+                                                 })?;
+    source.set_path(path.to_owned());
+    Ok(Self(source))
+  }
+}
+
+impl DataSource for NativeSource {
+  fn path(&self) -> &Path {
+    self.0.path()
+  }
+
+  fn idx(&self) -> usize {
+    self.0.idx()
+  }
+
+  // RUN LEVEL FUNCTIONS --------------------------------------------------- //
+  fn championship(&self) -> Result<String> {
+    self.0.championship()
+  }
+
+  fn track(&self) -> Result<String> {
+    self.0.track()
+  }
+
+  fn venue_type(&self) -> Result<String> {
+    self.0.venue_type()
+  }
+
+  fn vehicle(&self) -> Result<String> {
+    self.0.vehicle()
+  }
+
+  fn racer(&self) -> Result<String> {
+    self.0.racer()
+  }
+
+  fn datetime(&self) -> Result<NaiveDateTime> {
+    self.0.datetime()
+  }
+
+  fn laps_count(&self) -> Result<usize> {
+    self.0.laps_count()
+  }
+
+  fn lap_info(&self, lap_idx: usize) -> Result<LapInfo> {
+    self.0.lap_info(lap_idx)
+  }
+
+  // CHANNEL INFORMATION FUNCTIONS ----------------------------------------- //
+  fn channels_count(&self, group: ChannelGroup) -> Result<usize> {
+    self.0.channels_count(group)
+  }
+
+  fn channel_name(&self,
+                  group: ChannelGroup,
+                  channel_idx: usize)
+                  -> Result<String> {
+    self.0.channel_name(group, channel_idx)
+  }
+
+  fn channel_unit(&self,
+                  group: ChannelGroup,
+                  channel_idx: usize)
+                  -> Result<String> {
+    self.0.channel_unit(group, channel_idx)
+  }
+
+  fn channel_samples_count(&self,
+                           group: ChannelGroup,
+                           channel_idx: usize,
+                           lap_idx: Option<usize>)
+                           -> Result<usize> {
+    self.0.channel_samples_count(group, channel_idx, lap_idx)
+  }
+
+  fn channel_samples(&self,
+                     group: ChannelGroup,
+                     channel_idx: usize,
+                     lap_idx: Option<usize>)
+                     -> Result<ChannelData> {
+    self.0.channel_samples(group, channel_idx, lap_idx)
+  }
+}
+
+
+// PARSER ------------------------------------------------------------------ //
+//
+// XRK files are a sequence of records. Header records look like
+//
+//   <h TAG(4) LEN(u32) VERSION(u8) > BODY(LEN) < TAG(4) CHECKSUM(u16) >
+//
+// where the `CNF` record is a container holding the channel descriptions
+// (`CHS`) and channel groups (`GRP`), i.e. its closing tag follows after the
+// nested records. Data records look like
+//
+//   (G TIMESTAMP(u32) GROUP(u16) SAMPLES... )
+//   (S TIMESTAMP(u32) CHANNEL(u16) SAMPLE )
+//   (M TIMESTAMP(u32) CHANNEL(u16) COUNT(u16) SAMPLES... )
+//
+// all numbers are little endian, timestamps are in ms on the logger clock.
+// header records (e.g. GPS fixes and lap markers) and data records are
+// interleaved, so the file has to be read sequentially.
+//
+/// Description of a channel as found in a `CHS` record.
+#[derive(Debug)]
+struct ChannelInfo {
+  name:      String,
+  unit:      String,
+  source:    u8,
+  data_type: u8,
+  size:      usize,
+  period:    u32,
+  gain:      f64,
+  offset:    f64,
+}
+
+/// GPS fix as found in a `GPS1` record, which wraps a u-blox NAV-SOL message.
+#[derive(Debug, Clone, Copy)]
+struct GpsFix {
+  timestamp:         f64,
+  time_of_week:      f64,
+  week:              f64,
+  fix:               u8,
+  position:          [f64; 3],
+  position_accuracy: f64,
+  velocity:          [f64; 3],
+  speed_accuracy:    f64,
+  satellites:        f64,
+}
+
+/// End of a lap as found in a `LAP` record; both values in ms.
+#[derive(Debug, Clone, Copy)]
+struct LapMarker {
+  end:  u32,
+  time: u32,
+}
+
+#[derive(Debug)]
+struct Parser<'a> {
+  bytes:    &'a [u8],
+  pos:      usize,
+  channels: BTreeMap<u16, ChannelInfo>,
+  groups:   HashMap<u16, Vec<u16>>,
+  samples:  HashMap<u16, (Vec<f64>, Vec<f64>)>,
+  gps:      Vec<GpsFix>,
+  laps:     Vec<LapMarker>,
+  meta:     HashMap<[u8; 4], String>,
+  span:     Option<(u32, u32)>,
+}
+
+impl<'a> Parser<'a> {
+  fn new(bytes: &'a [u8]) -> Self {
+    Self { bytes,
+           pos: 0,
+           channels: BTreeMap::new(),
+           groups: HashMap::new(),
+           samples: HashMap::new(),
+           gps: Vec::new(),
+           laps: Vec::new(),
+           meta: HashMap::new(),
+           span: None }
+  }
+
+  fn parse(mut self) -> ParseResult<MemorySource> {
+    while self.pos < self.bytes.len() {
+      match self.slice(self.pos, 2)? {
+        b"<h" => self.header_record()?,
+        // closing tag of a container record
+        [b'<', _] => self.pos += 8,
+        b"(G" => self.group_record()?,
+        b"(S" => self.single_record()?,
+        b"(M" => self.multi_record()?,
+        _ => return Err(format!("unknown record at offset {}", self.pos)),
+      }
+    }
+    self.finish()
+  }
+
+  // RECORDS --------------------------------------------------------------- //
+  fn header_record(&mut self) -> ParseResult<()> {
+    let mut tag = [0u8; 4];
+    tag.copy_from_slice(self.slice(self.pos + 2, 4)?);
+    let len = read_u32(self.slice(self.pos + 6, 4)?, 0) as usize;
+
+    // the configuration container holds further records, we descend into it
+    if &tag == b"CNF\0" {
+      self.pos += 12;
+      return Ok(());
+    }
+
+    let body = self.slice(self.pos + 12, len)?;
+    let close = self.slice(self.pos + 12 + len, 8)?;
+    if !(close[0] == b'<' && close[1..5] == tag && close[7] == b'>') {
+      return Err(format!("malformed {} record at offset {}",
+                         String::from_utf8_lossy(&tag),
+                         self.pos));
+    }
+
+    match &tag {
+      b"CHS\0" => self.channel_description(body)?,
+      b"GRP\0" => self.group_description(body)?,
+      b"GPS1" => self.gps_fix(body)?,
+      b"LAP\0" => self.lap_marker(body)?,
+      b"RCR\0" | b"VEH\0" | b"CMP\0" | b"VTY\0" | b"TRK " | b"TMD\0"
+      | b"TMT\0" => {
+        let end = body.iter().position(|&b| b == 0).unwrap_or(body.len());
+        let value = String::from_utf8_lossy(&body[..end]).trim().to_string();
+        self.meta.insert(tag, value);
+      }
+      _ => {}
+    }
+
+    self.pos += 12 + len + 8;
+    Ok(())
+  }
+
+  fn group_record(&mut self) -> ParseResult<()> {
+    let timestamp = read_u32(self.slice(self.pos + 2, 4)?, 0);
+    let group = read_u16(self.slice(self.pos + 6, 2)?, 0);
+    let len = self.groups
+                  .get(&group)
+                  .ok_or_else(|| {
+                    format!("unknown channel group {} at offset {}",
+                            group, self.pos)
+                  })?
+                  .len();
+
+    let mut at = self.pos + 8;
+    for idx in 0..len {
+      let channel = self.groups[&group][idx];
+      at += self.push_sample(channel, timestamp, at)?;
+    }
+    self.close_data_record(at)
+  }
+
+  fn single_record(&mut self) -> ParseResult<()> {
+    let timestamp = read_u32(self.slice(self.pos + 2, 4)?, 0);
+    let channel = read_u16(self.slice(self.pos + 6, 2)?, 0);
+    let at = self.pos + 8;
+    let size = self.push_sample(channel, timestamp, at)?;
+    self.close_data_record(at + size)
+  }
+
+  fn multi_record(&mut self) -> ParseResult<()> {
+    let timestamp = read_u32(self.slice(self.pos + 2, 4)?, 0);
+    let channel = read_u16(self.slice(self.pos + 6, 2)?, 0);
+    let count = read_u16(self.slice(self.pos + 8, 2)?, 0) as u32;
+    let period = self.channel_info(channel)?.period;
+
+    let mut at = self.pos + 10;
+    for idx in 0..count {
+      let timestamp = idx.checked_mul(period)
+                         .and_then(|offset| timestamp.checked_add(offset))
+                         .ok_or_else(|| {
+                           format!("timestamp out of range in data record at \
+                                    offset {}",
+                                   self.pos)
+                         })?;
+      at += self.push_sample(channel, timestamp, at)?;
+    }
+    self.close_data_record(at)
+  }
+
+  fn close_data_record(&mut self, at: usize) -> ParseResult<()> {
+    if self.slice(at, 1)? != b")" {
+      return Err(format!("malformed data record at offset {}", self.pos));
+    }
+    self.pos = at + 1;
+    Ok(())
+  }
+
+  // RECORD BODIES --------------------------------------------------------- //
+  fn channel_description(&mut self, body: &[u8]) -> ParseResult<()> {
+    if body.len() < 76 {
+      return Err("channel description too short".to_string());
+    }
+    let text = |from: usize, to: usize| {
+      let raw = &body[from..to];
+      let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+      String::from_utf8_lossy(&raw[..end]).trim().to_string()
+    };
+
+    let unit = UNITS.iter()
+                    .find(|(code, _)| *code == body[12] & 0x7f)
+                    .map_or("", |(_, unit)| unit);
+    // older files don't carry a calibration
+    let (gain, offset) = match body.len() {
+      len if len >= 104 => (read_f32(body, 100), read_f32(body, 96)),
+      _ => (1.0, 0.0),
+    };
+    let info = ChannelInfo { name:      text(32, 56),
+                             unit:      unit.to_string(),
+                             source:    body[16],
+                             data_type: body[20],
+                             size:      read_u32(body, 72) as usize,
+                             period:    read_u32(body, 64) / 1000,
+                             gain,
+                             offset, };
+    self.channels.insert(read_u16(body, 0), info);
+    Ok(())
+  }
+
+  fn group_description(&mut self, body: &[u8]) -> ParseResult<()> {
+    if body.len() < 4 {
+      return Err("channel group description too short".to_string());
+    }
+    let values = body.chunks_exact(2)
+                     .map(|chunk| read_u16(chunk, 0))
+                     .collect::<Vec<_>>();
+    // first value is the group index, second the count of channels
+    self.groups.insert(values[0], values[2..].to_vec());
+    Ok(())
+  }
+
+  fn gps_fix(&mut self, body: &[u8]) -> ParseResult<()> {
+    if body.len() < 56 {
+      return Err("GPS record too short".to_string());
+    }
+    let cm = |at: usize| read_i32(body, at) as f64 / 100.0;
+    self.gps
+        .push(GpsFix { timestamp:         read_u32(body, 0) as f64 / 1000.0,
+                       time_of_week:      read_u32(body, 4) as f64,
+                       week:              read_u16(body, 12) as f64,
+                       fix:               body[14],
+                       position:          [cm(16), cm(20), cm(24)],
+                       position_accuracy: read_u32(body, 28) as f64 / 100.0,
+                       velocity:          [cm(32), cm(36), cm(40)],
+                       speed_accuracy:    read_u32(body, 44) as f64 / 100.0,
+                       satellites:        body[51] as f64, });
+    Ok(())
+  }
+
+  fn lap_marker(&mut self, body: &[u8]) -> ParseResult<()> {
+    if body.len() < 20 {
+      return Err("lap record too short".to_string());
+    }
+    // records with a non-zero segment index mark split times within a lap
+    if body[1] == 0 {
+      self.laps.push(LapMarker { end:  read_u32(body, 16),
+                                 time: read_u32(body, 4), });
+    }
+    Ok(())
+  }
+
+  // HELPERS --------------------------------------------------------------- //
+  fn slice(&self, at: usize, len: usize) -> ParseResult<&'a [u8]> {
+    self.bytes.get(at..at + len).ok_or_else(|| {
+                                  format!("unexpected end of file at offset \
+                                           {}",
+                                          at)
+                                })
+  }
+
+  fn channel_info(&self, channel: u16) -> ParseResult<&ChannelInfo> {
+    self.channels
+        .get(&channel)
+        .ok_or_else(|| {
+          format!("unknown channel {} at offset {}", channel, self.pos)
+        })
+  }
+
+  /// Decodes the sample of `channel` at offset `at`, applies the calibration
+  /// and stores it. Returns the size of the sample in bytes.
+  fn push_sample(&mut self,
+                 channel: u16,
+                 timestamp: u32,
+                 at: usize)
+                 -> ParseResult<usize> {
+    let (size, data_type, gain, offset) = {
+      let info = self.channel_info(channel)?;
+      (info.size, info.data_type, info.gain, info.offset)
+    };
+    let value = decode(data_type, self.slice(at, size)?) * gain + offset;
+
+    let (timestamps, samples) = self.samples.entry(channel).or_default();
+    timestamps.push(timestamp as f64 / 1000.0);
+    samples.push(value);
+
+    self.span = Some(match self.span {
+                       Some((first, last)) => {
+                         (first.min(timestamp), last.max(timestamp))
+                       }
+                       None => (timestamp, timestamp),
+                     });
+    Ok(size)
+  }
+
+  // ASSEMBLY -------------------------------------------------------------- //
+  fn finish(mut self) -> ParseResult<MemorySource> {
+    let mut source = MemorySource::new(self.lap_infos());
+
+    let meta = |tag: &[u8; 4]| self.meta.get(tag).cloned().unwrap_or_default();
+    source.set_championship(meta(b"CMP\0"))
+          .set_track(meta(b"TRK "))
+          .set_venue_type(meta(b"VTY\0"))
+          .set_vehicle(meta(b"VEH\0"))
+          .set_racer(meta(b"RCR\0"));
+    if let (Some(date), Some(time)) =
+      (self.meta.get(b"TMD\0"), self.meta.get(b"TMT\0"))
+    {
+      source.set_datetime(NaiveDateTime::parse_from_str(&format!("{} {}",
+                                                                 date,
+                                                                 time),
+                                                        "%m/%d/%Y %H:%M:%S")
+                                  .map_err(|e| {
+                                    format!("invalid date or time: {}", e)
+                                  })?);
+    }
+
+    for (idx, info) in &self.channels {
+      if HIDDEN_SOURCES.contains(&info.source) {
+        continue;
+      }
+      let (timestamps, samples) = self.samples.remove(idx).unwrap_or_default();
+      source.add_channel(ChannelGroup::Regular,
+                         Channel::new(info.name.clone(),
+                                      info.unit.clone(),
+                                      ChannelData::new(timestamps, samples)));
+    }
+
+    for channel in gps_raw_channels(&self.gps) {
+      source.add_channel(ChannelGroup::GpsRaw, channel);
+    }
+    for channel in gps_channels(&self.gps) {
+      source.add_channel(ChannelGroup::Gps, channel);
+    }
+
+    Ok(source)
+  }
+
+  /// Laps are delimited by the lap markers. Data recorded after the last
+  /// marker forms another lap; without any markers, the whole recording is
+  /// one lap.
+  fn lap_infos(&self) -> Vec<LapInfo> {
+    let mut infos = self.laps
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, marker)| {
+                          // a marker ending before the lap started is
+                          // malformed, the lap is taken to start at 0
+                          let start = marker.end.saturating_sub(marker.time);
+                          LapInfo::new(idx,
+                                       start as f64 / 1000.0,
+                                       marker.time as f64 / 1000.0)
+                        })
+                        .collect::<Vec<_>>();
+
+    if let Some((first, last)) = self.span {
+      let start = self.laps.last().map_or(first, |marker| marker.end);
+      if last > start {
+        infos.push(LapInfo::new(infos.len(),
+                                start as f64 / 1000.0,
+                                (last - start) as f64 / 1000.0));
+      }
+    }
+    infos
+  }
+}
+
+
+// DECODING ---------------------------------------------------------------- //
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+  u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+  u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn read_i32(bytes: &[u8], at: usize) -> i32 {
+  read_u32(bytes, at) as i32
+}
+
+fn read_f32(bytes: &[u8], at: usize) -> f64 {
+  f32::from_bits(read_u32(bytes, at)) as f64
+}
+
+/// Decodes a sample. Data type 20 denotes half precision and 6 single
+/// precision floats, everything else is read as signed integer.
+fn decode(data_type: u8, bytes: &[u8]) -> f64 {
+  match (data_type, bytes.len()) {
+    (20, 2) => f16_to_f64(read_u16(bytes, 0)),
+    (6, 4) => read_f32(bytes, 0),
+    (_, 1) => bytes[0] as i8 as f64,
+    (_, 2) => read_u16(bytes, 0) as i16 as f64,
+    (_, 4) => read_i32(bytes, 0) as f64,
+    _ => f64::NAN,
+  }
+}
+
+fn f16_to_f64(bits: u16) -> f64 {
+  let sign = if bits & 0x8000 == 0 { 1.0 } else { -1.0 };
+  let exponent = ((bits >> 10) & 0x1f) as i32;
+  let fraction = (bits & 0x3ff) as f64;
+
+  sign
+  * match exponent {
+    0 => fraction * 2f64.powi(-24),
+    0x1f if fraction == 0.0 => f64::INFINITY,
+    0x1f => f64::NAN,
+    _ => (1.0 + fraction / 1024.0) * 2f64.powi(exponent - 15),
+  }
+}
+
+
+// GPS --------------------------------------------------------------------- //
+fn gps_raw_channels(fixes: &[GpsFix]) -> Vec<Channel> {
+  let timestamps = fixes.iter().map(|fix| fix.timestamp).collect::<Vec<_>>();
+  let values: [fn(&GpsFix) -> f64; 9] = [|fix| fix.position[0],
+                                         |fix| fix.position[1],
+                                         |fix| fix.position[2],
+                                         |fix| fix.velocity[0],
+                                         |fix| fix.velocity[1],
+                                         |fix| fix.velocity[2],
+                                         |fix| fix.satellites,
+                                         |fix| fix.time_of_week,
+                                         |fix| fix.week];
+
+  GPS_RAW_CHANNELS.iter()
+                  .zip(values.iter())
+                  .map(|((name, unit), value)| {
+                    Channel::new(name.to_string(),
+                                 unit.to_string(),
+                                 ChannelData::new(timestamps.clone(),
+                                                  fixes.iter()
+                                                       .map(value)
+                                                       .collect()))
+                  })
+                  .collect()
+}
+
+/// Computes the GPS channels from all fixes with a valid 2D or 3D solution
+/// and resamples them to `GPS_RATE`.
+fn gps_channels(fixes: &[GpsFix]) -> Vec<Channel> {
+  let fixes = fixes.iter()
+                   .filter(|fix| fix.fix == 2 || fix.fix == 3)
+                   .collect::<Vec<_>>();
+  let timestamps = fixes.iter().map(|fix| fix.timestamp).collect::<Vec<_>>();
+
+  let (mut speed, mut slope, mut heading, mut altitude) =
+    (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+  for fix in &fixes {
+    let (latitude, longitude, height) = ecef_to_geodetic(fix.position);
+    let (east, north, up) = ecef_to_enu(fix.velocity, latitude, longitude);
+    let horizontal = east.hypot(north);
+
+    speed.push(horizontal);
+    slope.push(up.atan2(horizontal).to_degrees());
+    heading.push(east.atan2(north).to_degrees());
+    altitude.push(height);
+  }
+
+  // heading is unwrapped before differentiating and interpolating, so
+  // crossing north doesn't produce spikes in the yaw rate
+  let mut offset = 0.0;
+  let unwrapped = heading.iter()
+                         .enumerate()
+                         .map(|(idx, &value)| {
+                           if idx > 0 {
+                             let delta = value - heading[idx - 1];
+                             if delta > 180.0 {
+                               offset -= 360.0;
+                             } else if delta < -180.0 {
+                               offset += 360.0;
+                             }
+                           }
+                           value + offset
+                         })
+                         .collect::<Vec<_>>();
+
+  let gyro = differentiate(&timestamps, &unwrapped);
+  let lon_acc =
+    differentiate(&timestamps, &speed).iter()
+                                      .map(|a| a / STANDARD_GRAVITY)
+                                      .collect::<Vec<_>>();
+  let (lat_acc, radius): (Vec<_>, Vec<_>) =
+    speed.iter()
+         .zip(&gyro)
+         .map(|(v, w)| {
+           let w = w.to_radians();
+           let radius = if w.abs() > f64::EPSILON { v / w } else { 0.0 };
+           (v * w / STANDARD_GRAVITY, radius)
+         })
+         .unzip();
+
+  let columns = vec![speed,
+                     fixes.iter().map(|fix| fix.satellites).collect(),
+                     lat_acc,
+                     lon_acc,
+                     slope,
+                     unwrapped,
+                     gyro,
+                     altitude,
+                     fixes.iter().map(|fix| fix.position_accuracy).collect(),
+                     fixes.iter().map(|fix| fix.speed_accuracy).collect(),
+                     radius];
+
+  GPS_CHANNELS.iter()
+              .zip(columns)
+              .map(|((name, unit), samples)| {
+                let channel =
+                  Channel::new(name.to_string(),
+                               unit.to_string(),
+                               ChannelData::new(timestamps.clone(), samples));
+                // the count of satellites is a step function
+                let method = match *unit {
+                  "#" => Interpolation::ZeroOrderHold,
+                  _ => Interpolation::Linear,
+                };
+                // with less than two fixes there is nothing to interpolate
+                let channel =
+                  channel.resample(GPS_RATE, method).unwrap_or(channel);
+                match *name {
+                  "GPS Heading" => channel.map(|h| h.rem_euclid(360.0)),
+                  _ => channel,
+                }
+              })
+              .collect()
+}
+
+/// Central differences, one-sided at the edges.
+fn differentiate(timestamps: &[f64], values: &[f64]) -> Vec<f64> {
+  let len = values.len();
+  (0..len).map(|idx| {
+            let (prev, next) = (idx.saturating_sub(1), (idx + 1).min(len - 1));
+            let dt = timestamps[next] - timestamps[prev];
+            if dt > 0.0 {
+              (values[next] - values[prev]) / dt
+            } else {
+              0.0
+            }
+          })
+          .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  const XRK_PATH: &str =
+    "./testdata/072/TCR_EU-21_E02-LCA_Q1_HO-FK7-M5-X-S_072_A_1252.xrk";
+
+  #[test]
+  fn native_source_test() {
+    let source = NativeSource::open(Path::new(XRK_PATH)).unwrap();
+
+    assert_eq!("TCR_EU-21_E02-LCA", &source.championship().unwrap());
+    assert_eq!("HO-FK7-M5-X-S", &source.vehicle().unwrap());
+    assert_eq!("072", &source.racer().unwrap());
+    assert_eq!("Q1", &source.venue_type().unwrap());
+    assert_eq!("TCR_LCA_2.0", &source.track().unwrap());
+    assert_eq!(NaiveDateTime::parse_from_str("2021-05-29 10:00:09",
+                                             "%Y-%m-%d %H:%M:%S").unwrap(),
+               source.datetime().unwrap());
+
+    assert_eq!(2, source.laps_count().unwrap());
+    assert_eq!(LapInfo::new(0, 241.429, 9.642), source.lap_info(0).unwrap());
+    assert_eq!(LapInfo::new(1, 251.071, 170.567),
+               source.lap_info(1).unwrap());
+
+    let regular = ChannelGroup::Regular;
+    assert_eq!(68, source.channels_count(regular).unwrap());
+    assert_eq!("Logger Temperature",
+               &source.channel_name(regular, 0).unwrap());
+    assert_eq!("C", &source.channel_unit(regular, 0).unwrap());
+    assert_eq!("pManifoldScrut", &source.channel_name(regular, 2).unwrap());
+    assert_eq!("bar", &source.channel_unit(regular, 2).unwrap());
+    assert_eq!("fEngRpm", &source.channel_name(regular, 19).unwrap());
+    assert_eq!("rpm", &source.channel_unit(regular, 19).unwrap());
+
+    assert_eq!(172, source.channel_samples_count(regular, 0, None).unwrap());
+    assert_eq!(17894,
+               source.channel_samples_count(regular, 2, None).unwrap());
+    let rpm = source.channel_samples(regular, 19, None).unwrap();
+    assert_eq!(17405, rpm.len());
+    assert_eq!(1645.0, rpm.samples()[100]);
+    assert_eq!(32.09375,
+               source.channel_samples(regular, 0, None).unwrap().samples()
+                 [100]);
+
+    assert_eq!(9, source.channels_count(ChannelGroup::GpsRaw).unwrap());
+    assert_eq!(1799,
+               source.channel_samples_count(ChannelGroup::GpsRaw, 0, None)
+                     .unwrap());
+    assert_eq!(11, source.channels_count(ChannelGroup::Gps).unwrap());
+    assert_eq!("GPS Speed",
+               &source.channel_name(ChannelGroup::Gps, 0).unwrap());
+    // calibration offset of -0.0141 bar
+    assert_eq!(0.8140193819999695,
+               source.channel_samples(regular, 2, None).unwrap().samples()
+                 [100]);
+
+    // GPS channels are interpolated from 1799 fixes to 100 Hz
+    let speed = source.channel_samples(ChannelGroup::Gps, 0, None).unwrap();
+    assert_eq!(17978, speed.len());
+    assert_eq!(&[241.67, 241.68, 241.69], &speed.timestamps()[..3]);
+    let heading = source.channel_samples(ChannelGroup::Gps, 5, None).unwrap();
+    assert_eq!(true,
+               heading.samples()
+                      .iter()
+                      .all(|h| (0.0..360.0).contains(h)));
+  }
+
+  #[cfg(feature = "aim")]
+  #[test]
+  fn aim_comparison_test() {
+    let path = Path::new(XRK_PATH);
+    let (native, aim) = (NativeSource::open(path).unwrap(),
+                         super::super::AimSource::open(path).unwrap());
+
+    assert_eq!(aim.championship().unwrap(), native.championship().unwrap());
+    assert_eq!(aim.track().unwrap(), native.track().unwrap());
+    assert_eq!(aim.venue_type().unwrap(), native.venue_type().unwrap());
+    assert_eq!(aim.vehicle().unwrap(), native.vehicle().unwrap());
+    assert_eq!(aim.racer().unwrap(), native.racer().unwrap());
+
+    assert_eq!(aim.laps_count().unwrap(), native.laps_count().unwrap());
+    for lap_idx in 0..aim.laps_count().unwrap() {
+      assert_eq!(aim.lap_info(lap_idx).unwrap(),
+                 native.lap_info(lap_idx).unwrap());
+    }
+
+    // regular channels have to match sample by sample
+    let regular = ChannelGroup::Regular;
+    assert_eq!(aim.channels_count(regular).unwrap(),
+               native.channels_count(regular).unwrap());
+    for idx in 0..aim.channels_count(regular).unwrap() {
+      assert_eq!(aim.channel_name(regular, idx).unwrap(),
+                 native.channel_name(regular, idx).unwrap());
+      assert_eq!(aim.channel_unit(regular, idx).unwrap(),
+                 native.channel_unit(regular, idx).unwrap());
+      assert_eq!(aim.channel_samples(regular, idx, None).unwrap(),
+                 native.channel_samples(regular, idx, None).unwrap());
+    }
+
+    // GPS raw channels have the same layout, GPS channels are interpolated
+    // differently, so their samples are compared within a tolerance of 2% of
+    // the range of the channel
+    for group in [ChannelGroup::GpsRaw, ChannelGroup::Gps].iter() {
+      assert_eq!(aim.channels_count(*group).unwrap(),
+                 native.channels_count(*group).unwrap());
+      for idx in 0..aim.channels_count(*group).unwrap() {
+        let name = aim.channel_name(*group, idx).unwrap();
+        assert_eq!(name, native.channel_name(*group, idx).unwrap());
+        let (expected, actual) =
+          (aim.channel_samples(*group, idx, None).unwrap(),
+           native.channel_samples(*group, idx, None).unwrap());
+        assert_eq!(expected.len(), actual.len(), "{}", name);
+
+        let (min, max) = expected.samples()
+                                 .iter()
+                                 .fold((f64::INFINITY, f64::NEG_INFINITY),
+                                       |(min, max), &s| {
+                                         (min.min(s), max.max(s))
+                                       });
+        let tolerance = 0.02 * (max - min).max(1.0);
+        let deviating =
+          expected.samples()
+                  .iter()
+                  .zip(actual.samples())
+                  .filter(|(e, a)| {
+                    let delta = (*e - *a).abs();
+                    // headings close to north may wrap around
+                    let delta = match name.as_str() {
+                      "GPS Heading" => delta.min(360.0 - delta),
+                      _ => delta,
+                    };
+                    delta > tolerance
+                  })
+                  .count();
+        // transients at e.g. lost fixes are allowed to deviate
+        assert_eq!(true, deviating * 100 <= expected.len(), "{}", name);
+      }
+    }
+  }
+
+  #[test]
+  fn gps_conversion_test() {
+    // ECEF position of a fix in the test data, close to Le Castellet
+    let (latitude, longitude, height) =
+      ecef_to_geodetic([4_629_084.51, 468_537.68, 4_348_696.07]);
+    assert!((latitude.to_degrees() - 43.257_480).abs() < 1e-6);
+    assert!((longitude.to_degrees() - 5.779_570).abs() < 1e-6);
+    assert!((height - 464.008).abs() < 1e-3);
+
+    let (east, north, up) = ecef_to_enu([0.0, 0.0, 1.0], 0.0, 0.0);
+    assert_eq!((0.0, 1.0, 0.0), (east, north, up));
+  }
+
+  #[test]
+  fn malformed_test() {
+    // samples of a multi record running past the end of the logger clock
+    let mut bytes = b"(M".to_vec();
+    bytes.extend_from_slice(&(u32::MAX - 500).to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.push(b')');
+    let mut parser = Parser::new(&bytes);
+    parser.channels.insert(1,
+                           ChannelInfo { name:      "fEngRpm".to_string(),
+                                         unit:      "rpm".to_string(),
+                                         source:    0,
+                                         data_type: 0,
+                                         size:      2,
+                                         period:    1000,
+                                         gain:      1.0,
+                                         offset:    0.0, });
+    assert_eq!(true,
+               parser.multi_record()
+                     .unwrap_err()
+                     .starts_with("timestamp out of range"));
+
+    // a lap marker ending before the lap started
+    let mut parser = Parser::new(&[]);
+    parser.laps.push(LapMarker { end:  1000,
+                                 time: 5000, });
+    assert_eq!(vec![LapInfo::new(0, 0.0, 5.0)], parser.lap_infos());
+  }
+
+  #[test]
+  fn f16_test() {
+    assert_eq!(0.0, f16_to_f64(0x0000));
+    assert_eq!(1.0, f16_to_f64(0x3c00));
+    assert_eq!(-2.0, f16_to_f64(0xc000));
+    assert_eq!(65504.0, f16_to_f64(0x7bff));
+    assert_eq!(f64::INFINITY, f16_to_f64(0x7c00));
+  }
+}
@@ -0,0 +1,522 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+mod protocol;
+
+use super::{ChannelGroup, DataSource};
+use crate::{ChannelData, Error, LapInfo, Result};
+use chrono::NaiveDateTime;
+use protocol::{Request, Response};
+use std::{env,
+          io::{self, Read, Write},
+          path::{Path, PathBuf},
+          process::{Child, ChildStdin, Command, Stdio},
+          sync::{mpsc::{self, Receiver, RecvTimeoutError},
+                 Mutex},
+          thread,
+          time::{Duration, Instant}};
+
+
+/// Environment variable holding the path to the helper binary.
+const HELPER_PATH_VAR: &str = "XDRK_SANDBOX_HELPER";
+
+/// File name of the helper binary, which is looked for next to the current
+/// executable and then in `PATH` if `HELPER_PATH_VAR` isn't set.
+const HELPER_NAME: &str = if cfg!(target_family = "windows") {
+  "xdrk-sandbox.exe"
+} else {
+  "xdrk-sandbox"
+};
+
+/// Format used to transfer datetimes.
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+
+/// `DataSource` hosting the AiM library in a separate process.
+///
+/// Each `SandboxSource` starts its own instance of the `xdrk-sandbox` helper
+/// binary (built with the `sandbox` feature), which opens the file using the
+/// AiM library and answers requests over its stdin and stdout. If the helper
+/// crashes or doesn't answer within the timeout, it is killed and the request
+/// fails with `Error::Sandbox` or `Error::Timeout` respectively, as does every
+/// request after that.
+///
+/// The helper is looked for at the path in the environment variable
+/// `XDRK_SANDBOX_HELPER`, next to the current executable, and in `PATH`, in
+/// that order.
+#[derive(Debug)]
+pub struct SandboxSource {
+  path:    PathBuf,
+  idx:     usize,
+  timeout: Duration,
+  helper:  Mutex<Helper>,
+}
+
+impl Drop for SandboxSource {
+  /// Lets the helper close the file and exit, killing it if it doesn't do so
+  /// within the timeout.
+  fn drop(&mut self) {
+    let helper = match self.helper.get_mut() {
+      Ok(helper) => helper,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+    helper.stdin.take();
+
+    let deadline = Instant::now() + self.timeout;
+    while Instant::now() < deadline {
+      match helper.child.try_wait() {
+        Ok(None) => thread::sleep(Duration::from_millis(10)),
+        _ => return,
+      }
+    }
+    helper.kill();
+  }
+}
+
+impl SandboxSource {
+  /// How long the helper gets to answer a request by default. Generous,
+  /// since opening a file takes the AiM library up to 20 seconds on Windows.
+  pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+  /// Opens a drk/xrk file in a new helper process, using `DEFAULT_TIMEOUT`.
+  pub fn open(path: &Path) -> Result<Self> {
+    Self::open_with_timeout(path, Self::DEFAULT_TIMEOUT)
+  }
+
+  /// Opens a drk/xrk file in a new helper process, which has to answer every
+  /// request (including opening the file) within `timeout`.
+  pub fn open_with_timeout(path: &Path, timeout: Duration) -> Result<Self> {
+    if path.to_str().is_none() {
+      return Err(Error::InvalidPath { path:   path.to_owned(),
+                                      reason: "path is not valid unicode", });
+    }
+
+    let helper_path = helper_path();
+    let helper = Helper::spawn(&mut Command::new(&helper_path)).map_err(|e| {
+                   Error::Sandbox { path:   path.to_owned(),
+                                    reason: format!("could not start helper \
+                                                     {}: {}",
+                                                    helper_path.display(),
+                                                    e), }
+                 })?;
+
+    let mut source = Self { path: path.to_owned(),
+                            idx: 0,
+                            timeout,
+                            helper: Mutex::new(helper) };
+    source.idx = source.count(Request::Open(path.to_owned()))?;
+    Ok(source)
+  }
+
+  /// Serves requests read from `input`, writing the responses to `output`,
+  /// until `input` ends. Files are opened using `open`.
+  ///
+  /// This is what the `xdrk-sandbox` helper binary runs, there should be no
+  /// need to call it directly.
+  #[doc(hidden)]
+  pub fn serve<R, W, F>(mut input: R,
+                        mut output: W,
+                        open: F)
+                        -> io::Result<()>
+    where R: Read,
+          W: Write,
+          F: Fn(&Path) -> Result<Box<dyn DataSource>>
+  {
+    let mut source: Option<Box<dyn DataSource>> = None;
+    while let Some(frame) = protocol::read_frame(&mut input)? {
+      let response = match (Request::decode(&frame), &source) {
+        (Some(Request::Open(path)), _) => match open(&path) {
+          Ok(opened) => {
+            let response = Response::Count(opened.idx());
+            source = Some(opened);
+            response
+          }
+          Err(e) => Response::Failed(e),
+        },
+        (Some(request), Some(source)) => respond(source.as_ref(), request),
+        (request, _) => {
+          let reason = match request {
+            Some(_) => "no file opened",
+            None => "malformed request",
+          };
+          Response::Failed(Error::Sandbox { path:   PathBuf::new(),
+                                            reason: reason.to_string(), })
+        }
+      };
+      protocol::write_frame(&mut output, &response.encode())?;
+    }
+    Ok(())
+  }
+
+  /// Sends `request` to the helper and waits for the response.
+  fn request(&self, request: Request) -> Result<Response> {
+    let mut helper = self.helper.lock().unwrap();
+    let frame = helper.roundtrip(&request.encode(), self.timeout)
+                      .map_err(|failure| match failure {
+                        Failure::Timeout => {
+                          Error::Timeout { path:    self.path.clone(),
+                                           timeout: self.timeout, }
+                        }
+                        Failure::Gone(reason) => {
+                          Error::Sandbox { path: self.path.clone(),
+                                           reason }
+                        }
+                      })?;
+
+    match Response::decode(&frame, &self.path) {
+      Some(Response::Failed(error)) => Err(error),
+      Some(response) => Ok(response),
+      None => Err(self.malformed()),
+    }
+  }
+
+  fn count(&self, request: Request) -> Result<usize> {
+    match self.request(request)? {
+      Response::Count(count) => Ok(count),
+      _ => Err(self.malformed()),
+    }
+  }
+
+  fn text(&self, request: Request) -> Result<String> {
+    match self.request(request)? {
+      Response::Text(text) => Ok(text),
+      _ => Err(self.malformed()),
+    }
+  }
+
+  fn malformed(&self) -> Error {
+    Error::Sandbox { path:   self.path.clone(),
+                     reason: "malformed response".to_string(), }
+  }
+}
+
+impl DataSource for SandboxSource {
+  fn path(&self) -> &Path {
+    &self.path
+  }
+
+  fn idx(&self) -> usize {
+    self.idx
+  }
+
+  // RUN LEVEL FUNCTIONS --------------------------------------------------- //
+  fn championship(&self) -> Result<String> {
+    self.text(Request::Championship)
+  }
+
+  fn track(&self) -> Result<String> {
+    self.text(Request::Track)
+  }
+
+  fn venue_type(&self) -> Result<String> {
+    self.text(Request::VenueType)
+  }
+
+  fn vehicle(&self) -> Result<String> {
+    self.text(Request::Vehicle)
+  }
+
+  fn racer(&self) -> Result<String> {
+    self.text(Request::Racer)
+  }
+
+  fn datetime(&self) -> Result<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(&self.text(Request::Datetime)?,
+                                  DATETIME_FORMAT).map_err(|_| {
+                                                    self.malformed()
+                                                  })
+  }
+
+  fn laps_count(&self) -> Result<usize> {
+    self.count(Request::LapsCount)
+  }
+
+  fn lap_info(&self, lap_idx: usize) -> Result<LapInfo> {
+    match self.request(Request::LapInfo(lap_idx))? {
+      Response::LapInfo(start, time) => Ok(LapInfo::new(lap_idx, start, time)),
+      _ => Err(self.malformed()),
+    }
+  }
+
+  // CHANNEL INFORMATION FUNCTIONS ----------------------------------------- //
+  fn channels_count(&self, group: ChannelGroup) -> Result<usize> {
+    self.count(Request::ChannelsCount(group))
+  }
+
+  fn channel_name(&self,
+                  group: ChannelGroup,
+                  channel_idx: usize)
+                  -> Result<String> {
+    self.text(Request::ChannelName(group, channel_idx))
+  }
+
+  fn channel_unit(&self,
+                  group: ChannelGroup,
+                  channel_idx: usize)
+                  -> Result<String> {
+    self.text(Request::ChannelUnit(group, channel_idx))
+  }
+
+  fn channel_samples_count(&self,
+                           group: ChannelGroup,
+                           channel_idx: usize,
+                           lap_idx: Option<usize>)
+                           -> Result<usize> {
+    self.count(Request::ChannelSamplesCount(group, channel_idx, lap_idx))
+  }
+
+  fn channel_samples(&self,
+                     group: ChannelGroup,
+                     channel_idx: usize,
+                     lap_idx: Option<usize>)
+                     -> Result<ChannelData> {
+    match self.request(Request::ChannelSamples(group, channel_idx, lap_idx))? {
+      Response::Samples(data) => Ok(data),
+      _ => Err(self.malformed()),
+    }
+  }
+}
+
+
+/// Why the helper couldn't answer a request.
+#[derive(Debug)]
+enum Failure {
+  Timeout,
+  Gone(String),
+}
+
+
+/// The helper process and the ends of the pipes connected to it.
+#[derive(Debug)]
+struct Helper {
+  child:     Child,
+  /// `None` once the helper was told to exit.
+  stdin:     Option<ChildStdin>,
+  /// Frames read from the helper's stdout by a separate thread, so waiting
+  /// for them can time out.
+  responses: Receiver<io::Result<Option<Vec<u8>>>>,
+  /// Set once the helper failed, it is not used anymore after that.
+  failed:    Option<String>,
+}
+
+impl Helper {
+  fn spawn(command: &mut Command) -> io::Result<Self> {
+    let mut child = command.stdin(Stdio::piped())
+                           .stdout(Stdio::piped())
+                           .stderr(Stdio::inherit())
+                           .spawn()?;
+    let (stdin, mut stdout) =
+      (child.stdin.take(), child.stdout.take().expect("stdout is piped"));
+
+    let (sender, responses) = mpsc::channel();
+    thread::Builder::new().name("xdrk-sandbox-reader".to_string())
+                          .spawn(move || loop {
+                            let frame = protocol::read_frame(&mut stdout);
+                            let done = !matches!(frame, Ok(Some(_)));
+                            if sender.send(frame).is_err() || done {
+                              break;
+                            }
+                          })?;
+
+    Ok(Self { child,
+              stdin,
+              responses,
+              failed: None })
+  }
+
+  /// Sends a request frame and waits up to `timeout` for the response frame.
+  /// On failure, the helper is killed and all further requests fail.
+  fn roundtrip(&mut self,
+               request: &[u8],
+               timeout: Duration)
+               -> std::result::Result<Vec<u8>, Failure> {
+    if let Some(reason) = &self.failed {
+      return Err(Failure::Gone(reason.clone()));
+    }
+
+    let sent = match self.stdin.as_mut() {
+      Some(stdin) => protocol::write_frame(stdin, request).is_ok(),
+      None => false,
+    };
+    let failure = match sent.then(|| self.responses.recv_timeout(timeout)) {
+      Some(Ok(Ok(Some(frame)))) => return Ok(frame),
+      Some(Err(RecvTimeoutError::Timeout)) => {
+        self.kill();
+        self.failed = Some(format!("helper killed after not answering \
+                                    within {:?}",
+                                   timeout));
+        Failure::Timeout
+      }
+      _ => {
+        let reason = self.kill();
+        self.failed = Some(reason.clone());
+        Failure::Gone(reason)
+      }
+    };
+    Err(failure)
+  }
+
+  /// Kills the helper if it is still running, returning how it exited.
+  fn kill(&mut self) -> String {
+    self.stdin.take();
+    let _ = self.child.kill();
+    match self.child.wait() {
+      Ok(status) => format!("helper exited ({})", status),
+      Err(e) => format!("helper is gone ({})", e),
+    }
+  }
+}
+
+
+/// Answers a request using the file opened in the helper.
+fn respond(source: &dyn DataSource, request: Request) -> Response {
+  let response = match request {
+    Request::Open(_) => unreachable!("handled by `serve`"),
+    Request::Championship => source.championship().map(Response::Text),
+    Request::Track => source.track().map(Response::Text),
+    Request::VenueType => source.venue_type().map(Response::Text),
+    Request::Vehicle => source.vehicle().map(Response::Text),
+    Request::Racer => source.racer().map(Response::Text),
+    Request::Datetime => {
+      source.datetime()
+            .map(|datetime| {
+              Response::Text(datetime.format(DATETIME_FORMAT).to_string())
+            })
+    }
+    Request::LapsCount => source.laps_count().map(Response::Count),
+    Request::LapInfo(lap_idx) => {
+      source.lap_info(lap_idx)
+            .map(|info| Response::LapInfo(info.start(), info.time()))
+    }
+    Request::ChannelsCount(group) => {
+      source.channels_count(group).map(Response::Count)
+    }
+    Request::ChannelName(group, channel_idx) => {
+      source.channel_name(group, channel_idx).map(Response::Text)
+    }
+    Request::ChannelUnit(group, channel_idx) => {
+      source.channel_unit(group, channel_idx).map(Response::Text)
+    }
+    Request::ChannelSamplesCount(group, channel_idx, lap_idx) => {
+      source.channel_samples_count(group, channel_idx, lap_idx)
+            .map(Response::Count)
+    }
+    Request::ChannelSamples(group, channel_idx, lap_idx) => {
+      source.channel_samples(group, channel_idx, lap_idx)
+            .map(Response::Samples)
+    }
+  };
+  response.unwrap_or_else(Response::Failed)
+}
+
+/// Where to find the helper binary, see `SandboxSource`.
+fn helper_path() -> PathBuf {
+  if let Some(path) = env::var_os(HELPER_PATH_VAR) {
+    return path.into();
+  }
+
+  // test executables end up in `deps` next to the binaries, hence also look
+  // one directory up
+  env::current_exe().ok()
+                    .and_then(|exe| {
+                      exe.ancestors()
+                         .skip(1)
+                         .take(2)
+                         .map(|dir| dir.join(HELPER_NAME))
+                         .find(|path| path.is_file())
+                    })
+                    .unwrap_or_else(|| HELPER_NAME.into())
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::{super::MemorySource, *};
+  use crate::Channel;
+  use pretty_assertions::assert_eq;
+
+
+  /// Sends `requests` through `serve` in one go, returning the responses.
+  fn serve(requests: &[Request]) -> Vec<Response> {
+    let mut input = Vec::new();
+    for request in requests {
+      protocol::write_frame(&mut input, &request.encode()).unwrap();
+    }
+
+    let mut output = Vec::new();
+    SandboxSource::serve(input.as_slice(), &mut output, |path| {
+      let mut source = MemorySource::new(vec![LapInfo::new(0, 0.0, 1.0)]);
+      source.set_path(path.to_owned())
+            .set_vehicle("HO-FK7-M5-X-S".to_string())
+            .add_channel(ChannelGroup::Regular,
+                         Channel::new("fEngRpm".to_string(),
+                                      "rpm".to_string(),
+                                      ChannelData::new(vec![0.0, 0.5],
+                                                       vec![7e3, 8e3])));
+      Ok(Box::new(source))
+    }).unwrap();
+
+    let path = Path::new("run.xrk");
+    let mut output = output.as_slice();
+    let mut responses = Vec::new();
+    while let Some(frame) = protocol::read_frame(&mut output).unwrap() {
+      responses.push(Response::decode(&frame, path).unwrap());
+    }
+    responses
+  }
+
+  #[test]
+  fn serve_test() {
+    let responses =
+      serve(&[Request::Vehicle,
+              Request::Open("run.xrk".into()),
+              Request::Vehicle,
+              Request::ChannelSamples(ChannelGroup::Regular, 0, Some(0)),
+              Request::ChannelName(ChannelGroup::Gps, 0)]);
+    assert_eq!(5, responses.len());
+    assert_eq!(true,
+               matches!(&responses[0],
+                        Response::Failed(Error::Sandbox { .. })));
+    assert_eq!(true, matches!(&responses[1], Response::Count(0)));
+    assert_eq!(true,
+               matches!(&responses[2], Response::Text(vehicle)
+                                       if vehicle == "HO-FK7-M5-X-S"));
+    assert_eq!(true,
+               matches!(&responses[3], Response::Samples(data)
+                                       if data.samples() == &[7e3, 8e3]));
+    assert_eq!(true,
+               matches!(&responses[4],
+                        Response::Failed(Error::Sandbox { reason, .. })
+                        if reason.contains("GPS channel index 0")));
+  }
+
+  #[cfg(target_family = "unix")]
+  #[test]
+  fn helper_test() {
+    let timeout = Duration::from_millis(200);
+
+    // echoes every request, i.e. answers with a frame
+    let mut helper = Helper::spawn(&mut Command::new("cat")).unwrap();
+    assert_eq!(vec![1, 2, 3],
+               helper.roundtrip(&[1, 2, 3], timeout).unwrap());
+
+    // never answers
+    let mut helper = Helper::spawn(Command::new("sleep").arg("10")).unwrap();
+    assert_eq!(true,
+               matches!(helper.roundtrip(&[1], timeout),
+                        Err(Failure::Timeout)));
+    assert_eq!(true,
+               matches!(helper.roundtrip(&[1], timeout),
+                        Err(Failure::Gone(_))));
+
+    // dies right away
+    let mut helper = Helper::spawn(&mut Command::new("false")).unwrap();
+    assert_eq!(true,
+               matches!(helper.roundtrip(&[1], timeout),
+                        Err(Failure::Gone(reason))
+                          if reason.contains("exit")));
+  }
+}
@@ -0,0 +1,360 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Wire format between `SandboxSource` and the helper process.
+//!
+//! Every message is sent as a frame: its length as `u32`, followed by that
+//! many bytes. Within messages, integers are little endian, floats are `f64`,
+//! indices and counts are `u32`, strings are prefixed with their length and
+//! optional values with a `u8` flag. Requests start with an opcode, responses
+//! with a tag telling what kind of result follows.
+
+use crate::{bindings, ChannelData, ChannelGroup, Error};
+use std::{convert::TryInto,
+          io::{self, Read, Write},
+          path::{Path, PathBuf}};
+
+
+/// Upper bound for the length of a frame, to not allocate arbitrary amounts
+/// of memory when reading garbage.
+const MAX_FRAME_LEN: usize = 1 << 30;
+
+
+/// Writes `body` as a frame.
+pub(super) fn write_frame<W: Write>(writer: &mut W,
+                                    body: &[u8])
+                                    -> io::Result<()> {
+  writer.write_all(&(body.len() as u32).to_le_bytes())?;
+  writer.write_all(body)?;
+  writer.flush()
+}
+
+/// Reads a frame, returning `None` if the stream ended before it started.
+pub(super) fn read_frame<R: Read>(reader: &mut R)
+                                  -> io::Result<Option<Vec<u8>>> {
+  let mut len = [0u8; 4];
+  match reader.read_exact(&mut len) {
+    Ok(()) => {}
+    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+    Err(e) => return Err(e),
+  }
+
+  let len = u32::from_le_bytes(len) as usize;
+  if len > MAX_FRAME_LEN {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+  }
+  let mut body = vec![0u8; len];
+  reader.read_exact(&mut body)?;
+  Ok(Some(body))
+}
+
+
+/// Requests sent to the helper. All but `Open` refer to the file opened
+/// before.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Request {
+  Open(PathBuf),
+  Championship,
+  Track,
+  VenueType,
+  Vehicle,
+  Racer,
+  Datetime,
+  LapsCount,
+  LapInfo(usize),
+  ChannelsCount(ChannelGroup),
+  ChannelName(ChannelGroup, usize),
+  ChannelUnit(ChannelGroup, usize),
+  ChannelSamplesCount(ChannelGroup, usize, Option<usize>),
+  ChannelSamples(ChannelGroup, usize, Option<usize>),
+}
+
+impl Request {
+  pub(super) fn encode(&self) -> Vec<u8> {
+    let mut w = Writer::default();
+    match self {
+      Self::Open(path) => w.u8(0).str(&path.to_string_lossy()),
+      Self::Championship => w.u8(1),
+      Self::Track => w.u8(2),
+      Self::VenueType => w.u8(3),
+      Self::Vehicle => w.u8(4),
+      Self::Racer => w.u8(5),
+      Self::Datetime => w.u8(6),
+      Self::LapsCount => w.u8(7),
+      Self::LapInfo(lap_idx) => w.u8(8).usize(*lap_idx),
+      Self::ChannelsCount(group) => w.u8(9).group(*group),
+      Self::ChannelName(group, channel_idx) => {
+        w.u8(10).group(*group).usize(*channel_idx)
+      }
+      Self::ChannelUnit(group, channel_idx) => {
+        w.u8(11).group(*group).usize(*channel_idx)
+      }
+      Self::ChannelSamplesCount(group, channel_idx, lap_idx) => {
+        w.u8(12).group(*group).usize(*channel_idx).lap(*lap_idx)
+      }
+      Self::ChannelSamples(group, channel_idx, lap_idx) => {
+        w.u8(13).group(*group).usize(*channel_idx).lap(*lap_idx)
+      }
+    };
+    w.0
+  }
+
+  pub(super) fn decode(bytes: &[u8]) -> Option<Self> {
+    let mut r = Reader { bytes, pos: 0 };
+    let request = match r.u8()? {
+      0 => Self::Open(r.str()?.into()),
+      1 => Self::Championship,
+      2 => Self::Track,
+      3 => Self::VenueType,
+      4 => Self::Vehicle,
+      5 => Self::Racer,
+      6 => Self::Datetime,
+      7 => Self::LapsCount,
+      8 => Self::LapInfo(r.usize()?),
+      9 => Self::ChannelsCount(r.group()?),
+      10 => Self::ChannelName(r.group()?, r.usize()?),
+      11 => Self::ChannelUnit(r.group()?, r.usize()?),
+      12 => Self::ChannelSamplesCount(r.group()?, r.usize()?, r.lap()?),
+      13 => Self::ChannelSamples(r.group()?, r.usize()?, r.lap()?),
+      _ => return None,
+    };
+    r.finish(request)
+  }
+}
+
+
+/// Responses sent by the helper, one per request.
+#[derive(Debug)]
+pub(super) enum Response {
+  Count(usize),
+  Text(String),
+  LapInfo(f64, f64),
+  Samples(ChannelData),
+  Failed(Error),
+}
+
+impl Response {
+  pub(super) fn encode(&self) -> Vec<u8> {
+    let mut w = Writer::default();
+    match self {
+      Self::Count(count) => w.u8(0).usize(*count),
+      Self::Text(text) => w.u8(1).str(text),
+      Self::LapInfo(start, time) => w.u8(2).f64(*start).f64(*time),
+      Self::Samples(data) => {
+        w.u8(3).usize(data.len());
+        for value in data.timestamps().iter().chain(data.samples()) {
+          w.f64(*value);
+        }
+        &mut w
+      }
+      // errors the caller may want to react to are passed on as they are,
+      // all others only by their message
+      Self::Failed(Error::Library { call, code, .. }) => {
+        w.u8(4).str(call).i32(*code)
+      }
+      Self::Failed(Error::LibraryData { call, .. }) => w.u8(5).str(call),
+      Self::Failed(Error::Unparsable { reason, .. }) => w.u8(6).str(reason),
+      Self::Failed(error) => w.u8(7).str(&error.to_string()),
+    };
+    w.0
+  }
+
+  /// Decodes a response; errors are attributed to the file at `path`.
+  pub(super) fn decode(bytes: &[u8], path: &Path) -> Option<Self> {
+    let mut r = Reader { bytes, pos: 0 };
+    let path = path.to_owned();
+    let response = match r.u8()? {
+      0 => Self::Count(r.usize()?),
+      1 => Self::Text(r.str()?),
+      2 => Self::LapInfo(r.f64()?, r.f64()?),
+      3 => {
+        let len = r.usize()?;
+        let values =
+          (0..2 * len).map(|_| r.f64()).collect::<Option<Vec<_>>>()?;
+        let (timestamps, samples) = values.split_at(len);
+        Self::Samples(ChannelData::new(timestamps.to_vec(), samples.to_vec()))
+      }
+      4 => Self::Failed(Error::Library { path,
+                                         call: symbol(&r.str()?),
+                                         code: r.i32()? }),
+      5 => Self::Failed(Error::LibraryData { path,
+                                             call: symbol(&r.str()?) }),
+      6 => Self::Failed(Error::Unparsable { path,
+                                            reason: r.str()? }),
+      7 => Self::Failed(Error::Sandbox { path,
+                                         reason: r.str()? }),
+      _ => return None,
+    };
+    r.finish(response)
+  }
+}
+
+
+/// Maps the name of a library function received over the wire back to the
+/// name of the binding.
+fn symbol(name: &str) -> &'static str {
+  bindings::SYMBOLS.iter()
+                   .find(|symbol| **symbol == name)
+                   .copied()
+                   .unwrap_or("unknown")
+}
+
+
+#[derive(Debug, Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+  fn u8(&mut self, value: u8) -> &mut Self {
+    self.0.push(value);
+    self
+  }
+
+  fn i32(&mut self, value: i32) -> &mut Self {
+    self.0.extend_from_slice(&value.to_le_bytes());
+    self
+  }
+
+  fn usize(&mut self, value: usize) -> &mut Self {
+    self.0.extend_from_slice(&(value as u32).to_le_bytes());
+    self
+  }
+
+  fn f64(&mut self, value: f64) -> &mut Self {
+    self.0.extend_from_slice(&value.to_le_bytes());
+    self
+  }
+
+  fn str(&mut self, value: &str) -> &mut Self {
+    self.usize(value.len());
+    self.0.extend_from_slice(value.as_bytes());
+    self
+  }
+
+  fn group(&mut self, group: ChannelGroup) -> &mut Self {
+    self.u8(match group {
+              ChannelGroup::Regular => 0,
+              ChannelGroup::Gps => 1,
+              ChannelGroup::GpsRaw => 2,
+            })
+  }
+
+  fn lap(&mut self, lap_idx: Option<usize>) -> &mut Self {
+    match lap_idx {
+      Some(lap_idx) => self.u8(1).usize(lap_idx),
+      None => self.u8(0),
+    }
+  }
+}
+
+
+#[derive(Debug)]
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos:   usize,
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+    let bytes = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+    self.pos += len;
+    Some(bytes)
+  }
+
+  fn u8(&mut self) -> Option<u8> {
+    Some(self.take(1)?[0])
+  }
+
+  fn i32(&mut self) -> Option<i32> {
+    Some(i32::from_le_bytes(self.take(4)?.try_into().ok()?))
+  }
+
+  fn usize(&mut self) -> Option<usize> {
+    Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?) as usize)
+  }
+
+  fn f64(&mut self) -> Option<f64> {
+    Some(f64::from_le_bytes(self.take(8)?.try_into().ok()?))
+  }
+
+  fn str(&mut self) -> Option<String> {
+    let len = self.usize()?;
+    String::from_utf8(self.take(len)?.to_vec()).ok()
+  }
+
+  fn group(&mut self) -> Option<ChannelGroup> {
+    match self.u8()? {
+      0 => Some(ChannelGroup::Regular),
+      1 => Some(ChannelGroup::Gps),
+      2 => Some(ChannelGroup::GpsRaw),
+      _ => None,
+    }
+  }
+
+  fn lap(&mut self) -> Option<Option<usize>> {
+    match self.u8()? {
+      0 => Some(None),
+      1 => Some(Some(self.usize()?)),
+      _ => None,
+    }
+  }
+
+  /// Returns `value` if all bytes were consumed.
+  fn finish<T>(self, value: T) -> Option<T> {
+    if self.pos == self.bytes.len() {
+      Some(value)
+    } else {
+      None
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn protocol_test() {
+    let requests =
+      vec![Request::Open("./testdata/072/run.xrk".into()),
+           Request::Racer,
+           Request::LapInfo(1),
+           Request::ChannelName(ChannelGroup::Gps, 3),
+           Request::ChannelSamples(ChannelGroup::GpsRaw, 2, Some(1)),
+           Request::ChannelSamplesCount(ChannelGroup::Regular, 19, None),];
+    for request in requests {
+      assert_eq!(Some(request.clone()), Request::decode(&request.encode()));
+    }
+    assert_eq!(None, Request::decode(&[9, 3]));
+    assert_eq!(None, Request::decode(&[1, 0]));
+
+    let path = Path::new("run.xrk");
+    let data = ChannelData::new(vec![0.0, 0.1], vec![1.0, 2.0]);
+    match Response::decode(&Response::Samples(data.clone()).encode(), path) {
+      Some(Response::Samples(decoded)) => assert_eq!(data, decoded),
+      other => panic!("unexpected response {:?}", other),
+    }
+    let error = Error::Library { path: "elsewhere.xrk".into(),
+                                 call: "get_laps_count",
+                                 code: -3, };
+    match Response::decode(&Response::Failed(error).encode(), path) {
+      Some(Response::Failed(error)) => {
+        assert_eq!("library call `get_laps_count` failed with code -3 \
+                    (run.xrk)",
+                   error.to_string())
+      }
+      other => panic!("unexpected response {:?}", other),
+    }
+
+    let mut stream = Vec::new();
+    write_frame(&mut stream, &[1, 2, 3]).unwrap();
+    let mut reader = stream.as_slice();
+    assert_eq!(Some(vec![1, 2, 3]), read_frame(&mut reader).unwrap());
+    assert_eq!(None, read_frame(&mut reader).unwrap());
+  }
+}
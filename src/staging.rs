@@ -0,0 +1,86 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Staging of file contents which don't come from a path, e.g. data held in
+//! memory.
+//!
+//! The AiM library can only open files by path and drops temporary files next
+//! to them, so contents are written to a private temporary directory first,
+//! which is removed again (along with anything the library left in it) when
+//! the `Staging` is dropped.
+
+use crate::{Error, Result};
+use std::{env,
+          fs::File,
+          io::{self, Read},
+          path::{Path, PathBuf}};
+use tempfile::TempDir;
+
+
+/// Name of the staged file within the temporary directory. The AiM library
+/// tells XRK and DRK files apart by their contents, so any of the two
+/// extensions does.
+const FILE_NAME: &str = "run.xrk";
+
+
+/// A file staged into a temporary directory owned by xdrk.
+#[derive(Debug)]
+pub(crate) struct Staging {
+  path: PathBuf,
+  _dir: TempDir,
+}
+
+impl Staging {
+  /// Creates a temporary directory and copies everything `reader` yields
+  /// into a file within it.
+  pub(crate) fn new<R: Read>(mut reader: R) -> Result<Self> {
+    let dir = tempfile::Builder::new().prefix("xdrk-")
+                                      .tempdir()
+                                      .map_err(|source| {
+                                        Error::Io { path: env::temp_dir(),
+                                                    source }
+                                      })?;
+    let path = dir.path().join(FILE_NAME);
+    let io_error = |source| Error::Io { path: path.clone(),
+                                        source };
+
+    let mut file = File::create(&path).map_err(io_error)?;
+    io::copy(&mut reader, &mut file).map_err(io_error)?;
+    file.sync_all().map_err(io_error)?;
+
+    Ok(Self { path, _dir: dir })
+  }
+
+  /// Path of the staged file.
+  pub(crate) fn path(&self) -> &Path {
+    &self.path
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pretty_assertions::assert_eq;
+  use std::fs;
+
+
+  #[test]
+  fn staging_test() {
+    let staging = Staging::new(&b"contents"[..]).unwrap();
+    let path = staging.path().to_owned();
+    let dir = path.parent().unwrap().to_owned();
+
+    assert_eq!(b"contents".to_vec(), fs::read(&path).unwrap());
+    assert_eq!(true, dir.starts_with(env::temp_dir()));
+    assert_eq!(true, path.file_name().unwrap() == FILE_NAME);
+
+    // whatever ends up in the directory is removed along with it
+    fs::write(dir.join("run.rrk"), b"left behind").unwrap();
+    drop(staging);
+    assert_eq!(false, dir.exists());
+  }
+}
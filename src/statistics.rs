@@ -0,0 +1,364 @@
+// Copyright 2021 bmc::labs Gmbh. All rights reserved.
+//
+// Authors:
+//   Florian Eich <florian@bmc-labs.com>
+//   Jonas Reitemeyer <alumni@bmc-labs.com>
+
+//! Statistics of channels, and a summary of them for all laps of a run.
+
+use super::{Channel, Lap, Result, Run};
+use getset::{CopyGetters, Getters};
+use std::{fmt, io};
+
+
+/// A statistic of the samples of a channel.
+///
+/// All statistics are weighted by time, taking the channel to change
+/// linearly between samples, so that they don't depend on the sampling rate
+/// and aren't skewed where samples are spaced irregularly.
+///
+/// NaN samples, e.g. from sensor dropouts or data types the reader doesn't
+/// know, are left out of all statistics, i.e. the channel is taken to change
+/// linearly from the sample before them to the one after.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statistic {
+  /// Smallest sample.
+  Min,
+  /// Largest sample.
+  Max,
+  /// Mean over time.
+  Mean,
+  /// Value the channel stays at or below for the given percentage of the
+  /// time, from 0 to 100, e.g. 50 for the median.
+  Percentile(f64),
+  /// Time in s the channel spends above the given value.
+  TimeAbove(f64),
+  /// Time in s the channel spends below the given value.
+  TimeBelow(f64),
+  /// Integral over time, in the unit of the channel times s.
+  Integral,
+}
+
+impl Statistic {
+  /// Computes the statistic of `channel`, `None` if it contains no samples
+  /// other than NaN.
+  pub fn of(&self, channel: &Channel) -> Option<f64> {
+    let (timestamps, samples) =
+      (channel.data().timestamps(), channel.data().samples());
+    if samples.iter().any(|sample| sample.is_nan()) {
+      let (timestamps, samples): (Vec<f64>, Vec<f64>) =
+        timestamps.iter()
+                  .zip(samples.iter())
+                  .filter(|(_, sample)| !sample.is_nan())
+                  .unzip();
+      return self.of_samples(&timestamps, &samples);
+    }
+    self.of_samples(timestamps, samples)
+  }
+
+  /// Computes the statistic of samples none of which are NaN.
+  fn of_samples(&self, timestamps: &[f64], samples: &[f64]) -> Option<f64> {
+    if samples.is_empty() {
+      return None;
+    }
+    let value = match *self {
+      Self::Min => samples.iter().copied().fold(f64::MAX, f64::min),
+      Self::Max => samples.iter().copied().fold(f64::MIN, f64::max),
+      Self::Mean => {
+        let duration = timestamps[timestamps.len() - 1] - timestamps[0];
+        if duration > 0.0 {
+          integral(timestamps, samples) / duration
+        } else {
+          samples.iter().sum::<f64>() / samples.len() as f64
+        }
+      }
+      Self::Percentile(percent) => percentile(timestamps, samples, percent),
+      Self::TimeAbove(level) => time_above(timestamps, samples, level),
+      Self::TimeBelow(level) => {
+        let negated = samples.iter().map(|v| -v).collect::<Vec<_>>();
+        time_above(timestamps, &negated, -level)
+      }
+      Self::Integral => integral(timestamps, samples),
+    };
+    Some(value)
+  }
+}
+
+impl fmt::Display for Statistic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Min => write!(f, "min"),
+      Self::Max => write!(f, "max"),
+      Self::Mean => write!(f, "mean"),
+      Self::Percentile(percent) => write!(f, "p{}", percent),
+      Self::TimeAbove(level) => write!(f, "time above {}", level),
+      Self::TimeBelow(level) => write!(f, "time below {}", level),
+      Self::Integral => write!(f, "integral"),
+    }
+  }
+}
+
+
+/// Statistics of the channels of one lap in a `SummaryTable`.
+#[derive(Debug, Clone, PartialEq, CopyGetters, Getters)]
+pub struct SummaryRow {
+  /// Index of the lap in its run.
+  #[getset(get_copy = "pub")]
+  lap:    usize,
+  /// For each channel of the table, the value of each statistic, `None` if
+  /// the lap lacks the channel or the channel has no samples in the lap.
+  #[getset(get = "pub")]
+  values: Vec<Vec<Option<f64>>>,
+}
+
+
+/// Statistics of channels for all laps of a `Run`, i.e. a value for each
+/// lap, channel and statistic.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct SummaryTable {
+  channels:   Vec<String>,
+  statistics: Vec<Statistic>,
+  rows:       Vec<SummaryRow>,
+}
+
+impl SummaryTable {
+  /// Computes `statistics` of all channels of `run`, including its math
+  /// channels, for each lap. Fails if the laps can't be read from the run or
+  /// a math channel can't be computed.
+  pub fn new(run: &Run, statistics: &[Statistic]) -> Result<Self> {
+    let names = run.all_channel_names()?;
+    let channels = names.iter().map(String::as_str).collect::<Vec<_>>();
+    Self::from_laps(&run.all_laps()?, &channels, statistics)
+  }
+
+  /// Computes `statistics` of the channels named in `channels` for each of
+  /// `laps`. Fails if one of them is a math channel which can't be computed,
+  /// see `Lap::math_channel`.
+  pub fn from_laps(laps: &[Lap],
+                   channels: &[&str],
+                   statistics: &[Statistic])
+                   -> Result<Self> {
+    let mut rows = Vec::with_capacity(laps.len());
+    for lap in laps {
+      let mut values = Vec::with_capacity(channels.len());
+      for name in channels {
+        let channel = lap.optional(name)?;
+        values.push(statistics.iter()
+                              .map(|statistic| {
+                                channel.and_then(|c| statistic.of(c))
+                              })
+                              .collect());
+      }
+      rows.push(SummaryRow { lap: lap.idx(),
+                             values });
+    }
+    Ok(Self { channels: channels.iter()
+                                .map(|name| name.to_string())
+                                .collect(),
+              statistics: statistics.to_vec(),
+              rows })
+  }
+
+  /// Value of `statistic` of the channel named `channel` in the lap with
+  /// index `lap`, `None` if any of them isn't in the table or the value
+  /// couldn't be computed.
+  pub fn value(&self,
+               lap: usize,
+               channel: &str,
+               statistic: Statistic)
+               -> Option<f64> {
+    let row = self.rows.iter().find(|row| row.lap == lap)?;
+    let channel = self.channels.iter().position(|name| name == channel)?;
+    let statistic = self.statistics.iter().position(|s| *s == statistic)?;
+    row.values[channel][statistic]
+  }
+
+  /// Writes the table as CSV to `writer`, with a line per lap and a column
+  /// per channel and statistic, e.g. `fEngRpm max`. Values which couldn't
+  /// be computed are left empty.
+  pub fn write_csv<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+    let mut header = vec!["lap".to_string()];
+    for channel in &self.channels {
+      for statistic in &self.statistics {
+        header.push(csv_field(&format!("{} {}", channel, statistic)));
+      }
+    }
+    writeln!(writer, "{}", header.join(","))?;
+
+    for row in &self.rows {
+      let mut line = vec![row.lap.to_string()];
+      for value in row.values.iter().flatten() {
+        line.push(value.map_or_else(String::new, |v| v.to_string()));
+      }
+      writeln!(writer, "{}", line.join(","))?;
+    }
+    Ok(())
+  }
+}
+
+
+/// `field` quoted if it contains a character with a meaning in CSV.
+fn csv_field(field: &str) -> String {
+  if field.contains(&[',', '"', '\n'][..]) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_string()
+  }
+}
+
+/// Integral of the samples over time, using the trapezoidal rule.
+fn integral(timestamps: &[f64], samples: &[f64]) -> f64 {
+  timestamps.windows(2)
+            .zip(samples.windows(2))
+            .map(|(t, v)| (t[1] - t[0]) * (v[0] + v[1]) / 2.0)
+            .sum()
+}
+
+/// Time the samples spend above `level`, interpolating linearly between them
+/// to find where they cross it.
+fn time_above(timestamps: &[f64], samples: &[f64], level: f64) -> f64 {
+  timestamps.windows(2)
+            .zip(samples.windows(2))
+            .map(|(t, v)| {
+              let dt = t[1] - t[0];
+              match (v[0] > level, v[1] > level) {
+                (true, true) => dt,
+                (false, false) => 0.0,
+                // the fraction of the interval until the level is crossed
+                (above, _) => {
+                  let crossing = (level - v[0]) / (v[1] - v[0]);
+                  if above {
+                    crossing * dt
+                  } else {
+                    (1.0 - crossing) * dt
+                  }
+                }
+              }
+            })
+            .sum()
+}
+
+/// Value the samples stay at or below for `percent` of the time. Each sample
+/// stands for half of the intervals to its neighbours, or for an equal share
+/// if all samples are at the same time.
+fn percentile(timestamps: &[f64], samples: &[f64], percent: f64) -> f64 {
+  let n = samples.len();
+  let same_time = timestamps[n - 1] <= timestamps[0];
+  let weight = |i: usize| {
+    if same_time {
+      return 1.0;
+    }
+    let before = if i > 0 { timestamps[i] - timestamps[i - 1] } else { 0.0 };
+    let after = if i + 1 < n {
+      timestamps[i + 1] - timestamps[i]
+    } else {
+      0.0
+    };
+    (before + after) / 2.0
+  };
+  let mut weighted = (0..n).map(|i| (samples[i], weight(i)))
+                           .collect::<Vec<_>>();
+  let total = weighted.iter().map(|(_, w)| w).sum::<f64>();
+  weighted.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+  let target = percent.clamp(0.0, 100.0) / 100.0 * total;
+  let mut cumulative = 0.0;
+  for &(value, weight) in &weighted {
+    cumulative += weight;
+    if cumulative >= target {
+      return value;
+    }
+  }
+  weighted[n - 1].0
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ChannelData, LapInfo};
+  use pretty_assertions::assert_eq;
+
+
+  fn channel(name: &str, timestamps: Vec<f64>, samples: Vec<f64>) -> Channel {
+    Channel::new(name.to_string(),
+                 "bar".to_string(),
+                 ChannelData::new(timestamps, samples))
+  }
+
+  #[test]
+  fn statistic_test() {
+    // irregularly sampled: 1 bar for 9 s, then 11 bar for 1 s at 10 Hz
+    let mut timestamps = vec![0.0, 9.0];
+    timestamps.extend((1..=10).map(|i| 9.0 + i as f64 / 10.0));
+    let mut samples = vec![1.0, 1.0];
+    samples.extend(vec![11.0; 10]);
+    let pressure = channel("pBrakeF", timestamps, samples);
+
+    let stat = |statistic: Statistic| {
+      (statistic.of(&pressure).unwrap() * 1e6).round() / 1e6
+    };
+    assert_eq!(1.0, stat(Statistic::Min));
+    assert_eq!(11.0, stat(Statistic::Max));
+    // the ramp from 9.0 to 9.1 s is half above 6 bar
+    assert_eq!(1.95, stat(Statistic::Mean));
+    assert_eq!(19.5, stat(Statistic::Integral));
+    assert_eq!(0.95, stat(Statistic::TimeAbove(6.0)));
+    assert_eq!(9.05, stat(Statistic::TimeBelow(6.0)));
+    assert_eq!(1.0, stat(Statistic::Percentile(50.0)));
+    assert_eq!(11.0, stat(Statistic::Percentile(95.0)));
+    assert_eq!(11.0, stat(Statistic::Percentile(200.0)));
+
+    let single = channel("pBrakeF", vec![1.0], vec![3.0]);
+    assert_eq!(Some(3.0), Statistic::Mean.of(&single));
+    assert_eq!(Some(3.0), Statistic::Percentile(10.0).of(&single));
+    assert_eq!(Some(0.0), Statistic::Integral.of(&single));
+    assert_eq!(None, Statistic::Max.of(&channel("pBrakeF", vec![], vec![])));
+
+    // NaN samples are left out, bridging the gap between their neighbours
+    let nan = f64::NAN;
+    let dropout = channel("pBrakeF",
+                          vec![0.0, 1.0, 2.0, 3.0, 4.0],
+                          vec![1.0, nan, nan, 3.0, 3.0]);
+    for (expected, statistic) in [(1.0, Statistic::Min),
+                                  (3.0, Statistic::Max),
+                                  (2.25, Statistic::Mean),
+                                  (9.0, Statistic::Integral),
+                                  (3.0, Statistic::Percentile(50.0)),
+                                  (3.25, Statistic::TimeAbove(1.5))].iter()
+    {
+      assert_eq!(Some(*expected), statistic.of(&dropout));
+    }
+    let nans = channel("pBrakeF", vec![0.0, 1.0], vec![nan, nan]);
+    assert_eq!(None, Statistic::Percentile(50.0).of(&nans));
+  }
+
+  #[test]
+  fn summary_table_test() {
+    let laps =
+      vec![Lap::new(LapInfo::new(0, 0.0, 2.0),
+                    vec![channel("pBrakeF", vec![0.0, 1.0], vec![0.0, 2.0]),
+                         channel("a,b", vec![0.0, 1.0], vec![1.0, 1.0])]),
+           Lap::new(LapInfo::new(1, 2.0, 2.0),
+                    vec![channel("pBrakeF", vec![2.0, 3.0], vec![4.0, 4.0])])];
+    let table = SummaryTable::from_laps(&laps,
+                                        &["pBrakeF", "a,b"],
+                                        &[Statistic::Max, Statistic::Mean])
+                             .unwrap();
+
+    assert_eq!(2, table.rows().len());
+    assert_eq!(Some(1.0), table.value(0, "pBrakeF", Statistic::Mean));
+    assert_eq!(Some(4.0), table.value(1, "pBrakeF", Statistic::Max));
+    assert_eq!(None, table.value(1, "a,b", Statistic::Max));
+    assert_eq!(None, table.value(0, "pBrakeF", Statistic::Min));
+    assert_eq!(None, table.value(2, "pBrakeF", Statistic::Max));
+
+    let mut csv = Vec::new();
+    table.write_csv(&mut csv).unwrap();
+    assert_eq!("lap,pBrakeF max,pBrakeF mean,\"a,b max\",\"a,b mean\"\n\
+                0,2,1,1,1\n\
+                1,4,4,,\n",
+               String::from_utf8(csv).unwrap());
+  }
+}
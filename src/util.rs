@@ -4,7 +4,7 @@
 //   Florian Eich <florian@bmc-labs.com>
 //   Jonas Reitemeyer <alumni@bmc-labs.com>
 
-use eyre::{ensure, eyre, Result};
+use crate::{Error, Result};
 use std::{ffi::{CStr, CString},
           os::raw::c_char,
           path::Path};
@@ -12,31 +12,39 @@ use std::{ffi::{CStr, CString},
 
 /// Converts a `*const c_char`, i.e. a raw C string (`const char *` in C), to a
 /// Rust `std::ffi::CString`, which is owned. This guarantees lifetime safety.
-pub fn strptr_to_cstring(strptr: *const c_char) -> Result<CString> {
-  ensure!(!strptr.is_null(), "error: fetched null pointer");
-  Ok(unsafe { CStr::from_ptr(strptr) }.to_owned())
+/// Returns `None` for a null pointer, which the library returns on errors.
+pub fn strptr_to_cstring(strptr: *const c_char) -> Option<CString> {
+  if strptr.is_null() {
+    return None;
+  }
+  Some(unsafe { CStr::from_ptr(strptr) }.to_owned())
 }
 
 /// Convenience function to convert directly to Rust's `String` type from a
 /// `*const c_char`, i.e. a raw C string (`const char *` in C).
-pub fn strptr_to_string(strptr: *const c_char) -> Result<String> {
-  Ok(strptr_to_cstring(strptr)?.to_string_lossy().into_owned())
+pub fn strptr_to_string(strptr: *const c_char) -> Option<String> {
+  Some(strptr_to_cstring(strptr)?.to_string_lossy().into_owned())
 }
 
 /// Convenience function to convert directly from a Rust `&str` to a
 /// `std::ffi::CString`, i.e. a lifetime safe object capable of providing a raw
-/// C string (`*const c_char` in Rust, `const char *` in C).
-pub fn strref_to_cstring(strref: &str) -> Result<CString> {
-  Ok(CString::new(strref)?)
+/// C string (`*const c_char` in Rust, `const char *` in C). Returns `None` if
+/// `strref` contains a nul byte.
+pub fn strref_to_cstring(strref: &str) -> Option<CString> {
+  CString::new(strref).ok()
 }
 
 /// Converts a Rust `std::path::Path` to a `std::ffi::CString` object. This is
 /// helpful here since the original C library takes absolute paths (this
 /// function takes any path) as `*const c_char` (`const char *` in C).
 pub fn path_to_cstring(path: &Path) -> Result<CString> {
-  strref_to_cstring(path.canonicalize()?
-                        .to_str()
-                        .ok_or(eyre!("path '{}' invalid", path.display()))?)
+  let invalid = || Error::InvalidPath { path:   path.to_owned(),
+                                        reason: "path is not a valid C \
+                                                 string", };
+  let path_abs = path.canonicalize()
+                     .map_err(|source| Error::Io { path: path.to_owned(),
+                                                   source })?;
+  strref_to_cstring(path_abs.to_str().ok_or_else(invalid)?).ok_or_else(invalid)
 }
 
 